@@ -0,0 +1,394 @@
+// Native witness generation: computes an optimal alignment of two sequences under a ScoringScheme with
+// Gotoh's affine-gap dynamic programme (Needleman-Wunsch for global, Smith-Waterman for local
+// alignments), optionally restricted to a band around the diagonal for long sequences.
+
+use ark_crypto_primitives::sponge::Absorb;
+use ark_ff::PrimeField;
+
+use crate::alignment::{
+    AlignmentCircuit, MaxLengths, ScoringScheme, BASE_N, CIGAR_DELETION, CIGAR_INSERTION, CIGAR_MATCH,
+    CIGAR_MISMATCH, CIGAR_SOFT_CLIP,
+};
+
+const NEG_INF: i64 = i64::MIN / 4;
+
+/// Whether better alignments score higher, as with `ScoringScheme::bwa_mem`, or lower, as with
+/// `ScoringScheme::edit_distance`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Objective {
+    Maximize,
+    Minimize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AlignmentMode {
+    Global, // both sequences end to end
+    Local, // the best scoring pair of substrings, only meaningful when maximizing
+}
+
+/// An optimal alignment of `target[target_start..target_end]` against
+/// `reference[reference_start..reference_end]`. Global alignments cover both sequences.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Alignment {
+    pub reference_start: usize,
+    pub reference_end: usize,
+    pub target_start: usize,
+    pub target_end: usize,
+    pub cigar_string_bases: Vec<usize>,
+    pub score: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Aligner {
+    pub scoring_scheme: ScoringScheme,
+    pub objective: Objective,
+    pub mode: AlignmentMode,
+    pub band: Option<usize>, // cells with |reference index - target index| > band are never visited
+}
+
+// The three Gotoh states: the alignment so far ends in a match or mismatch, a deletion or an insertion.
+// Start marks where an alignment begins.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Start,
+    Diagonal,
+    Deletion,
+    Insertion,
+}
+
+#[derive(Clone, Copy)]
+struct Cell {
+    scores: [i64; 3], // indexed by Diagonal, Deletion, Insertion
+    from: [State; 3],
+}
+
+const EMPTY_CELL: Cell = Cell { scores: [NEG_INF; 3], from: [State::Start; 3] };
+
+fn state_index(state: State) -> usize {
+    match state {
+        State::Diagonal => 0,
+        State::Deletion => 1,
+        State::Insertion => 2,
+        State::Start => unreachable!("the start state has no score"),
+    }
+}
+
+/// The best of `candidates`, preferring earlier ones on ties.
+fn best(candidates: &[(i64, State)]) -> (i64, State) {
+    let mut result = (NEG_INF, State::Start);
+    for &(score, state) in candidates {
+        if score > result.0 {
+            result = (score, state);
+        }
+    }
+    result
+}
+
+impl Aligner {
+    /// Global, unbanded alignment.
+    pub fn new(scoring_scheme: ScoringScheme, objective: Objective) -> Self {
+        Self {
+            scoring_scheme,
+            objective,
+            mode: AlignmentMode::Global,
+            band: None,
+        }
+    }
+
+    pub fn local(mut self) -> Self {
+        assert_eq!(self.objective, Objective::Maximize, "local alignment needs higher scores to be better");
+        self.mode = AlignmentMode::Local;
+        self
+    }
+
+    pub fn banded(mut self, band: usize) -> Self {
+        self.band = Some(band);
+        self
+    }
+
+    // Scores are negated when minimizing so that the programme always maximizes.
+    fn sign(&self) -> i64 {
+        match self.objective {
+            Objective::Maximize => 1,
+            Objective::Minimize => -1,
+        }
+    }
+
+    fn diagonal(&self, reference_base: usize, target_base: usize) -> (i64, usize) {
+        if reference_base == target_base && reference_base != BASE_N {
+            (self.sign() * self.scoring_scheme.match_score, CIGAR_MATCH)
+        } else {
+            (self.sign() * self.scoring_scheme.substitution_score(reference_base, target_base), CIGAR_MISMATCH)
+        }
+    }
+
+    fn columns(&self, i: usize, target_length: usize) -> std::ops::Range<usize> {
+        match self.band {
+            Some(band) => i.saturating_sub(band)..(i + band).min(target_length) + 1,
+            None => 0..target_length + 1,
+        }
+    }
+
+    /// Computes an optimal alignment, or None if a global alignment does not fit in the band.
+    pub fn align(&self, reference: &[usize], target: &[usize]) -> Option<Alignment> {
+        let (n, m) = (reference.len(), target.len());
+        if self.mode == AlignmentMode::Global && self.band.is_some_and(|band| n.abs_diff(m) > band) {
+            return None;
+        }
+        let sign = self.sign();
+        let gap_open = sign * (self.scoring_scheme.gap_open + self.scoring_scheme.gap_extend);
+        let gap_extend = sign * self.scoring_scheme.gap_extend;
+
+        // Row i holds the cells of columns self.columns(i, m).
+        let mut rows: Vec<Vec<Cell>> = Vec::with_capacity(n + 1);
+        let cell = |rows: &Vec<Vec<Cell>>, i: usize, j: usize| -> Cell {
+            let columns = self.columns(i, m);
+            if columns.contains(&j) { rows[i][j - columns.start] } else { EMPTY_CELL }
+        };
+        let mut local_best = (0, 0, 0);
+        for i in 0..=n {
+            let mut row = Vec::new();
+            for j in self.columns(i, m) {
+                let mut current = EMPTY_CELL;
+                if i == 0 && j == 0 {
+                    if self.mode == AlignmentMode::Global {
+                        current.scores[0] = 0;
+                    }
+                    row.push(current);
+                    continue;
+                }
+                if i > 0 && j > 0 {
+                    let previous = cell(&rows, i - 1, j - 1);
+                    let mut candidates = vec![
+                        (previous.scores[0], State::Diagonal),
+                        (previous.scores[1], State::Deletion),
+                        (previous.scores[2], State::Insertion),
+                    ];
+                    if self.mode == AlignmentMode::Local {
+                        candidates.push((0, State::Start));
+                    }
+                    let (score, from) = best(&candidates);
+                    current.scores[0] = score + self.diagonal(reference[i - 1], target[j - 1]).0;
+                    current.from[0] = from;
+                }
+                if i > 0 {
+                    let previous = cell(&rows, i - 1, j);
+                    let (score, from) = best(&[
+                        (previous.scores[0] + gap_open, State::Diagonal),
+                        (previous.scores[1] + gap_extend, State::Deletion),
+                        (previous.scores[2] + gap_open, State::Insertion),
+                    ]);
+                    current.scores[1] = score;
+                    current.from[1] = from;
+                }
+                if j > 0 {
+                    let previous = row.last().copied().unwrap_or(EMPTY_CELL);
+                    let (score, from) = best(&[
+                        (previous.scores[0] + gap_open, State::Diagonal),
+                        (previous.scores[1] + gap_open, State::Deletion),
+                        (previous.scores[2] + gap_extend, State::Insertion),
+                    ]);
+                    current.scores[2] = score;
+                    current.from[2] = from;
+                }
+                for score in current.scores.iter_mut() {
+                    *score = (*score).max(NEG_INF);
+                }
+                if current.scores[0] > local_best.0 {
+                    local_best = (current.scores[0], i, j);
+                }
+                row.push(current);
+            }
+            rows.push(row);
+        }
+
+        // Trace back from the end of the alignment.
+        let (score, mut i, mut j, mut state) = match self.mode {
+            AlignmentMode::Global => {
+                let last = cell(&rows, n, m);
+                let (score, state) = best(&[
+                    (last.scores[0], State::Diagonal),
+                    (last.scores[1], State::Deletion),
+                    (last.scores[2], State::Insertion),
+                ]);
+                if n + m > 0 && score <= NEG_INF / 2 {
+                    return None;
+                }
+                (score, n, m, if n + m > 0 { state } else { State::Start })
+            }
+            AlignmentMode::Local => {
+                let (score, i, j) = local_best;
+                (score, i, j, if score > 0 { State::Diagonal } else { State::Start })
+            }
+        };
+        let (reference_end, target_end) = (i, j);
+        let mut cigar_string_bases = Vec::new();
+        while state != State::Start {
+            let from = cell(&rows, i, j).from[state_index(state)];
+            match state {
+                State::Diagonal => {
+                    cigar_string_bases.push(self.diagonal(reference[i - 1], target[j - 1]).1);
+                    i -= 1;
+                    j -= 1;
+                }
+                State::Deletion => {
+                    cigar_string_bases.push(CIGAR_DELETION);
+                    i -= 1;
+                }
+                State::Insertion => {
+                    cigar_string_bases.push(CIGAR_INSERTION);
+                    j -= 1;
+                }
+                State::Start => unreachable!(),
+            }
+            // A global alignment starts at the origin, where the diagonal state scores 0.
+            state = if i == 0 && j == 0 { State::Start } else { from };
+        }
+        cigar_string_bases.reverse();
+
+        Some(Alignment {
+            reference_start: i,
+            reference_end,
+            target_start: j,
+            target_end,
+            cigar_string_bases,
+            score: sign * score,
+        })
+    }
+
+    /// Aligns the sequences and builds the circuit proving that alignment, sized for `max_lengths`. The
+    /// circuit covers the aligned part of the reference and all of the target, the unaligned ends of
+    /// the target being soft clipped.
+    pub fn circuit<F: PrimeField + Absorb>(
+        &self,
+        reference: &[usize],
+        target: &[usize],
+        max_lengths: &MaxLengths,
+    ) -> Option<(Alignment, AlignmentCircuit<F>)> {
+        let alignment = self.align(reference, target)?;
+        let mut cigar_string_bases = vec![CIGAR_SOFT_CLIP; alignment.target_start];
+        cigar_string_bases.extend(&alignment.cigar_string_bases);
+        cigar_string_bases.resize(cigar_string_bases.len() + target.len() - alignment.target_end, CIGAR_SOFT_CLIP);
+        let circuit = AlignmentCircuit::padded(
+            reference[alignment.reference_start..alignment.reference_end].to_vec(),
+            target.to_vec(),
+            cigar_string_bases,
+            self.scoring_scheme,
+            alignment.score,
+            max_lengths,
+        );
+        Some((alignment, circuit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::{BASE_A, BASE_C, BASE_G, BASE_T};
+    use crate::sam::encode_sequence;
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+    use ark_std::rand::Rng;
+
+    // Best score over every CIGAR string that aligns the two sequences end to end.
+    fn exhaustive_best(scheme: &ScoringScheme, objective: Objective, reference: &[usize], target: &[usize]) -> i64 {
+        fn extend(scheme: &ScoringScheme, reference: &[usize], target: &[usize], cigar: &mut Vec<usize>, i: usize, j: usize, scores: &mut Vec<i64>) {
+            if i == reference.len() && j == target.len() {
+                scores.push(scheme.score(reference, target, cigar));
+                return;
+            }
+            if i < reference.len() && j < target.len() {
+                cigar.push(if reference[i] == target[j] && reference[i] != BASE_N { CIGAR_MATCH } else { CIGAR_MISMATCH });
+                extend(scheme, reference, target, cigar, i + 1, j + 1, scores);
+                cigar.pop();
+            }
+            if i < reference.len() {
+                cigar.push(CIGAR_DELETION);
+                extend(scheme, reference, target, cigar, i + 1, j, scores);
+                cigar.pop();
+            }
+            if j < target.len() {
+                cigar.push(CIGAR_INSERTION);
+                extend(scheme, reference, target, cigar, i, j + 1, scores);
+                cigar.pop();
+            }
+        }
+        let mut scores = Vec::new();
+        extend(scheme, reference, target, &mut Vec::new(), 0, 0, &mut scores);
+        match objective {
+            Objective::Maximize => *scores.iter().max().unwrap(),
+            Objective::Minimize => *scores.iter().min().unwrap(),
+        }
+    }
+
+    #[test]
+    fn global_alignment_is_optimal() {
+        let rng = &mut ark_std::test_rng();
+        let schemes = [
+            (ScoringScheme::bwa_mem(), Objective::Maximize),
+            (ScoringScheme::with_transition_transversion(2, -1, -3, -2, -1), Objective::Maximize),
+            (ScoringScheme::edit_distance(), Objective::Minimize),
+        ];
+        for _ in 0..20 {
+            let reference: Vec<usize> = (0..rng.gen_range(0..6)).map(|_| rng.gen_range(BASE_A..=BASE_N)).collect();
+            let target: Vec<usize> = (0..rng.gen_range(0..6)).map(|_| rng.gen_range(BASE_A..=BASE_N)).collect();
+            for (scheme, objective) in schemes {
+                let alignment = Aligner::new(scheme, objective).align(&reference, &target).unwrap();
+                assert_eq!(alignment.score, exhaustive_best(&scheme, objective, &reference, &target));
+                assert_eq!(alignment.score, scheme.score(&reference, &target, &alignment.cigar_string_bases));
+            }
+        }
+    }
+
+    #[test]
+    fn generated_witnesses_satisfy_the_circuit() {
+        let reference = encode_sequence(b"ACGTTGCANNACGTACGTTTGCAACG").unwrap();
+        let target = encode_sequence(b"ACGTGCAGNACGTAGTACGGTTGCA").unwrap();
+        let max_lengths = MaxLengths { reference: 84, target: 84, cigar: 84 };
+        for aligner in [
+            Aligner::new(ScoringScheme::bwa_mem(), Objective::Maximize),
+            Aligner::new(ScoringScheme::edit_distance(), Objective::Minimize).banded(4),
+            Aligner::new(ScoringScheme::bwa_mem(), Objective::Maximize).local(),
+        ] {
+            let (alignment, circuit) = aligner.circuit::<Fr>(&reference, &target, &max_lengths).unwrap();
+            let cs = ConstraintSystem::new_ref();
+            circuit.clone().generate_constraints(cs.clone()).unwrap();
+            assert!(cs.is_satisfied().unwrap());
+
+            // The circuit rejects any other score for the same alignment.
+            let mut wrong = circuit;
+            wrong.alignment_score = alignment.score + 1;
+            let cs = ConstraintSystem::new_ref();
+            wrong.generate_constraints(cs.clone()).unwrap();
+            assert!(!cs.is_satisfied().unwrap());
+        }
+    }
+
+    #[test]
+    fn banded_alignment_matches_unbanded_near_the_diagonal() {
+        let rng = &mut ark_std::test_rng();
+        let reference: Vec<usize> = (0..300).map(|_| rng.gen_range(BASE_A..=BASE_T)).collect();
+        let mut target = reference.clone();
+        target.remove(120);
+        target.insert(200, BASE_C);
+        target[50] = if target[50] == BASE_G { BASE_A } else { BASE_G };
+        let aligner = Aligner::new(ScoringScheme::bwa_mem(), Objective::Maximize);
+        let full = aligner.align(&reference, &target).unwrap();
+        assert_eq!(aligner.banded(8).align(&reference, &target).unwrap(), full);
+        assert_eq!(full.score, 298 - 4 - 2 * 7);
+
+        // A band of 0 leaves only gapless alignments, which need equal lengths.
+        assert!(aligner.banded(0).align(&reference, &target[1..]).is_none());
+        assert!(aligner.banded(0).align(&reference, &target).unwrap().score < full.score);
+    }
+
+    #[test]
+    fn local_alignment_finds_the_shared_substring() {
+        let reference = encode_sequence(b"TTTTTTTTACGTACGGTTTTTTTT").unwrap();
+        let target = encode_sequence(b"CCCACGTACGGCCC").unwrap();
+        let alignment = Aligner::new(ScoringScheme::bwa_mem(), Objective::Maximize).local().align(&reference, &target).unwrap();
+        assert_eq!((alignment.reference_start, alignment.reference_end), (8, 16));
+        assert_eq!((alignment.target_start, alignment.target_end), (3, 11));
+        assert_eq!(alignment.score, 8);
+    }
+}
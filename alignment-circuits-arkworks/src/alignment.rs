@@ -0,0 +1,972 @@
+// The alignment relation, adopted from the VeriTAS paper (thank you Trisha).
+
+use ark_crypto_primitives::sponge::constraints::CryptographicSpongeVar;
+use ark_crypto_primitives::sponge::Absorb;
+use ark_crypto_primitives::sponge::poseidon::constraints::PoseidonSpongeVar;
+use ark_ff::vec::Vec;
+use ark_ff::{BigInteger, PrimeField};
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+
+use crate::commitment::{commit_bases, commit_blocks, commit_blocks_var, unpack_committed_sequence};
+use crate::memory::{MemoryCell, MemoryReader, ReadOnlyMemoryVar};
+use crate::poseidon::poseidon_parameters;
+use crate::reference_tree::{enforce_window_contents, ReferenceOpening, ReferenceTree};
+
+// Bases and CIGAR operations are packed 3 bits each, which leaves room for N and for padding symbols.
+pub const BITS_PER_BASE: usize = 3;
+pub const BASES_PER_BLOCK: usize = 84;
+pub const BITS_PER_CIGAR_OP: usize = 3;
+pub const CIGAR_OPS_PER_BLOCK: usize = 84;
+
+// CIGAR operations. Padding fills the CIGAR up to the circuit's maximum length and must only trail. Soft
+// clips consume target bases without scoring them and may only appear at either end of the alignment.
+pub const CIGAR_MATCH: usize = 0;
+pub const CIGAR_INSERTION: usize = 1;
+pub const CIGAR_DELETION: usize = 2;
+pub const CIGAR_MISMATCH: usize = 3;
+pub const CIGAR_PADDING: usize = 4;
+pub const CIGAR_SOFT_CLIP: usize = 5;
+
+// Bases. N stands for every ambiguous IUPAC code and never counts as a match, not even against another N.
+pub const BASE_A: usize = 0;
+pub const BASE_C: usize = 1;
+pub const BASE_G: usize = 2;
+pub const BASE_T: usize = 3;
+pub const BASE_N: usize = 4;
+pub const NUM_BASES: usize = 5;
+// Fills a sequence up to the circuit's maximum length. Padding is never read by the CIGAR walk.
+pub const BASE_PADDING: usize = 5;
+
+// Phred base qualities, capped at MAX_QUALITY and packed 6 bits each. Slots past the read are 0.
+pub const BITS_PER_QUALITY: usize = 6;
+pub const QUALITIES_PER_BLOCK: usize = 42;
+pub const MAX_QUALITY: usize = (1 << BITS_PER_QUALITY) - 1;
+
+/// Scoring parameters for the circuit. The score of an alignment is the sum of `match_score` for
+/// every match, `substitution_matrix[reference base][target base]` for every mismatch and
+/// `gap_open + k * gap_extend` for every run of k insertions (or deletions), which is the affine-gap
+/// convention used by BWA and minimap2. A mismatch where either base is N scores `ambiguous_score`
+/// instead. The substitution matrix is a public input of the proof, the other parameters are fixed at
+/// setup time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScoringScheme {
+    pub match_score: i64,
+    pub substitution_matrix: [[i64; 4]; 4], // indexed by [reference base][target base], the diagonal is unused
+    pub ambiguous_score: i64,
+    pub gap_open: i64,
+    pub gap_extend: i64,
+}
+
+#[allow(dead_code)]
+impl ScoringScheme {
+    pub fn new(match_score: i64, substitution_matrix: [[i64; 4]; 4], ambiguous_score: i64, gap_open: i64, gap_extend: i64) -> Self {
+        Self {
+            match_score,
+            substitution_matrix,
+            ambiguous_score,
+            gap_open,
+            gap_extend,
+        }
+    }
+
+    /// Every substitution, including those against N, scores the same.
+    pub fn with_mismatch_score(match_score: i64, mismatch_score: i64, gap_open: i64, gap_extend: i64) -> Self {
+        Self::new(match_score, [[mismatch_score; 4]; 4], mismatch_score, gap_open, gap_extend)
+    }
+
+    /// Transitions (A<->G, C<->T) and transversions score differently. N scores as a transversion.
+    pub fn with_transition_transversion(match_score: i64, transition_score: i64, transversion_score: i64, gap_open: i64, gap_extend: i64) -> Self {
+        let mut substitution_matrix = [[transversion_score; 4]; 4];
+        for (a, b) in [(BASE_A, BASE_G), (BASE_C, BASE_T)] {
+            substitution_matrix[a][b] = transition_score;
+            substitution_matrix[b][a] = transition_score;
+        }
+        Self::new(match_score, substitution_matrix, transversion_score, gap_open, gap_extend)
+    }
+
+    /// Plain edit distance: 0 is perfect, +1 for each mismatch, insertion or deletion.
+    pub fn edit_distance() -> Self {
+        Self::with_mismatch_score(0, 1, 0, 1)
+    }
+
+    /// BWA-MEM defaults (-A1 -B4 -O6 -E1, N scores -1).
+    pub fn bwa_mem() -> Self {
+        Self::new(1, [[-4; 4]; 4], -1, -6, -1)
+    }
+
+    /// Score of aligning `target_base` against a different (or ambiguous) `reference_base`.
+    pub fn substitution_score(&self, reference_base: usize, target_base: usize) -> i64 {
+        if reference_base == BASE_N || target_base == BASE_N {
+            self.ambiguous_score
+        } else {
+            self.substitution_matrix[reference_base][target_base]
+        }
+    }
+
+    /// The substitution matrix in row-major order, as it appears in the public inputs.
+    pub fn substitution_matrix_felts<F: PrimeField>(&self) -> Vec<F> {
+        self.substitution_matrix.iter().flatten().map(|&score| i64_to_felt::<F>(score)).collect()
+    }
+
+    /// Scores an alignment natively, with the same semantics as the circuit.
+    pub fn score(&self, reference_sequence_bases: &[usize], target_sequence_bases: &[usize], cigar_string_bases: &[usize]) -> i64 {
+        self.score_with_qualities(reference_sequence_bases, target_sequence_bases, None, cigar_string_bases)
+    }
+
+    /// Scores an alignment with every mismatch and gap multiplied by the quality of the target base it
+    /// involves. Insertions and mismatches take the quality of their own base, a deletion that of the
+    /// last target base before it, or MAX_QUALITY if there is none. Matches are not weighted.
+    pub fn quality_weighted_score(&self, reference_sequence_bases: &[usize], target_sequence_bases: &[usize], target_qualities: &[usize], cigar_string_bases: &[usize]) -> i64 {
+        self.score_with_qualities(reference_sequence_bases, target_sequence_bases, Some(target_qualities), cigar_string_bases)
+    }
+
+    fn score_with_qualities(&self, reference_sequence_bases: &[usize], target_sequence_bases: &[usize], target_qualities: Option<&[usize]>, cigar_string_bases: &[usize]) -> i64 {
+        let mut score = 0;
+        let mut previous_op = None;
+        let mut target_index = 0usize;
+        let mut reference_index = 0usize;
+        let mut weight = if target_qualities.is_some() { MAX_QUALITY as i64 } else { 1 };
+        for &op in cigar_string_bases {
+            if let Some(qualities) = target_qualities
+                && matches!(op, CIGAR_MATCH | CIGAR_MISMATCH | CIGAR_INSERTION | CIGAR_SOFT_CLIP)
+            {
+                weight = qualities[target_index] as i64;
+            }
+            let penalty = match op {
+                CIGAR_MISMATCH => self.substitution_score(reference_sequence_bases[reference_index], target_sequence_bases[target_index]),
+                CIGAR_INSERTION | CIGAR_DELETION if previous_op == Some(op) => self.gap_extend,
+                CIGAR_INSERTION | CIGAR_DELETION => self.gap_open + self.gap_extend,
+                CIGAR_MATCH | CIGAR_PADDING | CIGAR_SOFT_CLIP => 0,
+                _ => panic!("bad CIGAR character provided"),
+            };
+            score += penalty * weight;
+            if op == CIGAR_MATCH {
+                score += self.match_score;
+            }
+            match op {
+                CIGAR_MATCH | CIGAR_MISMATCH => {target_index+=1; reference_index +=1},
+                CIGAR_INSERTION | CIGAR_SOFT_CLIP => {target_index+=1;},
+                CIGAR_DELETION => {reference_index +=1},
+                _ => {},
+            }
+            previous_op = Some(op);
+        }
+        score
+    }
+}
+
+/// Maximum lengths a circuit is set up for. Actual lengths are public inputs, so one proving key
+/// serves every alignment that fits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaxLengths {
+    pub reference: usize,
+    pub target: usize,
+    pub cigar: usize,
+}
+
+impl MaxLengths {
+    /// Rounds every length up to a whole number of blocks, since that is what the circuit allocates.
+    pub fn capacity(&self) -> Self {
+        Self {
+            reference: self.reference.div_ceil(BASES_PER_BLOCK) * BASES_PER_BLOCK,
+            target: self.target.div_ceil(BASES_PER_BLOCK) * BASES_PER_BLOCK,
+            cigar: self.cigar.div_ceil(CIGAR_OPS_PER_BLOCK) * CIGAR_OPS_PER_BLOCK,
+        }
+    }
+}
+
+/// Proves the score of an alignment against a reference committed as a whole, and optionally that the
+/// reference is a window under the root of a ReferenceTree, see `with_reference_tree`.
+pub struct AlignmentCircuit<F: PrimeField + Absorb> {
+    pub reference_sequence_felts: Vec<F>, // The reference for a certain gene, encoded as field elements, where 84 bases are packed per field element and BASE_PADDING fills the rest
+    pub reference_sequence_bases: Vec<usize>, // Reference sequence encoded as usize's between 0 and 4, see BASE_N, without padding
+    pub target_sequence_felts: Vec<F>, // The value being aligned against the target
+    pub target_sequence_bases: Vec<usize>,
+    pub cigar_string_felts: Vec<F>, // Not fully a CIGAR string yet, padded with CIGAR_PADDING
+    pub cigar_string_bases: Vec<usize>, // encoded as the CIGAR_* operations, without padding
+    pub scoring_scheme: ScoringScheme, // scoring parameters, see ScoringScheme for which are public inputs
+    pub alignment_score: i64, // claimed alignment score under scoring_scheme which is a public output.
+    pub reference_salt: F, // salts of the public sequence commitments, see commitment::commit_bases
+    pub target_salt: F,
+    pub target_qualities: Option<Vec<usize>>, // committed Phred qualities weighting the score, see with_qualities
+    pub quality_salt: F,
+    pub min_mean_quality: Option<usize>, // public lower bound on the mean of target_qualities
+    pub reference_opening: Option<ReferenceOpening<F>>, // window of a ReferenceTree holding the reference, see with_reference_tree
+}
+
+#[allow(dead_code)]
+impl<F: PrimeField + Absorb> AlignmentCircuit<F> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        reference_sequence_felts: Vec<F>,
+        reference_sequence_bases: Vec<usize>,
+        target_sequence_felts: Vec<F>,
+        target_sequence_bases: Vec<usize>,
+        cigar_string_felts: Vec<F>,
+        cigar_string_bases: Vec<usize>,
+        scoring_scheme: ScoringScheme,
+        alignment_score: i64,
+    ) -> Self {
+        Self {
+            reference_sequence_felts,
+            reference_sequence_bases,
+            target_sequence_felts,
+            target_sequence_bases,
+            cigar_string_felts,
+            cigar_string_bases,
+            scoring_scheme,
+            alignment_score,
+            reference_salt: F::zero(),
+            target_salt: F::zero(),
+            target_qualities: None,
+            quality_salt: F::zero(),
+            min_mean_quality: None,
+            reference_opening: None,
+        }
+    }
+
+    /// Salts the public sequence commitments. Without a random target salt a verifier can test
+    /// guesses of the read against its commitment.
+    pub fn with_salts(mut self, reference_salt: F, target_salt: F) -> Self {
+        self.reference_salt = reference_salt;
+        self.target_salt = target_salt;
+        self
+    }
+
+    /// Weights every mismatch and gap by the quality of the target base it involves, see
+    /// `ScoringScheme::quality_weighted_score`, and claims the weighted score. The qualities become a
+    /// salted public commitment.
+    pub fn with_qualities(mut self, target_qualities: Vec<usize>, quality_salt: F) -> Self {
+        assert_eq!(target_qualities.len(), self.target_sequence_bases.len(), "need one quality per target base");
+        assert!(target_qualities.iter().all(|&quality| quality <= MAX_QUALITY), "qualities must be capped at MAX_QUALITY");
+        self.alignment_score = self.scoring_scheme.quality_weighted_score(
+            &self.reference_sequence_bases,
+            &self.target_sequence_bases,
+            &target_qualities,
+            &self.cigar_string_bases,
+        );
+        self.target_qualities = Some(target_qualities);
+        self.quality_salt = quality_salt;
+        self
+    }
+
+    /// Also proves that the mean target quality is at least `min_mean_quality`, which is public.
+    pub fn with_min_mean_quality(mut self, min_mean_quality: usize) -> Self {
+        assert!(self.target_qualities.is_some(), "a quality bound needs qualities");
+        self.min_mean_quality = Some(min_mean_quality);
+        self
+    }
+
+    /// Also proves that the reference is the window of `tree` starting at `window_start`, opening the
+    /// blocks it covers against the root, which is public. The position stays private. The number of
+    /// opened blocks follows the maximum reference length, so call this after `pad_to`.
+    pub fn with_reference_tree(mut self, tree: &ReferenceTree<F>, window_start: usize) -> Self {
+        let len = self.reference_sequence_bases.len();
+        let window = tree.open_window(window_start, len, self.max_lengths().reference);
+        assert_eq!(
+            &tree.bases()[window_start..window_start + len],
+            self.reference_sequence_bases.as_slice(),
+            "reference is not this window of the tree"
+        );
+        self.reference_opening = Some(ReferenceOpening { root: tree.root(), tree_height: tree.height(), window });
+        self
+    }
+}
+
+impl<F: PrimeField + Absorb> Clone for AlignmentCircuit<F> {
+    fn clone(&self) -> Self {
+        AlignmentCircuit {
+            reference_sequence_felts: self.reference_sequence_felts.clone(),
+            reference_sequence_bases: self.reference_sequence_bases.clone(),
+            target_sequence_felts: self.target_sequence_felts.clone(),
+            target_sequence_bases: self.target_sequence_bases.clone(),
+            cigar_string_felts: self.cigar_string_felts.clone(),
+            cigar_string_bases: self.cigar_string_bases.clone(),
+            scoring_scheme: self.scoring_scheme,
+            alignment_score: self.alignment_score,
+            reference_salt: self.reference_salt,
+            target_salt: self.target_salt,
+            target_qualities: self.target_qualities.clone(),
+            quality_salt: self.quality_salt,
+            min_mean_quality: self.min_mean_quality,
+            reference_opening: self.reference_opening.clone(),
+        }
+    }
+}
+
+impl<F: PrimeField + Absorb> AlignmentCircuit<F> {
+    /// Packs an alignment into a circuit sized for `max_lengths`.
+    pub fn padded(
+        reference_sequence_bases: Vec<usize>,
+        target_sequence_bases: Vec<usize>,
+        cigar_string_bases: Vec<usize>,
+        scoring_scheme: ScoringScheme,
+        alignment_score: i64,
+        max_lengths: &MaxLengths,
+    ) -> Self {
+        assert!(reference_sequence_bases.len() <= max_lengths.reference, "reference is longer than the circuit allows");
+        assert!(target_sequence_bases.len() <= max_lengths.target, "target is longer than the circuit allows");
+        assert!(cigar_string_bases.len() <= max_lengths.cigar, "CIGAR is longer than the circuit allows");
+        Self::new(
+            pack_bases_to(&reference_sequence_bases, max_lengths.reference),
+            reference_sequence_bases,
+            pack_bases_to(&target_sequence_bases, max_lengths.target),
+            target_sequence_bases,
+            pack_cigar_to(&cigar_string_bases, max_lengths.cigar),
+            cigar_string_bases,
+            scoring_scheme,
+            alignment_score,
+        )
+    }
+
+    /// Repacks the circuit for larger maximum lengths.
+    pub fn pad_to(self, max_lengths: &MaxLengths) -> Self {
+        let (reference_salt, target_salt) = (self.reference_salt, self.target_salt);
+        let mut padded = Self::padded(
+            self.reference_sequence_bases,
+            self.target_sequence_bases,
+            self.cigar_string_bases,
+            self.scoring_scheme,
+            self.alignment_score,
+            max_lengths,
+        )
+        .with_salts(reference_salt, target_salt);
+        padded.target_qualities = self.target_qualities;
+        padded.quality_salt = self.quality_salt;
+        padded.min_mean_quality = self.min_mean_quality;
+        padded.reference_opening = self.reference_opening;
+        padded
+    }
+
+    /// The maximum lengths this circuit was packed for, which fix its shape.
+    pub fn max_lengths(&self) -> MaxLengths {
+        MaxLengths {
+            reference: self.reference_sequence_felts.len() * BASES_PER_BLOCK,
+            target: self.target_sequence_felts.len() * BASES_PER_BLOCK,
+            cigar: self.cigar_string_felts.len() * CIGAR_OPS_PER_BLOCK,
+        }
+    }
+}
+
+impl<F: PrimeField + Absorb> AlignmentCircuit<F> {
+    /// Public inputs in the order the circuit allocates them.
+    pub fn public_inputs(&self) -> Vec<F> {
+        let mut inputs = self.scoring_scheme.substitution_matrix_felts::<F>();
+        inputs.push(commit_bases(&self.reference_sequence_bases, self.reference_salt));
+        inputs.push(commit_bases(&self.target_sequence_bases, self.target_salt));
+        inputs.push(F::from(self.reference_sequence_bases.len() as u64));
+        inputs.push(F::from(self.target_sequence_bases.len() as u64));
+        if let Some(opening) = &self.reference_opening {
+            inputs.push(opening.root);
+        }
+        inputs.push(i64_to_felt::<F>(self.alignment_score));
+        if let Some(target_qualities) = &self.target_qualities {
+            inputs.push(commit_blocks(&pack_qualities_to::<F>(target_qualities, 0), self.quality_salt));
+        }
+        if let Some(min_mean_quality) = self.min_mean_quality {
+            inputs.push(F::from(min_mean_quality as u64));
+        }
+        inputs
+    }
+}
+
+impl<F: PrimeField + Absorb> ConstraintSynthesizer<F> for AlignmentCircuit<F> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+        let mut reference_sequence_vars = Vec::new();
+        for elem in self.reference_sequence_felts.iter() {
+            reference_sequence_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+
+        let mut target_sequence_vars = Vec::new();
+        for elem in self.target_sequence_felts.iter() {
+            target_sequence_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+
+        let mut cigar_string_vars = Vec::new();
+        for elem in self.cigar_string_felts.iter() {
+            cigar_string_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+
+        let params = poseidon_parameters();
+
+        // Exactly the slots from each public length on are padding, so a walk that reads as many bases
+        // as the lengths reads every committed one.
+        let reference = unpack_committed_sequence(cs.clone(), &reference_sequence_vars, self.reference_sequence_bases.len(), self.reference_salt)?;
+        let target = unpack_committed_sequence(cs.clone(), &target_sequence_vars, self.target_sequence_bases.len(), self.target_salt)?;
+        let target_bases = &target.bases;
+
+        let mut cigar_chars = Vec::new();
+        for block in cigar_string_vars.iter() {
+            cigar_chars.extend(unpack_cigar(block)?);
+        }
+
+        let mut cigar_sponge = PoseidonSpongeVar::new(cs.clone(), &params);
+        cigar_sponge.absorb(&cigar_string_vars)?;
+        let cigar_hash = cigar_sponge.squeeze_field_elements(1)?;
+
+        let mut substitution_matrix_vars = Vec::new();
+        for elem in self.scoring_scheme.substitution_matrix_felts::<F>() {
+            substitution_matrix_vars.push(FpVar::new_input(cs.clone(), || Ok(elem))?);
+        }
+
+        let reference_commitment_var = FpVar::new_input(cs.clone(), || reference.commitment.value())?;
+        reference_commitment_var.enforce_equal(&reference.commitment)?;
+        let target_commitment_var = FpVar::new_input(cs.clone(), || target.commitment.value())?;
+        target_commitment_var.enforce_equal(&target.commitment)?;
+
+        let reference_length_var = FpVar::new_input(cs.clone(), || reference.length.value())?;
+        reference_length_var.enforce_equal(&reference.length)?;
+        let target_length_var = FpVar::new_input(cs.clone(), || target.length.value())?;
+        target_length_var.enforce_equal(&target.length)?;
+
+        let mut reference_memory = ReadOnlyMemoryVar::from_bases(cs.clone(), &reference.bases, 1);
+        let mut target_memory = ReadOnlyMemoryVar::from_bases(cs.clone(), target_bases, 1);
+        let walk = enforce_cigar_walk(
+            cs.clone(),
+            &cigar_chars,
+            &mut reference_memory,
+            &mut target_memory,
+            &self.scoring_scheme,
+            &substitution_matrix_vars,
+        )?;
+
+        // The commitments fix every block because the slots after the committed ones must be padding,
+        // and the CIGAR hash fixes every address read.
+        let mut transcript = vec![reference_commitment_var.clone(), target_commitment_var.clone(), cigar_hash[0].clone(), reference_length_var.clone(), target_length_var.clone()];
+        transcript.extend(substitution_matrix_vars.iter().cloned());
+        reference_memory.finalize(&transcript)?;
+        target_memory.finalize(&transcript)?;
+        // The walk reads distinct present slots, so reading as many as the lengths reads them all.
+        walk.target_length.enforce_equal(&target_length_var)?;
+        walk.reference_length.enforce_equal(&reference_length_var)?;
+
+        if let Some(opening) = &self.reference_opening {
+            let root_var = FpVar::new_input(cs.clone(), || Ok(opening.root))?;
+            enforce_window_contents(cs.clone(), opening, &root_var, &reference.bases, &[reference_commitment_var.clone(), reference_length_var.clone()])?;
+        }
+
+        let Some(target_qualities) = &self.target_qualities else {
+            let res_score = FpVar::<F>::new_input(cs.clone(), || Ok(i64_to_felt::<F>(self.alignment_score)))?;
+            res_score.enforce_equal(&walk.alignment_score).unwrap();
+            return Ok(());
+        };
+
+        // Qualities are packed for as many slots as the target, and a quality block is padding iff the
+        // target slot of its first quality is.
+        let mut quality_vars = Vec::new();
+        for elem in pack_qualities_to::<F>(target_qualities, target_bases.len()) {
+            quality_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+        let quality_salt_var = FpVar::new_witness(cs.clone(), || Ok(self.quality_salt))?;
+        let mut qualities = Vec::new();
+        for block in quality_vars.iter() {
+            qualities.extend(unpack_qualities(block)?);
+        }
+        let quality_block_is_padding: Vec<_> = target_bases.iter().step_by(QUALITIES_PER_BLOCK).map(|base| base.is_padding.clone()).collect();
+        let quality_commitment = commit_blocks_var(cs.clone(), &quality_vars, &quality_block_is_padding, &quality_salt_var)?;
+
+        // Every step reads the quality of the target base it reads, and a deletion keeps the weight of
+        // the step before it.
+        let present: Vec<_> = target_bases.iter().map(|base| FpVar::from(base.is_padding.not())).collect();
+        let cells = qualities
+            .iter()
+            .zip(present.iter())
+            .enumerate()
+            .map(|(i, (quality, present))| MemoryCell { address: FpVar::constant(F::from(i as u64)), value: quality.clone(), present: present.clone() })
+            .collect();
+        let mut quality_memory = ReadOnlyMemoryVar::new(cs.clone(), cells, BITS_PER_QUALITY, 1);
+        let mut score = walk.alignment_score.clone();
+        let mut weight = FpVar::constant(usize_to_felt::<F>(MAX_QUALITY));
+        for step in walk.steps.iter() {
+            let quality = quality_memory.read(&step.target_index, &step.consumes_target)?;
+            weight = step.consumes_target.select(&quality, &weight)?;
+            score += &step.penalty * &weight - &step.penalty;
+        }
+        quality_memory.finalize(&[quality_commitment.clone(), target_commitment_var, cigar_hash[0].clone(), target_length_var.clone()])?;
+
+        let res_score = FpVar::<F>::new_input(cs.clone(), || Ok(i64_to_felt::<F>(self.alignment_score)))?;
+        res_score.enforce_equal(&score)?;
+        let quality_commitment_var = FpVar::new_input(cs.clone(), || quality_commitment.value())?;
+        quality_commitment_var.enforce_equal(&quality_commitment)?;
+
+        if let Some(min_mean_quality) = self.min_mean_quality {
+            // sum of qualities - min_mean_quality * length must fit in as many bits as the largest sum.
+            let min_mean_quality_var = FpVar::new_input(cs.clone(), || Ok(usize_to_felt::<F>(min_mean_quality)))?;
+            let mut quality_sum = FpVar::zero();
+            for (quality, present) in qualities.iter().zip(present.iter()) {
+                quality_sum += quality * present;
+            }
+            let slack = quality_sum - min_mean_quality_var * &target_length_var;
+            let native_slack = target_qualities.iter().sum::<usize>() as i64 - (min_mean_quality * target_qualities.len()) as i64;
+            let num_bits = (u64::BITS - ((MAX_QUALITY * target_bases.len()) as u64).leading_zeros()) as usize;
+            let mut slack_bits = Vec::new();
+            for j in 0..num_bits {
+                slack_bits.push(Boolean::new_witness(cs.clone(), || Ok(native_slack >= 0 && (native_slack >> j) & 1 == 1))?);
+            }
+            Boolean::le_bits_to_fp_var(&slack_bits)?.enforce_equal(&slack)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Results of walking a CIGAR string in-circuit.
+pub struct CigarWalk<F: PrimeField> {
+    pub target_length: FpVar<F>, // number of target bases read
+    pub reference_length: FpVar<F>,
+    pub alignment_score: FpVar<F>,
+    pub steps: Vec<CigarStep<F>>, // one per operation, for circuits that need more than the totals
+}
+
+/// What a single CIGAR operation read during a walk.
+pub struct CigarStep<F: PrimeField> {
+    pub is_aligned: Boolean<F>, // match or mismatch, so that both bases are read
+    pub consumes_reference: Boolean<F>,
+    pub consumes_target: Boolean<F>,
+    pub reference_index: FpVar<F>, // position read if consumes_reference
+    pub target_index: FpVar<F>, // position read if consumes_target
+    pub reference_base: FpVar<F>,
+    pub target_base: FpVar<F>,
+    pub penalty: FpVar<F>, // the mismatch and gap part of the step's score, everything but match_score
+}
+
+/// The running state of a CIGAR walk, which a walk over the next segment of the CIGAR picks up.
+#[derive(Clone)]
+pub struct CigarWalkState<F: PrimeField> {
+    pub target_index: FpVar<F>,
+    pub reference_index: FpVar<F>,
+    pub alignment_score: FpVar<F>,
+    // The previous operation decides whether a gap is being opened or extended.
+    pub previous_is_insertion: Boolean<F>,
+    pub previous_is_deletion: Boolean<F>,
+    pub previous_is_padding: Boolean<F>,
+    // Whether an aligned operation has been seen, and whether a soft clip has followed one since.
+    pub aligned_so_far: Boolean<F>,
+    pub clipped_after_aligned: Boolean<F>,
+}
+
+impl<F: PrimeField> CigarWalkState<F> {
+    /// The state before the first operation, reading the reference from `reference_start`.
+    pub fn start(reference_start: &FpVar<F>) -> Self {
+        Self {
+            target_index: FpVar::zero(),
+            reference_index: reference_start.clone(),
+            alignment_score: FpVar::zero(),
+            previous_is_insertion: Boolean::FALSE,
+            previous_is_deletion: Boolean::FALSE,
+            previous_is_padding: Boolean::FALSE,
+            aligned_so_far: Boolean::FALSE,
+            clipped_after_aligned: Boolean::FALSE,
+        }
+    }
+}
+
+/// Walks `cigar_chars`, reading a target and/or reference base for every operation at running indices
+/// starting from 0, checking each operation against the bases it reads and accumulating the score.
+/// Padding operations do nothing and may only be followed by more padding. Soft clips read a target
+/// base without scoring it, and once one follows an aligned operation no aligned operation may follow.
+/// The reads are only checked once the memories are finalized.
+pub fn enforce_cigar_walk<F: PrimeField>(
+    cs: ConstraintSystemRef<F>,
+    cigar_chars: &[FpVar<F>],
+    reference: &mut ReadOnlyMemoryVar<F>,
+    target: &mut ReadOnlyMemoryVar<F>,
+    scoring_scheme: &ScoringScheme,
+    substitution_matrix_vars: &[FpVar<F>],
+) -> Result<CigarWalk<F>, SynthesisError> {
+    enforce_cigar_walk_from(cs, cigar_chars, &FpVar::zero(), reference, target, scoring_scheme, substitution_matrix_vars)
+}
+
+/// As `enforce_cigar_walk`, but reading the reference from `reference_start`. The reference length of
+/// the result counts from there.
+pub fn enforce_cigar_walk_from<F: PrimeField>(
+    cs: ConstraintSystemRef<F>,
+    cigar_chars: &[FpVar<F>],
+    reference_start: &FpVar<F>,
+    reference: &mut ReadOnlyMemoryVar<F>,
+    target: &mut ReadOnlyMemoryVar<F>,
+    scoring_scheme: &ScoringScheme,
+    substitution_matrix_vars: &[FpVar<F>],
+) -> Result<CigarWalk<F>, SynthesisError> {
+    let start = CigarWalkState::start(reference_start);
+    let (state, steps) = enforce_cigar_walk_segment(cs, cigar_chars, start, reference, target, scoring_scheme, substitution_matrix_vars)?;
+    Ok(CigarWalk {
+        target_length: state.target_index,
+        reference_length: state.reference_index - reference_start,
+        alignment_score: state.alignment_score,
+        steps,
+    })
+}
+
+/// Continues a walk from `state` over the next segment of the CIGAR, returning the state after it.
+/// The bases are read from any `MemoryReader`, which is responsible for checking the reads.
+pub fn enforce_cigar_walk_segment<F: PrimeField>(
+    cs: ConstraintSystemRef<F>,
+    cigar_chars: &[FpVar<F>],
+    state: CigarWalkState<F>,
+    reference: &mut impl MemoryReader<F>,
+    target: &mut impl MemoryReader<F>,
+    scoring_scheme: &ScoringScheme,
+    substitution_matrix_vars: &[FpVar<F>],
+) -> Result<(CigarWalkState<F>, Vec<CigarStep<F>>), SynthesisError> {
+    // Constants
+    let alignment_match = FpVar::new_constant(cs.clone(), usize_to_felt::<F>(CIGAR_MATCH)).unwrap();
+    let insertion = FpVar::new_constant(cs.clone(), usize_to_felt::<F>(CIGAR_INSERTION)).unwrap();
+    let deletion = FpVar::new_constant(cs.clone(), usize_to_felt::<F>(CIGAR_DELETION)).unwrap();
+    let mismatch = FpVar::new_constant(cs.clone(), usize_to_felt::<F>(CIGAR_MISMATCH)).unwrap();
+    let padding = FpVar::new_constant(cs.clone(), usize_to_felt::<F>(CIGAR_PADDING))?;
+    let soft_clip = FpVar::new_constant(cs.clone(), usize_to_felt::<F>(CIGAR_SOFT_CLIP))?;
+    let match_score = i64_to_felt::<F>(scoring_scheme.match_score);
+    let ambiguous_score = i64_to_felt::<F>(scoring_scheme.ambiguous_score);
+    let gap_open = i64_to_felt::<F>(scoring_scheme.gap_open);
+    let gap_extend = i64_to_felt::<F>(scoring_scheme.gap_extend);
+
+    let CigarWalkState {
+        target_index: mut target_index_var,
+        reference_index: mut reference_index_var,
+        mut alignment_score,
+        mut previous_is_insertion,
+        mut previous_is_deletion,
+        mut previous_is_padding,
+        mut aligned_so_far,
+        mut clipped_after_aligned,
+    } = state;
+    let mut steps = Vec::new();
+    for cigar_char in cigar_chars.iter() {
+        let is_match = cigar_char.is_eq(&alignment_match).unwrap();
+        let is_insertion = cigar_char.is_eq(&insertion).unwrap();
+        let is_deletion = cigar_char.is_eq(&deletion).unwrap();
+        let is_mismatch = cigar_char.is_eq(&mismatch).unwrap();
+        let is_padding = cigar_char.is_eq(&padding)?;
+        let is_soft_clip = cigar_char.is_eq(&soft_clip)?;
+        previous_is_padding.and(&is_padding.not())?.enforce_equal(&Boolean::FALSE)?;
+        let is_aligned = Boolean::kary_or(&[is_match.clone(), is_mismatch.clone(), is_insertion.clone(), is_deletion.clone()])?;
+        clipped_after_aligned.and(&is_aligned)?.enforce_equal(&Boolean::FALSE)?;
+        clipped_after_aligned = clipped_after_aligned.or(&is_soft_clip.and(&aligned_so_far)?)?;
+        aligned_so_far = aligned_so_far.or(&is_aligned)?;
+        let opens_insertion = is_insertion.and(&previous_is_insertion.not()).unwrap();
+        let opens_deletion = is_deletion.and(&previous_is_deletion.not()).unwrap();
+
+        let mut penalty = (FpVar::from(opens_insertion) + FpVar::from(opens_deletion)) * gap_open
+            + (FpVar::from(is_insertion.clone()) + FpVar::from(is_deletion.clone())) * gap_extend;
+
+        // Read values are looked up at the running indices. Operations that don't consume a sequence
+        // read an unconstrained dummy which the memory leaves out of its check.
+        let consumes_target = Boolean::kary_or(&[is_match.clone(), is_mismatch.clone(), is_insertion.clone(), is_soft_clip.clone()])?;
+        let consumes_reference = Boolean::kary_or(&[is_match.clone(), is_mismatch.clone(), is_deletion.clone()])?;
+        let target_sequence_read_val = target.read(&target_index_var, &consumes_target)?;
+        let reference_sequence_read_val = reference.read(&reference_index_var, &consumes_reference)?;
+
+        // Bases match when they are equal and not N. If is_match, bases_match must be true, and if
+        // is_mismatch it must be false.
+        let reference_basis = base_lagrange_basis(&reference_sequence_read_val)?;
+        let target_basis = base_lagrange_basis(&target_sequence_read_val)?;
+        let bases_equal = target_sequence_read_val.is_eq(&reference_sequence_read_val).unwrap();
+        let reference_is_n = reference_basis[BASE_N].is_one()?;
+        let bases_match = bases_equal.and(&reference_is_n.not())?;
+        is_match.not().or(&bases_match).unwrap().enforce_equal(&Boolean::<F>::TRUE).unwrap();
+        is_mismatch.not().or(&bases_match.not()).unwrap().enforce_equal(&Boolean::<F>::TRUE).unwrap();
+
+        // Look up the substitution score by interpolating it over the alphabet {A, C, G, T, N}^2. Rows
+        // and columns for N are the constant ambiguous_score.
+        let mut substitution_score = &reference_basis[BASE_N] * ambiguous_score;
+        for (a, reference_weight) in reference_basis.iter().take(4).enumerate() {
+            let mut row = &target_basis[BASE_N] * ambiguous_score;
+            for (b, target_weight) in target_basis.iter().take(4).enumerate() {
+                row += &substitution_matrix_vars[4 * a + b] * target_weight;
+            }
+            substitution_score += reference_weight * &row;
+        }
+        penalty += FpVar::from(is_mismatch.clone()) * &substitution_score;
+        alignment_score += FpVar::from(is_match.clone()) * match_score + &penalty;
+
+        steps.push(CigarStep {
+            is_aligned: is_match.or(&is_mismatch)?,
+            consumes_reference: consumes_reference.clone(),
+            consumes_target: consumes_target.clone(),
+            reference_index: reference_index_var.clone(),
+            target_index: target_index_var.clone(),
+            reference_base: reference_sequence_read_val.clone(),
+            target_base: target_sequence_read_val.clone(),
+            penalty,
+        });
+
+        target_index_var += FpVar::from(consumes_target);
+        reference_index_var += FpVar::from(consumes_reference);
+        previous_is_insertion = is_insertion;
+        previous_is_deletion = is_deletion;
+        previous_is_padding = is_padding;
+    }
+
+    let state = CigarWalkState {
+        target_index: target_index_var,
+        reference_index: reference_index_var,
+        alignment_score,
+        previous_is_insertion,
+        previous_is_deletion,
+        previous_is_padding,
+        aligned_so_far,
+        clipped_after_aligned,
+    };
+    Ok((state, steps))
+}
+
+/// Packs symbols of `bits_per_symbol` bits into field elements, `symbols_per_block` per element with the
+/// first symbol in the least significant bits. `padding_symbol` fills the blocks up to `capacity`
+/// symbols, rounded up to a whole block.
+pub fn pack_symbols<F: PrimeField>(symbols: &[usize], bits_per_symbol: usize, symbols_per_block: usize, padding_symbol: usize, capacity: usize) -> Vec<F> {
+    let mut symbols = symbols.to_vec();
+    let capacity = capacity.max(symbols.len()).div_ceil(symbols_per_block) * symbols_per_block;
+    symbols.resize(capacity, padding_symbol);
+    symbols
+        .chunks(symbols_per_block)
+        .map(|block| {
+            let bits: Vec<bool> = block.iter().flat_map(|&symbol| (0..bits_per_symbol).map(move |j| (symbol >> j) & 1 == 1)).collect();
+            F::from_bigint(F::BigInt::from_bits_le(&bits)).unwrap()
+        })
+        .collect()
+}
+
+/// Packs bases BASES_PER_BLOCK per field element, padding the last block.
+pub fn pack_bases<F: PrimeField>(bases: &[usize]) -> Vec<F> {
+    pack_bases_to(bases, 0)
+}
+
+/// Packs bases into enough blocks for `max_length` bases.
+pub fn pack_bases_to<F: PrimeField>(bases: &[usize], max_length: usize) -> Vec<F> {
+    pack_symbols(bases, BITS_PER_BASE, BASES_PER_BLOCK, BASE_PADDING, max_length)
+}
+
+/// Packs CIGAR operations CIGAR_OPS_PER_BLOCK per field element, padding the last block.
+pub fn pack_cigar<F: PrimeField>(cigar_string_bases: &[usize]) -> Vec<F> {
+    pack_cigar_to(cigar_string_bases, 0)
+}
+
+/// Packs CIGAR operations into enough blocks for `max_length` operations.
+pub fn pack_cigar_to<F: PrimeField>(cigar_string_bases: &[usize], max_length: usize) -> Vec<F> {
+    pack_symbols(cigar_string_bases, BITS_PER_CIGAR_OP, CIGAR_OPS_PER_BLOCK, CIGAR_PADDING, max_length)
+}
+
+/// Packs qualities QUALITIES_PER_BLOCK per field element into enough blocks for `max_length` qualities.
+pub fn pack_qualities_to<F: PrimeField>(qualities: &[usize], max_length: usize) -> Vec<F> {
+    pack_symbols(qualities, BITS_PER_QUALITY, QUALITIES_PER_BLOCK, 0, max_length)
+}
+
+/// Splits a packed block into the bits of its symbols. The bits are witnessed directly and recombined
+/// linearly into the block, which pins them down as long as the symbols fit below the modulus, instead
+/// of decomposing the whole field element with `to_bits_le`.
+pub fn unpack_block<F: PrimeField>(block: &FpVar<F>, bits_per_symbol: usize, symbols_per_block: usize) -> Result<Vec<Vec<Boolean<F>>>, SynthesisError> {
+    let used_bits = bits_per_symbol * symbols_per_block;
+    assert!(used_bits < F::MODULUS_BIT_SIZE as usize, "packed symbols do not fit below the modulus");
+    let native_bits = block.value().map(|value| value.into_bigint().to_bits_le());
+    let block_bits = if block.is_constant() {
+        native_bits?[..used_bits].iter().map(|&bit| Boolean::constant(bit)).collect()
+    } else {
+        let mut block_bits = Vec::with_capacity(used_bits);
+        for j in 0..used_bits {
+            block_bits.push(Boolean::new_witness(block.cs(), || native_bits.as_ref().map(|bits| bits[j]).map_err(|err| *err))?);
+        }
+        Boolean::le_bits_to_fp_var(&block_bits)?.enforce_equal(block)?;
+        block_bits
+    };
+    Ok(block_bits.chunks_exact(bits_per_symbol).map(|chunk| chunk.to_vec()).collect())
+}
+
+/// A base slot of a packed sequence.
+pub struct UnpackedBase<F: PrimeField> {
+    pub value: FpVar<F>,
+    pub is_padding: Boolean<F>,
+}
+
+/// Unpacks a block of bases, enforcing that every slot is at most BASE_PADDING.
+pub fn unpack_bases<F: PrimeField>(block: &FpVar<F>) -> Result<Vec<UnpackedBase<F>>, SynthesisError> {
+    let mut bases = Vec::new();
+    for base_bits in unpack_block(block, BITS_PER_BASE, BASES_PER_BLOCK)? {
+        // 5 = 0b101 is the largest symbol, so the top bit excludes the middle one, and singles out
+        // padding together with the lowest one.
+        FpVar::from(base_bits[2].clone()).mul_equals(&FpVar::from(base_bits[1].clone()), &FpVar::zero())?;
+        bases.push(UnpackedBase {
+            value: bits_to_symbol(&base_bits),
+            is_padding: base_bits[2].and(&base_bits[0])?,
+        });
+    }
+    Ok(bases)
+}
+
+/// Unpacks a block of CIGAR operations, enforcing that every operation is at most CIGAR_SOFT_CLIP.
+pub fn unpack_cigar<F: PrimeField>(block: &FpVar<F>) -> Result<Vec<FpVar<F>>, SynthesisError> {
+    let mut ops = Vec::new();
+    for op_bits in unpack_block(block, BITS_PER_CIGAR_OP, CIGAR_OPS_PER_BLOCK)? {
+        // 5 = 0b101 is the largest operation, so the top bit excludes the middle one.
+        FpVar::from(op_bits[2].clone()).mul_equals(&FpVar::from(op_bits[1].clone()), &FpVar::zero())?;
+        ops.push(bits_to_symbol(&op_bits));
+    }
+    Ok(ops)
+}
+
+/// Unpacks a block of qualities. Every 6-bit value is a quality, so there is nothing to range check.
+pub fn unpack_qualities<F: PrimeField>(block: &FpVar<F>) -> Result<Vec<FpVar<F>>, SynthesisError> {
+    Ok(unpack_block(block, BITS_PER_QUALITY, QUALITIES_PER_BLOCK)?.iter().map(|bits| bits_to_symbol(bits)).collect())
+}
+
+/// Recombines little-endian bits into a symbol, for free.
+pub fn bits_to_symbol<F: PrimeField>(bits: &[Boolean<F>]) -> FpVar<F> {
+    bits.iter().rev().fold(FpVar::zero(), |acc, bit| acc.double().unwrap() + FpVar::from(bit.clone()))
+}
+
+pub fn usize_to_felt<F: PrimeField>(base: usize) -> F {
+    F::from(base as u64)
+}
+
+/// Returns the Lagrange basis polynomials over the base alphabet {0, ..., NUM_BASES - 1} evaluated at
+/// `base`, i.e. the one-hot encoding of `base` when it is a valid base. Costs 3 constraints.
+pub fn base_lagrange_basis<F: PrimeField>(base: &FpVar<F>) -> Result<Vec<FpVar<F>>, SynthesisError> {
+    let mut powers = vec![FpVar::one(), base.clone()];
+    for _ in 2..NUM_BASES {
+        let next = powers.last().unwrap() * base;
+        powers.push(next);
+    }
+    let nodes: Vec<F> = (0..NUM_BASES).map(usize_to_felt::<F>).collect();
+    let mut basis = Vec::new();
+    for (a, node) in nodes.iter().enumerate() {
+        // Expand prod_{j != a} (x - j) / (a - j) into monomial coefficients.
+        let mut coefficients = vec![F::one()];
+        let mut denominator = F::one();
+        for other in nodes.iter().enumerate().filter(|(j, _)| *j != a).map(|(_, other)| other) {
+            let mut next = vec![F::zero(); coefficients.len() + 1];
+            for (d, c) in coefficients.iter().enumerate() {
+                next[d + 1] += c;
+                next[d] -= *c * other;
+            }
+            coefficients = next;
+            denominator *= *node - other;
+        }
+        let denominator_inverse = denominator.inverse().unwrap();
+        let mut value = FpVar::zero();
+        for (power, c) in powers.iter().zip(coefficients) {
+            value += power * (c * denominator_inverse);
+        }
+        basis.push(value);
+    }
+    Ok(basis)
+}
+
+pub fn i64_to_felt<F: PrimeField>(score: i64) -> F {
+    let magnitude = F::from(score.unsigned_abs());
+    if score < 0 { -magnitude } else { magnitude }
+}
+
+/// Reads back a score written by `i64_to_felt`, or None if `value` is not within 2^63 of zero.
+pub fn felt_to_i64<F: PrimeField>(value: F) -> Option<i64> {
+    let small = |value: F| {
+        let bigint = value.into_bigint();
+        (bigint.num_bits() < 64).then(|| bigint.as_ref()[0] as i64)
+    };
+    small(value).or_else(|| small(-value).map(|magnitude| -magnitude))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::rand::Rng;
+
+    #[test]
+    fn unpacks_blocks_with_linear_recombination() {
+        let bases: Vec<usize> = (0..100).map(|i| i % NUM_BASES).collect();
+        let blocks = pack_bases::<Fr>(&bases);
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        let block_var = FpVar::new_witness(cs.clone(), || Ok(blocks[1])).unwrap();
+        let unpacked = unpack_bases(&block_var).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        // Three bits, the range check and the padding flag per base, and one recombination per block.
+        assert_eq!(cs.num_constraints(), 5 * BASES_PER_BLOCK + 1);
+        for (i, base) in unpacked.iter().enumerate() {
+            let expected = bases.get(BASES_PER_BLOCK + i).copied().unwrap_or(BASE_PADDING);
+            assert_eq!(base.value.value().unwrap(), Fr::from(expected as u64));
+            assert_eq!(base.is_padding.value().unwrap(), expected == BASE_PADDING);
+        }
+
+        let constant = unpack_bases(&FpVar::constant(blocks[0])).unwrap();
+        assert_eq!(constant[7].value.value().unwrap(), Fr::from(bases[7] as u64));
+
+        // A symbol above BASE_PADDING is rejected.
+        let out_of_range = pack_symbols::<Fr>(&[BASE_A, 6], BITS_PER_BASE, BASES_PER_BLOCK, BASE_PADDING, 0);
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        let block_var = FpVar::new_witness(cs.clone(), || Ok(out_of_range[0])).unwrap();
+        unpack_bases(&block_var).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn scores_round_trip_through_felts() {
+        for score in [0, 1, -1, 42, -1000, i64::MAX, i64::MIN + 1] {
+            assert_eq!(felt_to_i64(i64_to_felt::<Fr>(score)), Some(score));
+        }
+        assert_eq!(felt_to_i64(Fr::from(1u128 << 64)), None);
+    }
+
+    #[test]
+    fn committed_sequences_must_end_at_the_claimed_lengths() {
+        let sequence: Vec<usize> = (0..100).map(|i| i % 4).collect();
+        let cigar = vec![CIGAR_MATCH; 50];
+        let scheme = ScoringScheme::bwa_mem();
+        let score = scheme.score(&sequence[..50], &sequence[..50], &cigar);
+        let max_lengths = MaxLengths { reference: 200, target: 200, cigar: 200 };
+        let circuit = AlignmentCircuit::<Fr>::padded(sequence[..50].to_vec(), sequence[..50].to_vec(), cigar, scheme, score, &max_lengths);
+
+        let cs = ConstraintSystem::new_ref();
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // Committing to all 100 bases while claiming and aligning only the first 50 leaves cells unread.
+        let mut longer_target = circuit.clone();
+        longer_target.target_sequence_felts = pack_bases_to(&sequence, max_lengths.target);
+        let cs = ConstraintSystem::new_ref();
+        longer_target.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+
+        let mut longer_reference = circuit;
+        longer_reference.reference_sequence_felts = pack_bases_to(&sequence, max_lengths.reference);
+        let cs = ConstraintSystem::new_ref();
+        longer_reference.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn reference_opens_against_the_tree_root() {
+        let rng = &mut ark_std::test_rng();
+        let chromosome: Vec<usize> = (0..500).map(|_| rng.gen_range(BASE_A..=BASE_T)).collect();
+        let tree = ReferenceTree::<Fr>::new(chromosome.clone(), 200);
+        let bases = chromosome[130..230].to_vec();
+        let cigar = vec![CIGAR_MATCH; bases.len()];
+        let scheme = ScoringScheme::bwa_mem();
+        let score = scheme.score(&bases, &bases, &cigar);
+        let max_lengths = MaxLengths { reference: 200, target: 200, cigar: 200 };
+        let circuit = AlignmentCircuit::<Fr>::padded(bases.clone(), bases, cigar, scheme, score, &max_lengths).with_reference_tree(&tree, 130);
+        let public_inputs = circuit.public_inputs();
+        assert_eq!(public_inputs[20], tree.root());
+
+        let cs = ConstraintSystem::new_ref();
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.borrow().unwrap().instance_assignment[1..], public_inputs[..]);
+
+        // Another root fails, as does claiming the window one base later.
+        let mut wrong_root = circuit.clone();
+        wrong_root.reference_opening.as_mut().unwrap().root = Fr::from(7u64);
+        let cs = ConstraintSystem::new_ref();
+        wrong_root.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+
+        let mut shifted = circuit;
+        shifted.reference_opening.as_mut().unwrap().window.start += 1;
+        let cs = ConstraintSystem::new_ref();
+        shifted.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+}
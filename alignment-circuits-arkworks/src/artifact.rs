@@ -0,0 +1,299 @@
+// Files for Groth16 keys and proofs. Every file starts with a versioned header recording the shape of
+// the circuit it belongs to, followed by the compressed ark-serialize encoding of its contents, so that
+// keys for a differently shaped circuit are rejected before they are used.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+
+use crate::alignment::{MaxLengths, ScoringScheme, BASES_PER_BLOCK, CIGAR_OPS_PER_BLOCK};
+
+const MAGIC: &[u8; 4] = b"ALNC";
+pub const ARTIFACT_VERSION: u32 = 7;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArtifactKind {
+    ProvingKey = 0,
+    VerifyingKey = 1,
+    Proof = 2,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ArtifactError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    WrongKind { expected: ArtifactKind, found: u8 },
+    HeaderMismatch { expected: Box<CircuitHeader>, found: Box<CircuitHeader> },
+    Serialization(String),
+    Io(String),
+}
+
+impl fmt::Display for ArtifactError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArtifactError::BadMagic => write!(f, "not an alignment circuit key or proof file"),
+            ArtifactError::UnsupportedVersion(version) => {
+                write!(f, "file format version {} is not supported, expected {}", version, ARTIFACT_VERSION)
+            }
+            ArtifactError::WrongKind { expected, found } => write!(f, "expected a {:?} file, found kind {}", expected, found),
+            ArtifactError::HeaderMismatch { expected, found } => {
+                write!(f, "file is for circuit {:?} but circuit {:?} was expected", found, expected)
+            }
+            ArtifactError::Serialization(reason) => write!(f, "could not deserialize file contents: {}", reason),
+            ArtifactError::Io(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ArtifactError {}
+
+impl From<std::io::Error> for ArtifactError {
+    fn from(err: std::io::Error) -> Self {
+        ArtifactError::Io(err.to_string())
+    }
+}
+
+impl From<ark_serialize::SerializationError> for ArtifactError {
+    fn from(err: ark_serialize::SerializationError) -> Self {
+        ArtifactError::Serialization(err.to_string())
+    }
+}
+
+/// The parameters that fix the shape of an AlignmentCircuit, and with it its keys. The substitution
+/// matrix is a public input and is recorded for reference only.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitHeader {
+    pub bases_per_block: usize,
+    pub cigar_ops_per_block: usize,
+    pub max_lengths: MaxLengths,
+    pub scoring_scheme: ScoringScheme,
+    pub quality_weighted: bool, // whether the score is weighted by committed base qualities
+    pub min_mean_quality: Option<usize>, // the public lower bound on the mean quality, if one is proven
+}
+
+impl CircuitHeader {
+    const NUM_FIELDS: usize = 27;
+
+    pub fn new(max_lengths: MaxLengths, scoring_scheme: ScoringScheme) -> Self {
+        Self {
+            bases_per_block: BASES_PER_BLOCK,
+            cigar_ops_per_block: CIGAR_OPS_PER_BLOCK,
+            max_lengths: max_lengths.capacity(),
+            scoring_scheme,
+            quality_weighted: false,
+            min_mean_quality: None,
+        }
+    }
+
+    /// For circuits built with `AlignmentCircuit::with_qualities`.
+    pub fn with_quality_weighting(mut self) -> Self {
+        self.quality_weighted = true;
+        self
+    }
+
+    /// For circuits built with `AlignmentCircuit::with_min_mean_quality`.
+    pub fn with_min_mean_quality(mut self, min_mean_quality: usize) -> Self {
+        assert!(self.quality_weighted, "a quality bound needs qualities");
+        self.min_mean_quality = Some(min_mean_quality);
+        self
+    }
+
+    /// Number of public inputs of the circuit: the substitution matrix, the reference and target
+    /// commitments, their lengths and the score, then the quality commitment and the quality bound if
+    /// the circuit has them.
+    pub fn num_public_inputs(&self) -> usize {
+        let quality_inputs = self.quality_weighted as usize + self.min_mean_quality.is_some() as usize;
+        self.scoring_scheme.substitution_matrix.iter().flatten().count() + 5 + quality_inputs
+    }
+
+    fn fields(&self) -> Vec<i64> {
+        let scheme = &self.scoring_scheme;
+        let mut fields = vec![
+            self.bases_per_block as i64,
+            self.cigar_ops_per_block as i64,
+            self.max_lengths.reference as i64,
+            self.max_lengths.target as i64,
+            self.max_lengths.cigar as i64,
+            scheme.match_score,
+        ];
+        fields.extend(scheme.substitution_matrix.iter().flatten());
+        fields.extend([scheme.ambiguous_score, scheme.gap_open, scheme.gap_extend]);
+        fields.push(self.quality_weighted as i64);
+        fields.push(self.min_mean_quality.map_or(-1, |quality| quality as i64));
+        fields
+    }
+
+    fn from_fields(fields: &[i64]) -> Self {
+        let mut substitution_matrix = [[0; 4]; 4];
+        for (i, row) in substitution_matrix.iter_mut().enumerate() {
+            row.copy_from_slice(&fields[6 + 4 * i..10 + 4 * i]);
+        }
+        Self {
+            bases_per_block: fields[0] as usize,
+            cigar_ops_per_block: fields[1] as usize,
+            max_lengths: MaxLengths {
+                reference: fields[2] as usize,
+                target: fields[3] as usize,
+                cigar: fields[4] as usize,
+            },
+            scoring_scheme: ScoringScheme::new(fields[5], substitution_matrix, fields[22], fields[23], fields[24]),
+            quality_weighted: fields[25] != 0,
+            min_mean_quality: (fields[26] >= 0).then_some(fields[26] as usize),
+        }
+    }
+
+    fn write<W: Write>(&self, kind: ArtifactKind, mut writer: W) -> Result<(), ArtifactError> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&ARTIFACT_VERSION.to_le_bytes())?;
+        writer.write_all(&[kind as u8])?;
+        for field in self.fields() {
+            writer.write_all(&field.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(kind: ArtifactKind, mut reader: R) -> Result<Self, ArtifactError> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(ArtifactError::BadMagic);
+        }
+        let mut version = [0u8; 4];
+        reader.read_exact(&mut version)?;
+        let version = u32::from_le_bytes(version);
+        if version != ARTIFACT_VERSION {
+            return Err(ArtifactError::UnsupportedVersion(version));
+        }
+        let mut found = [0u8; 1];
+        reader.read_exact(&mut found)?;
+        if found[0] != kind as u8 {
+            return Err(ArtifactError::WrongKind { expected: kind, found: found[0] });
+        }
+        let mut fields = Vec::new();
+        for _ in 0..Self::NUM_FIELDS {
+            let mut field = [0u8; 8];
+            reader.read_exact(&mut field)?;
+            fields.push(i64::from_le_bytes(field));
+        }
+        Ok(Self::from_fields(&fields))
+    }
+}
+
+/// Writes `value` behind `header`.
+pub fn write_artifact<T: CanonicalSerialize, W: Write>(
+    mut writer: W,
+    kind: ArtifactKind,
+    header: &CircuitHeader,
+    value: &T,
+) -> Result<(), ArtifactError> {
+    header.write(kind, &mut writer)?;
+    value.serialize_compressed(&mut writer)?;
+    Ok(())
+}
+
+/// Reads a value written by `write_artifact`, rejecting it unless its header is `expected`.
+pub fn read_artifact<T: CanonicalDeserialize, R: Read>(
+    mut reader: R,
+    kind: ArtifactKind,
+    expected: &CircuitHeader,
+) -> Result<T, ArtifactError> {
+    let found = CircuitHeader::read(kind, &mut reader)?;
+    if found != *expected {
+        return Err(ArtifactError::HeaderMismatch { expected: Box::new(*expected), found: Box::new(found) });
+    }
+    Ok(T::deserialize_compressed(&mut reader)?)
+}
+
+/// As `read_artifact`, but skips the subgroup checks on the contents. Only for trusted files such as
+/// a proving key written by a local setup, where the checks dominate loading time.
+pub fn read_artifact_unchecked<T: CanonicalDeserialize, R: Read>(
+    mut reader: R,
+    kind: ArtifactKind,
+    expected: &CircuitHeader,
+) -> Result<T, ArtifactError> {
+    let found = CircuitHeader::read(kind, &mut reader)?;
+    if found != *expected {
+        return Err(ArtifactError::HeaderMismatch { expected: Box::new(*expected), found: Box::new(found) });
+    }
+    Ok(T::deserialize_compressed_unchecked(&mut reader)?)
+}
+
+/// Reads the header of a file without its contents.
+pub fn read_header<R: Read>(reader: R, kind: ArtifactKind) -> Result<CircuitHeader, ArtifactError> {
+    CircuitHeader::read(kind, reader)
+}
+
+pub fn save<T: CanonicalSerialize>(
+    path: impl AsRef<Path>,
+    kind: ArtifactKind,
+    header: &CircuitHeader,
+    value: &T,
+) -> Result<(), ArtifactError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_artifact(&mut writer, kind, header, value)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn load<T: CanonicalDeserialize>(
+    path: impl AsRef<Path>,
+    kind: ArtifactKind,
+    expected: &CircuitHeader,
+) -> Result<T, ArtifactError> {
+    read_artifact(BufReader::new(File::open(path)?), kind, expected)
+}
+
+pub fn load_unchecked<T: CanonicalDeserialize>(
+    path: impl AsRef<Path>,
+    kind: ArtifactKind,
+    expected: &CircuitHeader,
+) -> Result<T, ArtifactError> {
+    read_artifact_unchecked(BufReader::new(File::open(path)?), kind, expected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_bls12_381::Fr;
+
+    #[test]
+    fn rejects_mismatched_headers() {
+        let max_lengths = MaxLengths { reference: 100, target: 100, cigar: 100 };
+        let header = CircuitHeader::new(max_lengths, ScoringScheme::bwa_mem());
+        let inputs = vec![Fr::from(3u64), -Fr::from(7u64)];
+        let mut bytes = Vec::new();
+        write_artifact(&mut bytes, ArtifactKind::Proof, &header, &inputs).unwrap();
+
+        assert_eq!(header.num_public_inputs(), 21);
+        assert_eq!(header.with_quality_weighting().with_min_mean_quality(30).num_public_inputs(), 23);
+        assert_eq!(read_header(bytes.as_slice(), ArtifactKind::Proof).unwrap(), header);
+        let read: Vec<Fr> = read_artifact(bytes.as_slice(), ArtifactKind::Proof, &header).unwrap();
+        assert_eq!(read, inputs);
+
+        let other = CircuitHeader::new(max_lengths, ScoringScheme::edit_distance());
+        assert_eq!(
+            read_artifact::<Vec<Fr>, _>(bytes.as_slice(), ArtifactKind::Proof, &other).err(),
+            Some(ArtifactError::HeaderMismatch { expected: Box::new(other), found: Box::new(header) })
+        );
+        assert!(matches!(
+            read_artifact::<Vec<Fr>, _>(bytes.as_slice(), ArtifactKind::VerifyingKey, &header),
+            Err(ArtifactError::WrongKind { .. })
+        ));
+        let weighted = header.with_quality_weighting().with_min_mean_quality(30);
+        let mut weighted_bytes = Vec::new();
+        write_artifact(&mut weighted_bytes, ArtifactKind::Proof, &weighted, &inputs).unwrap();
+        assert_eq!(read_header(weighted_bytes.as_slice(), ArtifactKind::Proof).unwrap(), weighted);
+        assert!(matches!(
+            read_artifact::<Vec<Fr>, _>(weighted_bytes.as_slice(), ArtifactKind::Proof, &header),
+            Err(ArtifactError::HeaderMismatch { .. })
+        ));
+        bytes[4] = 0;
+        assert_eq!(
+            read_artifact::<Vec<Fr>, _>(bytes.as_slice(), ArtifactKind::Proof, &header).err(),
+            Some(ArtifactError::UnsupportedVersion(0))
+        );
+    }
+}
@@ -0,0 +1,347 @@
+// Proves many read alignments against one committed reference in a single circuit. The reference is
+// unpacked and committed once into a single read-only memory that every read walks through. Reads may
+// overlap, so a reference position may be read as often as there are reads in the batch. Every read is
+// bound to its own salted commitment and length, which are public like the reference's.
+
+use ark_crypto_primitives::sponge::constraints::CryptographicSpongeVar;
+use ark_crypto_primitives::sponge::poseidon::constraints::PoseidonSpongeVar;
+use ark_crypto_primitives::sponge::Absorb;
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+
+use crate::alignment::{
+    enforce_cigar_walk_from, i64_to_felt, pack_bases_to, pack_cigar_to, unpack_cigar, MaxLengths,
+    ScoringScheme, CIGAR_DELETION, CIGAR_MATCH, CIGAR_MISMATCH,
+};
+use crate::commitment::{commit_bases, unpack_committed_sequence};
+use crate::memory::ReadOnlyMemoryVar;
+use crate::poseidon::poseidon_parameters;
+
+// Scores are compared as integers below 2^SCORE_BITS in absolute value.
+const SCORE_BITS: usize = 64;
+
+/// One read of a batch, aligned against the reference from `reference_start`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchRead {
+    pub reference_start: usize, // private, like the read itself
+    pub target_sequence_bases: Vec<usize>,
+    pub cigar_string_bases: Vec<usize>,
+    pub alignment_score: i64,
+}
+
+/// What a batch proof reveals about the scores of its reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchClaim {
+    Scores, // every score, in read order
+    CountWithin(i64), // the number of reads scoring at most this, for distance-like schemes such as edit_distance
+}
+
+#[derive(Clone)]
+pub struct BatchAlignmentCircuit<F: PrimeField> {
+    pub reference_sequence_felts: Vec<F>,
+    pub reference_sequence_bases: Vec<usize>,
+    pub reference_salt: F,
+    pub reads: Vec<BatchRead>, // the number of reads is fixed at setup time, as are the maximum lengths
+    pub read_salts: Vec<F>,
+    pub target_sequence_felts: Vec<Vec<F>>,
+    pub cigar_string_felts: Vec<Vec<F>>,
+    pub scoring_scheme: ScoringScheme,
+    pub claim: BatchClaim,
+}
+
+impl BatchRead {
+    /// Scores the alignment of `target_sequence_bases` against `reference` from `reference_start`.
+    pub fn new(
+        reference: &[usize],
+        reference_start: usize,
+        target_sequence_bases: Vec<usize>,
+        cigar_string_bases: Vec<usize>,
+        scoring_scheme: &ScoringScheme,
+    ) -> Self {
+        let span = cigar_string_bases.iter().filter(|&&op| matches!(op, CIGAR_MATCH | CIGAR_MISMATCH | CIGAR_DELETION)).count();
+        assert!(reference_start + span <= reference.len(), "read runs past the end of the reference");
+        let alignment_score = scoring_scheme.score(&reference[reference_start..], &target_sequence_bases, &cigar_string_bases);
+        Self {
+            reference_start,
+            target_sequence_bases,
+            cigar_string_bases,
+            alignment_score,
+        }
+    }
+}
+
+impl<F: PrimeField> BatchAlignmentCircuit<F> {
+    /// Packs the batch for a reference of at most `max_lengths.reference` bases and reads of at most
+    /// `max_lengths.target` bases and `max_lengths.cigar` operations.
+    pub fn new(
+        reference_sequence_bases: Vec<usize>,
+        reads: Vec<BatchRead>,
+        scoring_scheme: ScoringScheme,
+        claim: BatchClaim,
+        max_lengths: &MaxLengths,
+    ) -> Self {
+        assert!(reference_sequence_bases.len() <= max_lengths.reference, "reference is longer than the circuit allows");
+        for read in reads.iter() {
+            assert!(read.target_sequence_bases.len() <= max_lengths.target, "read is longer than the circuit allows");
+            assert!(read.cigar_string_bases.len() <= max_lengths.cigar, "CIGAR is longer than the circuit allows");
+        }
+        Self {
+            reference_sequence_felts: pack_bases_to(&reference_sequence_bases, max_lengths.reference),
+            reference_sequence_bases,
+            reference_salt: F::zero(),
+            read_salts: vec![F::zero(); reads.len()],
+            target_sequence_felts: reads.iter().map(|read| pack_bases_to(&read.target_sequence_bases, max_lengths.target)).collect(),
+            cigar_string_felts: reads.iter().map(|read| pack_cigar_to(&read.cigar_string_bases, max_lengths.cigar)).collect(),
+            reads,
+            scoring_scheme,
+            claim,
+        }
+    }
+
+    pub fn with_reference_salt(mut self, reference_salt: F) -> Self {
+        self.reference_salt = reference_salt;
+        self
+    }
+
+    /// Salts the public read commitments, one salt per read.
+    pub fn with_read_salts(mut self, read_salts: Vec<F>) -> Self {
+        assert_eq!(read_salts.len(), self.reads.len(), "need one salt per read");
+        self.read_salts = read_salts;
+        self
+    }
+
+    /// Number of reads scoring at most `max_score`.
+    pub fn count_within(&self, max_score: i64) -> usize {
+        self.reads.iter().filter(|read| read.alignment_score <= max_score).count()
+    }
+}
+
+impl<F: PrimeField + Absorb> BatchAlignmentCircuit<F> {
+    /// Public inputs in the order the circuit allocates them.
+    pub fn public_inputs(&self) -> Vec<F> {
+        let mut inputs = self.scoring_scheme.substitution_matrix_felts::<F>();
+        inputs.push(commit_bases(&self.reference_sequence_bases, self.reference_salt));
+        inputs.push(F::from(self.reference_sequence_bases.len() as u64));
+        for (read, salt) in self.reads.iter().zip(self.read_salts.iter()) {
+            inputs.push(commit_bases(&read.target_sequence_bases, *salt));
+            inputs.push(F::from(read.target_sequence_bases.len() as u64));
+        }
+        match self.claim {
+            BatchClaim::Scores => inputs.extend(self.reads.iter().map(|read| i64_to_felt::<F>(read.alignment_score))),
+            BatchClaim::CountWithin(max_score) => {
+                inputs.push(i64_to_felt::<F>(max_score));
+                inputs.push(F::from(self.count_within(max_score) as u64));
+            }
+        }
+        inputs
+    }
+}
+
+impl<F: PrimeField> ConstraintSynthesizer<F> for BatchAlignmentCircuit<F> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+        let mut reference_sequence_vars = Vec::new();
+        for elem in self.reference_sequence_felts.iter() {
+            reference_sequence_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+
+        let mut target_sequence_vars = Vec::new();
+        let mut cigar_string_vars = Vec::new();
+        for (target_felts, cigar_felts) in self.target_sequence_felts.iter().zip(self.cigar_string_felts.iter()) {
+            let mut target_vars = Vec::new();
+            for elem in target_felts.iter() {
+                target_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+            }
+            target_sequence_vars.push(target_vars);
+            let mut cigar_vars = Vec::new();
+            for elem in cigar_felts.iter() {
+                cigar_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+            }
+            cigar_string_vars.push(cigar_vars);
+        }
+
+        let params = poseidon_parameters();
+
+        let reference = unpack_committed_sequence(cs.clone(), &reference_sequence_vars, self.reference_sequence_bases.len(), self.reference_salt)?;
+
+        // One sponge over every read and CIGAR block.
+        let mut reads_sponge = PoseidonSpongeVar::new(cs.clone(), &params);
+        for (target_vars, cigar_vars) in target_sequence_vars.iter().zip(cigar_string_vars.iter()) {
+            reads_sponge.absorb(target_vars)?;
+            reads_sponge.absorb(cigar_vars)?;
+        }
+        let reads_hash = reads_sponge.squeeze_field_elements(1)?;
+
+        let mut substitution_matrix_vars = Vec::new();
+        for elem in self.scoring_scheme.substitution_matrix_felts::<F>() {
+            substitution_matrix_vars.push(FpVar::new_input(cs.clone(), || Ok(elem))?);
+        }
+        let reference_commitment_var = FpVar::new_input(cs.clone(), || reference.commitment.value())?;
+        reference_commitment_var.enforce_equal(&reference.commitment)?;
+        let reference_length_var = FpVar::new_input(cs.clone(), || reference.length.value())?;
+        reference_length_var.enforce_equal(&reference.length)?;
+
+        let mut transcript = vec![reference_commitment_var, reference_length_var, reads_hash[0].clone()];
+        let mut targets = Vec::new();
+        for ((read, target_vars), salt) in self.reads.iter().zip(target_sequence_vars.iter()).zip(self.read_salts.iter()) {
+            let target = unpack_committed_sequence(cs.clone(), target_vars, read.target_sequence_bases.len(), *salt)?;
+            let target_commitment_var = FpVar::new_input(cs.clone(), || target.commitment.value())?;
+            target_commitment_var.enforce_equal(&target.commitment)?;
+            let target_length_var = FpVar::new_input(cs.clone(), || target.length.value())?;
+            target_length_var.enforce_equal(&target.length)?;
+            transcript.push(target_commitment_var);
+            transcript.push(target_length_var.clone());
+            targets.push((target, target_length_var));
+        }
+        transcript.extend(substitution_matrix_vars.iter().cloned());
+
+        // Walk every read through the shared reference memory.
+        let mut reference_memory = ReadOnlyMemoryVar::from_bases(cs.clone(), &reference.bases, self.reads.len() as u64);
+        let mut target_memories = Vec::new();
+        let mut score_vars = Vec::new();
+        for ((read, (target, target_length_var)), cigar_vars) in self.reads.iter().zip(targets.iter()).zip(cigar_string_vars.iter()) {
+            let mut cigar_chars = Vec::new();
+            for block in cigar_vars.iter() {
+                cigar_chars.extend(unpack_cigar(block)?);
+            }
+            let mut target_memory = ReadOnlyMemoryVar::from_bases(cs.clone(), &target.bases, 1);
+            let reference_start_var = FpVar::new_witness(cs.clone(), || Ok(F::from(read.reference_start as u64)))?;
+            let walk = enforce_cigar_walk_from(
+                cs.clone(),
+                &cigar_chars,
+                &reference_start_var,
+                &mut reference_memory,
+                &mut target_memory,
+                &self.scoring_scheme,
+                &substitution_matrix_vars,
+            )?;
+            // The walk reads distinct present slots, so reading as many as the length reads them all.
+            walk.target_length.enforce_equal(target_length_var)?;
+            target_memories.push(target_memory);
+            score_vars.push((walk.alignment_score, read.alignment_score));
+        }
+
+        reference_memory.finalize(&transcript)?;
+        for target_memory in target_memories {
+            target_memory.finalize(&transcript)?;
+        }
+
+        match self.claim {
+            BatchClaim::Scores => {
+                for (score_var, score) in score_vars {
+                    FpVar::new_input(cs.clone(), || Ok(i64_to_felt::<F>(score)))?.enforce_equal(&score_var)?;
+                }
+            }
+            BatchClaim::CountWithin(max_score) => {
+                let max_score_var = FpVar::new_input(cs.clone(), || Ok(i64_to_felt::<F>(max_score)))?;
+                let mut count = FpVar::zero();
+                for (score_var, score) in score_vars {
+                    // within iff max_score - score >= 0, otherwise score - max_score - 1 >= 0.
+                    let within = Boolean::new_witness(cs.clone(), || Ok(score <= max_score))?;
+                    let difference = within.select(&(&max_score_var - &score_var), &(&score_var - &max_score_var - FpVar::one()))?;
+                    let magnitude = if score <= max_score { max_score.abs_diff(score) } else { score.abs_diff(max_score) - 1 };
+                    let mut bits = Vec::new();
+                    for k in 0..SCORE_BITS {
+                        bits.push(Boolean::new_witness(cs.clone(), || Ok((magnitude >> k) & 1 == 1))?);
+                    }
+                    Boolean::le_bits_to_fp_var(&bits)?.enforce_equal(&difference)?;
+                    count += FpVar::from(within);
+                }
+                FpVar::new_input(cs.clone(), || count.value())?.enforce_equal(&count)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::{BASE_A, BASE_PADDING, BASE_T, CIGAR_INSERTION, CIGAR_SOFT_CLIP};
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::rand::Rng;
+    use ark_std::UniformRand;
+
+    fn reads(reference: &[usize], scheme: &ScoringScheme) -> Vec<BatchRead> {
+        let mut with_snp = reference[40..70].to_vec();
+        with_snp[5] = (with_snp[5] + 1) % 4;
+        let mut with_insertion = reference[50..60].to_vec();
+        with_insertion.insert(5, BASE_A);
+        let mut clipped = vec![BASE_T, BASE_T];
+        clipped.extend(&reference[100..120]);
+        vec![
+            BatchRead::new(reference, 10, reference[10..40].to_vec(), vec![CIGAR_MATCH; 30], scheme),
+            BatchRead::new(reference, 40, with_snp.clone(), expand(&reference[40..70], &with_snp), scheme),
+            // Overlaps the previous read.
+            BatchRead::new(reference, 50, with_insertion, [vec![CIGAR_MATCH; 5], vec![CIGAR_INSERTION], vec![CIGAR_MATCH; 5]].concat(), scheme),
+            BatchRead::new(reference, 100, clipped, [vec![CIGAR_SOFT_CLIP; 2], vec![CIGAR_MATCH; 20]].concat(), scheme),
+        ]
+    }
+
+    fn expand(reference: &[usize], target: &[usize]) -> Vec<usize> {
+        reference.iter().zip(target).map(|(r, t)| if r == t { CIGAR_MATCH } else { CIGAR_MISMATCH }).collect()
+    }
+
+    fn is_satisfied(circuit: BatchAlignmentCircuit<Fr>) -> bool {
+        let cs = ConstraintSystem::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn proves_a_batch_of_reads() {
+        let rng = &mut ark_std::test_rng();
+        let reference: Vec<usize> = (0..150).map(|_| rng.gen_range(BASE_A..=BASE_T)).collect();
+        let scheme = ScoringScheme::edit_distance();
+        let max_lengths = MaxLengths { reference: 168, target: 40, cigar: 40 };
+        let batch = BatchAlignmentCircuit::<Fr>::new(reference.clone(), reads(&reference, &scheme), scheme, BatchClaim::Scores, &max_lengths);
+        assert_eq!(batch.reads.iter().map(|read| read.alignment_score).collect::<Vec<_>>(), vec![0, 1, 1, 0]);
+
+        let cs = ConstraintSystem::new_ref();
+        batch.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.borrow().unwrap().instance_assignment[1..], batch.public_inputs()[..]);
+
+        let mut counted = batch.clone();
+        counted.claim = BatchClaim::CountWithin(0);
+        assert_eq!(counted.count_within(0), 2);
+        let cs = ConstraintSystem::new_ref();
+        counted.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.borrow().unwrap().instance_assignment[1..], counted.public_inputs()[..]);
+
+        // Claiming a lower score for the read with a SNP fails.
+        let mut wrong_score = batch.clone();
+        wrong_score.reads[1].alignment_score = 0;
+        assert!(!is_satisfied(wrong_score));
+
+        // So does aligning a read one position off.
+        let mut shifted = batch.clone();
+        shifted.reads[0].reference_start = 11;
+        assert!(!is_satisfied(shifted));
+
+        // Swapping two reads with equal scores changes their public commitments.
+        let salts: Vec<Fr> = (0..batch.reads.len()).map(|_| Fr::rand(rng)).collect();
+        let salted = batch.clone().with_read_salts(salts.clone());
+        let mut swapped = salted.clone();
+        swapped.reads.swap(0, 3);
+        swapped.target_sequence_felts.swap(0, 3);
+        swapped.cigar_string_felts.swap(0, 3);
+        assert_ne!(swapped.public_inputs(), salted.public_inputs());
+        let cs = ConstraintSystem::new_ref();
+        swapped.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        cs.borrow_mut().unwrap().instance_assignment[1..].copy_from_slice(&salted.public_inputs());
+        assert!(!cs.is_satisfied().unwrap());
+
+        // And padding an unread reference position before the public length.
+        let mut truncated = batch;
+        let mut padded_reference = reference;
+        padded_reference[140] = BASE_PADDING;
+        truncated.reference_sequence_felts = pack_bases_to(&padded_reference, max_lengths.reference);
+        assert!(!is_satisfied(truncated));
+    }
+}
@@ -1,280 +1,272 @@
 // This file is largely adopted from the VeriTAS paper (thank you Trisha) because it was already doing
 // basically what I needed.
 
+use std::str::FromStr;
+
 use ark_bls12_381::{Bls12_381, Fr};
 use ark_crypto_primitives::snark::{CircuitSpecificSetupSNARK, SNARK};
-use ark_crypto_primitives::sponge::constraints::CryptographicSpongeVar;
-use ark_crypto_primitives::sponge::poseidon::constraints::PoseidonSpongeVar;
-use ark_crypto_primitives::sponge::poseidon::PoseidonConfig;
-use ark_crypto_primitives::sponge::poseidon::PoseidonSponge;
 
-use ark_crypto_primitives::sponge::{CryptographicSponge, FieldBasedCryptographicSponge};
 use ark_ff::vec::Vec;
-use ark_ff::PrimeField;
-use ark_ff::UniformRand;
-use ark_ff::{BigInt, BigInteger};
-use ark_groth16::Groth16;
+use ark_groth16::{Groth16, Proof, ProvingKey, VerifyingKey};
+use ark_r1cs_std::alloc::AllocVar;
 use ark_r1cs_std::fields::fp::FpVar;
-use ark_r1cs_std::bits::ToBitsGadget;
-use ark_r1cs_std::ToConstraintFieldGadget;
-use ark_r1cs_std::{prelude::*, uint128::UInt128};
 use ark_relations::r1cs::ConstraintSystem;
-use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
-use ark_relations::*;
+use ark_relations::r1cs::ConstraintSynthesizer;
 use ark_std::rand::{RngCore, SeedableRng};
-use ark_std::test_rng;
-use ark_std::Zero;
-use ark_std::time::{SystemTime, UNIX_EPOCH};
+use ark_std::{test_rng, UniformRand};
 use rand::Rng;
-use alignment_circuits::poseidon_parameters_for_test;
+use alignment_circuits::alignment::{
+    felt_to_i64, pack_bases, unpack_bases, AlignmentCircuit, MAX_QUALITY, MaxLengths, ScoringScheme, BASES_PER_BLOCK, BASE_A, BASE_T, CIGAR_MATCH,
+    CIGAR_OPS_PER_BLOCK,
+};
+use alignment_circuits::artifact::{self, ArtifactKind, CircuitHeader};
+use alignment_circuits::sam::{alignment_circuit_from_sam, FastaReference, SamRecord};
+use alignment_circuits::ivc;
+use alignment_circuits::stark;
 use ark_relations::r1cs::{ConstraintLayer, TracingMode};
 use tracing_subscriber::layer::SubscriberExt;
 
-const BASES_PER_BLOCK: usize = 125;
-const SEQUENCE_BLOCK_LENGTH: usize = 1 << 6;
-const SEQUENCE_BASE_PAIRS: usize = SEQUENCE_BLOCK_LENGTH * BASES_PER_BLOCK;
-const CIGAR_STRING_LENGTH: usize = SEQUENCE_BASE_PAIRS;
-const CIGAR_STRING_LENGTH_BLOCKS: usize = SEQUENCE_BASE_PAIRS.div_ceil(BASES_PER_BLOCK);
-
+// The circuit is set up for these maximum lengths, any shorter alignment is proven with the same keys.
+const MAX_SEQUENCE_BLOCK_LENGTH: usize = 1 << 6;
+const MAX_SEQUENCE_BASE_PAIRS: usize = MAX_SEQUENCE_BLOCK_LENGTH * BASES_PER_BLOCK;
+const MAX_CIGAR_STRING_LENGTH: usize = MAX_SEQUENCE_BASE_PAIRS;
+// Longer alignments are folded in segments of this many blocks.
+const IVC_BLOCKS_PER_SEGMENT: usize = 8;
 
-pub struct AlignmentCircuit<F: PrimeField> {
-    pub reference_sequence_felts: Vec<F>, // The reference for a certain gene, encoded as field elements, where 125 bases are packed per field element
-    pub reference_sequence_bases: Vec<usize>, // Reference sequence encoded as usize's between 0 and 4
-    pub target_sequence_felts: Vec<F>, // The value being aligned against the target
-    pub target_sequence_bases: Vec<usize>,
-    pub cigar_string_felts: Vec<F>, // Not fully a CIGAR string yet
-    pub cigar_string_bases: Vec<usize>, // encoded as usizes between 0 and 3
-    pub alignment_score: usize, // claimed alignment score which is a public output. 0 is perfect, +1 for each insertion or deletion in the basic version.
+fn generate_random_sequence(bases: usize) -> Vec<usize> {
+    let rng = &mut ark_std::test_rng();
+    (0..bases).map(|_| rng.gen_range(BASE_A..=BASE_T)).collect::<Vec<_>>()
 }
 
-#[allow(dead_code)]
-impl<F: PrimeField> AlignmentCircuit<F> {
-    pub fn new(
-        reference_sequence_felts: Vec<F>,
-        reference_sequence_bases: Vec<usize>,
-        target_sequence_felts: Vec<F>,
-        target_sequence_bases: Vec<usize>,
-        cigar_string_felts: Vec<F>,
-        cigar_string_bases: Vec<usize>,
-        alignment_score: usize,
-    ) -> Self {
-        Self {
-            reference_sequence_felts,
-            reference_sequence_bases,
-            target_sequence_felts,
-            target_sequence_bases,
-            cigar_string_felts,
-            cigar_string_bases,
-            alignment_score,
-        }
-    }
+fn identical_alignment(bases: usize, scoring_scheme: ScoringScheme, max_lengths: &MaxLengths) -> AlignmentCircuit<Fr> {
+    let reference_sequence_bases = generate_random_sequence(bases);
+    let target_sequence_bases = reference_sequence_bases.clone();
+    let cigar_string_letters = (0..bases).map(|_| CIGAR_MATCH).collect::<Vec<_>>();
+    let alignment_score = scoring_scheme.score(&reference_sequence_bases, &target_sequence_bases, &cigar_string_letters);
+    AlignmentCircuit::padded(reference_sequence_bases, target_sequence_bases, cigar_string_letters, scoring_scheme, alignment_score, max_lengths)
 }
 
-impl<F: PrimeField> Clone for AlignmentCircuit<F> {
-    fn clone(&self) -> Self {
-        AlignmentCircuit {
-            reference_sequence_felts: self.reference_sequence_felts.clone(),
-            reference_sequence_bases: self.reference_sequence_bases.clone(),
-            target_sequence_felts: self.target_sequence_felts.clone(),
-            target_sequence_bases: self.target_sequence_bases.clone(),
-            cigar_string_felts: self.cigar_string_felts.clone(),
-            cigar_string_bases: self.cigar_string_bases.clone(),
-            alignment_score: self.alignment_score,
-        }
-    }
+/// Constraints spent unpacking one block of bases, per base.
+fn unpacking_constraints_per_base() -> f64 {
+    let cs = ConstraintSystem::<Fr>::new_ref();
+    let block = pack_bases::<Fr>(&generate_random_sequence(BASES_PER_BLOCK))[0];
+    let block_var = FpVar::new_witness(cs.clone(), || Ok(block)).unwrap();
+    unpack_bases(&block_var).unwrap();
+    cs.num_constraints() as f64 / BASES_PER_BLOCK as f64
 }
 
-impl<F: PrimeField> ConstraintSynthesizer<F> for AlignmentCircuit<F> {
-    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
-        let mut reference_sequence_vars = Vec::new();
-        for elem in self.reference_sequence_felts.iter() {
-            reference_sequence_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
-        }
-
-        let mut target_sequence_vars = Vec::new();
-        for elem in self.target_sequence_felts.iter() {
-            target_sequence_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
-        }
-
-        let mut cigar_string_vars = Vec::new();
-        for elem in self.cigar_string_felts.iter() {
-            cigar_string_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
-        }
-
-        let params = poseidon_parameters_for_test();
-
-        let mut reference_sponge = PoseidonSpongeVar::new(cs.clone(), &params);
-        reference_sponge.absorb(&reference_sequence_vars)?;
-        let reference_hash = reference_sponge.squeeze_field_elements(1)?;
-
-        let mut target_sponge = PoseidonSpongeVar::new(cs.clone(), &params);
-        target_sponge.absorb(&target_sequence_vars)?;
-        let target_hash = target_sponge.squeeze_field_elements(1)?;
-
-        let mut cigar_sponge = PoseidonSpongeVar::new(cs.clone(), &params);
-        cigar_sponge.absorb(&cigar_string_vars)?;
-        let cigar_hash = cigar_sponge.squeeze_field_elements(1)?;
-
-        let vars_for_fs_hash = vec![&reference_hash[0], &target_hash[0], &cigar_hash[0]];
-        let mut challenge_sponge = PoseidonSpongeVar::new(cs.clone(), &params);
-        challenge_sponge.absorb(&vars_for_fs_hash)?;
-        let challenge_vars = challenge_sponge.squeeze_field_elements(2)?;
-
-        let mut reference_bases = Vec::new();
-        let two = FpVar::new_constant(cs.clone(), F::one() + F::one()).unwrap();
-        for block in reference_sequence_vars {
-            let block_bits = &block.to_bits_le().unwrap();
-            let mut chunks = block_bits[0..250].chunks_exact(2);
-            for bit_pair in chunks.by_ref().take(BASES_PER_BLOCK) {
-                let new_bp = &bit_pair[0].to_constraint_field().unwrap()[0] + &two * &bit_pair[1].to_constraint_field().unwrap()[0];
-                reference_bases.push(new_bp);
-            }
-            for bit in &block_bits[250..] {
-                bit.enforce_equal(&Boolean::<F>::FALSE).unwrap();
-            }
-        }
+fn usage() -> ! {
+    eprintln!("usage: circuit [bench]");
+    eprintln!("       circuit setup <proving-key> <verifying-key> [--qualities [--min-mean-quality <quality>]]");
+    eprintln!("       circuit salts <salts>");
+    eprintln!("       circuit prove <proving-key> <proof> <salts> [<reference.fa> <alignments.sam>]");
+    eprintln!("       circuit verify <verifying-key> <proof>");
+    std::process::exit(2)
+}
 
-        let mut target_bases = Vec::new();
-        for block in target_sequence_vars {
-            let block_bits = &block.to_bits_le().unwrap();
-            let mut chunks = block_bits[0..250].chunks_exact(2);
-            for bit_pair in chunks.by_ref().take(BASES_PER_BLOCK) {
-                let new_bp = &bit_pair[0].to_constraint_field().unwrap()[0] + &two * &bit_pair[1].to_constraint_field().unwrap()[0];
-                target_bases.push(new_bp);
-            }
-            for bit in &block_bits[250..] {
-                bit.enforce_equal(&Boolean::<F>::FALSE).unwrap();
-            }
-        }
+fn circuit_header() -> (CircuitHeader, MaxLengths, ScoringScheme) {
+    let max_lengths = MaxLengths { reference: MAX_SEQUENCE_BASE_PAIRS, target: MAX_SEQUENCE_BASE_PAIRS, cigar: MAX_CIGAR_STRING_LENGTH };
+    let scoring_scheme = ScoringScheme::bwa_mem();
+    (CircuitHeader::new(max_lengths, scoring_scheme), max_lengths, scoring_scheme)
+}
 
-        let mut cigar_chars = Vec::new();
-        for block in cigar_string_vars {
-            let block_bits = &block.to_bits_le().unwrap();
-            let mut chunks = block_bits[0..250].chunks_exact(2);
-            for bit_pair in chunks.by_ref().take(BASES_PER_BLOCK) {
-                let new_bp = &bit_pair[0].to_constraint_field().unwrap()[0] + &two * &bit_pair[1].to_constraint_field().unwrap()[0];
-                cigar_chars.push(new_bp);
-            }
-            for bit in &block_bits[250..] {
-                bit.enforce_equal(&Boolean::<F>::FALSE).unwrap();
+/// The header for `setup` with the quality options given on the command line.
+fn setup_header(options: &[&str]) -> CircuitHeader {
+    let (header, _, _) = circuit_header();
+    match options {
+        [] => header,
+        ["--qualities"] => header.with_quality_weighting(),
+        ["--qualities", "--min-mean-quality", min_mean_quality] => {
+            let min_mean_quality = exit_on_error(min_mean_quality.parse::<usize>());
+            if min_mean_quality > MAX_QUALITY {
+                exit_on_error::<(), _>(Err(format!("the quality bound must be at most {}", MAX_QUALITY)));
             }
+            header.with_quality_weighting().with_min_mean_quality(min_mean_quality)
         }
+        _ => usage(),
+    }
+}
 
-        let mut target_string_memcheck_prod_1 = FpVar::new_constant(cs.clone(), F::one()).unwrap();
-        let mut reference_string_memcheck_prod_1 = FpVar::new_constant(cs.clone(), F::one()).unwrap();
-        let mut i_felt = F::zero();
-        for i in 0..reference_bases.len() {
-            target_string_memcheck_prod_1 *= &challenge_vars[0] + &target_bases[i] + FpVar::new_constant(cs.clone(), i_felt).unwrap() * &challenge_vars[1];
-            reference_string_memcheck_prod_1 *= &challenge_vars[0] + &reference_bases[i] + FpVar::new_constant(cs.clone(), i_felt).unwrap() * &challenge_vars[1];
-            i_felt += F::one();
-        }
-
-        // Constants
-        let alignment_match = FpVar::new_constant(cs.clone(), F::zero()).unwrap();
-        let insertion = FpVar::new_constant(cs.clone(), F::one()).unwrap();
-        let deletion = FpVar::new_constant(cs.clone(), F::one() + F::one()).unwrap();
+/// The header of the key in `path`: the circuit of this binary, with the quality options the key was
+/// set up for. Keys for any other circuit are still rejected when loaded.
+fn key_header(path: &str, kind: ArtifactKind) -> CircuitHeader {
+    let (header, _, _) = circuit_header();
+    let file = exit_on_error(std::fs::File::open(path));
+    let found = exit_on_error(artifact::read_header(std::io::BufReader::new(file), kind));
+    CircuitHeader { quality_weighted: found.quality_weighted, min_mean_quality: found.min_mean_quality, ..header }
+}
 
+/// Weights the score of `c` by `qualities` and proves the quality bound if `header` asks for them.
+fn with_quality_options(
+    c: AlignmentCircuit<Fr>,
+    header: &CircuitHeader,
+    qualities: Option<Vec<usize>>,
+    quality_salt: Option<Fr>,
+) -> AlignmentCircuit<Fr> {
+    if !header.quality_weighted {
+        return c;
+    }
+    let qualities = qualities.unwrap_or_else(|| exit_on_error(Err("the keys weight scores by quality but the read has none")));
+    let quality_salt = quality_salt.unwrap_or_else(|| exit_on_error(Err("the keys weight scores by quality but the salt file has no quality salt")));
+    let c = c.with_qualities(qualities.clone(), quality_salt);
+    let Some(min_mean_quality) = header.min_mean_quality else {
+        return c;
+    };
+    if qualities.iter().sum::<usize>() < min_mean_quality * qualities.len() {
+        exit_on_error::<(), _>(Err(format!("the mean base quality of the read is below {}", min_mean_quality)));
+    }
+    c.with_min_mean_quality(min_mean_quality)
+}
 
-        let mut target_index_var = FpVar::new_constant(cs.clone(), F::zero()).unwrap();
-        let mut reference_index_var = FpVar::new_constant(cs.clone(), F::zero()).unwrap();
-        let mut target_index = 0usize;
-        let mut reference_index = 0usize;
-        let mut target_string_memcheck_prod_2 = FpVar::new_constant(cs.clone(), F::one()).unwrap();
-        let mut reference_string_memcheck_prod_2 = FpVar::new_constant(cs.clone(), F::one()).unwrap();
-        let mut alignment_score = FpVar::new_constant(cs.clone(), F::zero()).unwrap();
-        for i in 0..cigar_chars.len() {
-            let is_match = cigar_chars[i].is_eq(&alignment_match).unwrap();
-            let is_insertion = cigar_chars[i].is_eq(&insertion).unwrap();
-            let is_deletion = cigar_chars[i].is_eq(&deletion).unwrap();
-            alignment_score += &is_deletion.to_constraint_field().unwrap()[0] + &is_insertion.to_constraint_field().unwrap()[0];
+fn exit_on_error<T, E: std::fmt::Display>(result: Result<T, E>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        std::process::exit(1)
+    })
+}
 
-            // TODO: convert the strings of felts into strings of base pairs
-            // as part of the struct (or this function). For now don't compute an alignment and just assume it's 
-            // 100% match.
-            let target_sequence_read_val = FpVar::new_witness(cs.clone(), || Ok(usize_to_felt::<F>(self.target_sequence_bases[i]))).unwrap();
-            let reference_sequence_read_val = FpVar::new_witness(cs.clone(), || Ok(usize_to_felt::<F>(self.reference_sequence_bases[i]))).unwrap();
+/// Writes a fresh reference, target and quality salt to `salts_path`, one decimal field element per
+/// line. The salts open the public commitments, so they are kept by the prover and never printed.
+fn generate_salts(salts_path: &str) {
+    let mut rng = ark_std::rand::rngs::StdRng::from_entropy();
+    let salts = format!("{}\n{}\n{}\n", Fr::rand(&mut rng), Fr::rand(&mut rng), Fr::rand(&mut rng));
+    exit_on_error(std::fs::write(salts_path, salts));
+    println!("wrote salts to {}", salts_path);
+}
 
-            // if is_match, bases_match must be true. if not is match either is fine.
-            let bases_match = target_sequence_read_val.is_eq(&reference_sequence_read_val).unwrap();
-            is_match.not().or(&bases_match).unwrap().enforce_equal(&Boolean::<F>::TRUE).unwrap();
+/// Reads the reference and target salts written by `generate_salts`, and the quality salt if there is
+/// one.
+fn read_salts(salts_path: &str) -> (Fr, Fr, Option<Fr>) {
+    let contents = exit_on_error(std::fs::read_to_string(salts_path));
+    let salts: Vec<Fr> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| exit_on_error(Fr::from_str(line).map_err(|_| format!("salt {:?} is not a field element", line))))
+        .collect();
+    match salts[..] {
+        [reference_salt, target_salt] => (reference_salt, target_salt, None),
+        [reference_salt, target_salt, quality_salt] => (reference_salt, target_salt, Some(quality_salt)),
+        _ => exit_on_error(Err("salt file must hold the reference salt, the target salt and optionally the quality salt, one per line")),
+    }
+}
 
-            target_string_memcheck_prod_2 *= (&is_match.or(&is_insertion).unwrap()).select(&(&challenge_vars[0] + &target_sequence_read_val + &target_index_var * &challenge_vars[1]), &FpVar::new_constant(cs.clone(), F::one()).unwrap()).unwrap();
-            reference_string_memcheck_prod_2 *= (&is_match.or(&is_deletion).unwrap()).select(&(&challenge_vars[0] + &reference_sequence_read_val + &reference_index_var * &challenge_vars[1]), &FpVar::new_constant(cs.clone(), F::one()).unwrap()).unwrap();
+fn setup(proving_key_path: &str, verifying_key_path: &str, header: CircuitHeader) {
+    let (_, max_lengths, scoring_scheme) = circuit_header();
+    let c = identical_alignment(MAX_SEQUENCE_BASE_PAIRS, scoring_scheme, &max_lengths);
+    let c = with_quality_options(c, &header, Some(vec![MAX_QUALITY; MAX_SEQUENCE_BASE_PAIRS]), Some(Fr::from(0u64)));
+    let mut rng = ark_std::rand::rngs::StdRng::from_entropy();
+    let (pk, vk) = Groth16::<Bls12_381>::setup(c, &mut rng).unwrap();
+    exit_on_error(artifact::save(proving_key_path, ArtifactKind::ProvingKey, &header, &pk));
+    exit_on_error(artifact::save(verifying_key_path, ArtifactKind::VerifyingKey, &header, &vk));
+    println!("wrote keys for {:?}", header);
+}
 
-            target_index_var += (&is_match.to_constraint_field().unwrap()[0] + &is_insertion.to_constraint_field().unwrap()[0]);
-            reference_index_var += &is_match.to_constraint_field().unwrap()[0] + &is_deletion.to_constraint_field().unwrap()[0];
-            match self.cigar_string_bases[i] {
-                0 => {target_index+=1; reference_index +=1},
-                1 => {target_index+=1;},
-                2 => {reference_index +=1},
-                _ => {panic!("Witness generation reached an incorrect CIGAR character")}
-            }
+fn prove(proving_key_path: &str, proof_path: &str, salts_path: &str, sam_input: Option<(&str, &str)>) {
+    let (_, max_lengths, scoring_scheme) = circuit_header();
+    let header = key_header(proving_key_path, ArtifactKind::ProvingKey);
+    let pk: ProvingKey<Bls12_381> = exit_on_error(artifact::load_unchecked(proving_key_path, ArtifactKind::ProvingKey, &header));
+    let (c, qualities) = match sam_input {
+        Some((fasta_path, sam_path)) => {
+            let reference = exit_on_error(FastaReference::read(fasta_path));
+            let sam = exit_on_error(std::fs::read_to_string(sam_path));
+            let record = sam
+                .lines()
+                .filter(|line| !line.starts_with('@') && !line.is_empty())
+                .map(|line| exit_on_error(SamRecord::parse(line)))
+                .find(|record| !record.is_unmapped())
+                .unwrap_or_else(|| exit_on_error(Err("no mapped alignment in the SAM file")));
+            let c = exit_on_error(alignment_circuit_from_sam::<Fr>(&record, &reference, scoring_scheme)).pad_to(&max_lengths);
+            (c, exit_on_error(record.qualities()))
         }
-
-        target_string_memcheck_prod_1.enforce_equal(&target_string_memcheck_prod_2).unwrap();
-        reference_string_memcheck_prod_1.enforce_equal(&reference_string_memcheck_prod_2).unwrap();
-
-        let res_score = FpVar::<F>::new_input(cs.clone(), || Ok(F::from_le_bytes_mod_order(&self.alignment_score.to_le_bytes())))?;
-        res_score.enforce_equal(&alignment_score).unwrap();
-
-        Ok(())
+        None => (identical_alignment(MAX_SEQUENCE_BASE_PAIRS, scoring_scheme, &max_lengths), Some(vec![MAX_QUALITY; MAX_SEQUENCE_BASE_PAIRS])),
+    };
+    let (reference_salt, target_salt, quality_salt) = read_salts(salts_path);
+    let c = with_quality_options(c.with_salts(reference_salt, target_salt), &header, qualities, quality_salt);
+    let public_inputs = c.public_inputs();
+    println!("reference commitment {}", public_inputs[16]);
+    println!("target commitment {}", public_inputs[17]);
+    if header.quality_weighted {
+        println!("quality commitment {}", public_inputs[21]);
     }
+    let mut rng = ark_std::rand::rngs::StdRng::from_entropy();
+    let proof = Groth16::<Bls12_381>::prove(&pk, c, &mut rng).unwrap();
+    exit_on_error(artifact::save(proof_path, ArtifactKind::Proof, &header, &(proof, public_inputs)));
+    println!("wrote proof to {}", proof_path);
 }
 
-fn generate_random_sequence(bases: usize) -> (Vec<Fr>, Vec<usize>) {
-    let rng = &mut ark_std::test_rng();
-    let random_bools = (0..bases).map(|_| vec![rng.gen_bool(0.5), rng.gen_bool(0.5)]).collect::<Vec<_>>();
-    let random_bases = random_bools.clone().into_iter().map(|x| (x[1] as usize) * 2 + (x[0] as usize)).collect::<Vec<_>>();
-
-    let random_felts = 
-            random_bools.clone()
-                .into_iter()
-                .flatten()
-                .collect::<Vec<_>>()
-                .chunks(BASES_PER_BLOCK * 2)
-                .map(|x| {
-                    let mut x_vec = vec![];
-                    let five_false =  vec![false; 5]; // need to pad x with 0's so this behaves as expected.
-                    x_vec.extend_from_slice(x);
-                    x_vec.extend_from_slice(&five_false);
-                    Fr::from_bigint(BigInt::from_bits_le(&x_vec)).unwrap()})
-                .collect();
-    (random_felts, random_bases)
+fn verify(verifying_key_path: &str, proof_path: &str) {
+    let header = key_header(verifying_key_path, ArtifactKind::VerifyingKey);
+    let vk: VerifyingKey<Bls12_381> = exit_on_error(artifact::load(verifying_key_path, ArtifactKind::VerifyingKey, &header));
+    let (proof, public_inputs): (Proof<Bls12_381>, Vec<Fr>) = exit_on_error(artifact::load(proof_path, ArtifactKind::Proof, &header));
+    if public_inputs.len() != header.num_public_inputs() {
+        exit_on_error::<(), _>(Err(format!("proof has {} public inputs, the circuit has {}", public_inputs.len(), header.num_public_inputs())));
+    }
+    if public_inputs[..16] != header.scoring_scheme.substitution_matrix_felts::<Fr>()[..] {
+        exit_on_error::<(), _>(Err("proof is for a different substitution matrix than the verifying key"));
+    }
+    if let Some(min_mean_quality) = header.min_mean_quality
+        && public_inputs[22] != Fr::from(min_mean_quality as u64)
+    {
+        exit_on_error::<(), _>(Err("proof is for a different quality bound than the verifying key"));
+    }
+    let pvk = exit_on_error(Groth16::<Bls12_381>::process_vk(&vk));
+    if exit_on_error(Groth16::<Bls12_381>::verify_with_processed_vk(&pvk, &public_inputs, &proof)) {
+        let score = public_inputs[20];
+        println!(
+            "proof verifies for reference commitment {}, target commitment {}, reference length {}, target length {}, alignment score {}",
+            public_inputs[16],
+            public_inputs[17],
+            public_inputs[18],
+            public_inputs[19],
+            felt_to_i64(score).map_or_else(|| score.to_string(), |score| score.to_string())
+        );
+        if header.quality_weighted {
+            println!("the score is weighted by the qualities with commitment {}", public_inputs[21]);
+        }
+        if let Some(min_mean_quality) = header.min_mean_quality {
+            println!("the mean base quality is at least {}", min_mean_quality);
+        }
+    } else {
+        eprintln!("proof does not verify");
+        std::process::exit(1);
+    }
 }
 
-fn usize_to_felt<F: PrimeField>(base: usize) -> F {
-    match base {
-        0 => F::zero(),
-        1 => F::one(),
-        2 => F::one() + F::one(),
-        3 => F::one() + F::one() + F::one(),
-        _ => panic!("bad base number provided"),
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[1..] {
+        [] | ["bench"] => bench(),
+        ["setup", proving_key, verifying_key, ref options @ ..] => setup(proving_key, verifying_key, setup_header(options)),
+        ["salts", salts] => generate_salts(salts),
+        ["prove", proving_key, proof, salts] => prove(proving_key, proof, salts, None),
+        ["prove", proving_key, proof, salts, fasta, sam] => prove(proving_key, proof, salts, Some((fasta, sam))),
+        ["verify", verifying_key, proof] => verify(verifying_key, proof),
+        _ => usage(),
     }
 }
 
-fn main() {
+fn bench() {
     // For benchmarking, just verify the alignment of 2 identical sequences, I did correctness testing separately.
     // zk proofs are a uniform model of computation so data used is not overly important.
-    let (reference_sequence_felts, reference_sequence_bases) = generate_random_sequence(SEQUENCE_BASE_PAIRS);
-    let (target_sequence_felts, target_sequence_bases) = (reference_sequence_felts.clone(), reference_sequence_bases.clone());
-
-    let cigar_string_felts: Vec<_> = (0..CIGAR_STRING_LENGTH_BLOCKS).map(|_| Fr::zero()).collect();
-    let cigar_string_letters = (0..CIGAR_STRING_LENGTH).map(|_| 0).collect::<Vec<_>>();
-    let alignment_score = 0;
-
-    println!("Sequence length is: {}", SEQUENCE_BASE_PAIRS);
+    let (_, max_lengths, scoring_scheme) = circuit_header();
+    let c = identical_alignment(MAX_SEQUENCE_BASE_PAIRS, scoring_scheme, &max_lengths);
 
-    let c = AlignmentCircuit::<Fr>::new(reference_sequence_felts.clone(), reference_sequence_bases.clone(), target_sequence_felts.clone(), target_sequence_bases.clone(), cigar_string_felts.clone(), cigar_string_letters.clone(), alignment_score);
+    println!("Maximum sequence length is: {}", MAX_SEQUENCE_BASE_PAIRS);
+    println!("Scoring scheme is: {:?}, alignment score is: {}", scoring_scheme, c.alignment_score);
     {
         let mut layer = ConstraintLayer::default();
         layer.mode = TracingMode::OnlyConstraints;
         let subscriber = tracing_subscriber::Registry::default().with(layer);
         let _guard = tracing::subscriber::set_default(subscriber);
 
-        let mut circuit = c.clone();
-        let mut cs = ConstraintSystem::new_ref();
+        let circuit = c.clone();
+        let cs = ConstraintSystem::new_ref();
         circuit.generate_constraints(cs.clone()).unwrap();
         println!("Num constraints: {:?}", cs.num_constraints());
+        println!("Constraints per base: {:.2}", cs.num_constraints() as f64 / MAX_SEQUENCE_BASE_PAIRS as f64);
+        println!("Unpacking constraints per base: {:.2}", unpacking_constraints_per_base());
         // Let's check whether the constraint system is satisfied
         let is_satisfied = cs.is_satisfied().unwrap();
         if !is_satisfied {
@@ -283,6 +275,11 @@ fn main() {
         }
     }
 
+    // Every folded segment also verifies the proof of the segments before it.
+    let cs = ConstraintSystem::<ivc::StepField>::new_ref();
+    ivc::SegmentCircuit::blank(scoring_scheme, IVC_BLOCKS_PER_SEGMENT).generate_constraints(cs.clone()).unwrap();
+    println!("Constraints per folded segment of {} operations: {}", IVC_BLOCKS_PER_SEGMENT * CIGAR_OPS_PER_BLOCK, cs.num_constraints());
+
     let mut rng = ark_std::rand::rngs::StdRng::seed_from_u64(test_rng().next_u64());
 
     let start = ark_std::time::Instant::now();
@@ -293,6 +290,7 @@ fn main() {
         start.elapsed().as_secs_f64()
     );
 
+    let public_inputs = c.public_inputs();
     let start = ark_std::time::Instant::now();
     let proof = Groth16::<Bls12_381>::prove(&pk, c, &mut rng).unwrap();
     println!(
@@ -301,9 +299,33 @@ fn main() {
     );
 
     let start = ark_std::time::Instant::now();
-    assert!(Groth16::<Bls12_381>::verify_with_processed_vk(&pvk, &[Fr::zero()], &proof).unwrap());
+    assert!(Groth16::<Bls12_381>::verify_with_processed_vk(&pvk, &public_inputs, &proof).unwrap());
     println!(
         "verification time for BLS12-381: {} s",
         start.elapsed().as_secs_f64()
     );
+
+    // A shorter alignment reuses the same keys.
+    let short = identical_alignment(MAX_SEQUENCE_BASE_PAIRS / 3, scoring_scheme, &max_lengths);
+    let public_inputs = short.public_inputs();
+    let proof = Groth16::<Bls12_381>::prove(&pk, short, &mut rng).unwrap();
+    assert!(Groth16::<Bls12_381>::verify_with_processed_vk(&pvk, &public_inputs, &proof).unwrap());
+    println!("verified an alignment of {} bases with the same keys", MAX_SEQUENCE_BASE_PAIRS / 3);
+
+    // The same alignment with the transparent STARK backend, which needs no setup.
+    let c = identical_alignment(MAX_SEQUENCE_BASE_PAIRS, scoring_scheme, &max_lengths);
+    let start = ark_std::time::Instant::now();
+    let (statement, proof) = stark::prove_alignment(&c);
+    println!(
+        "proving time for the BabyBear STARK: {} s",
+        start.elapsed().as_secs_f64()
+    );
+    println!("STARK proof size: {} bytes", bincode::serialized_size(&proof).unwrap());
+
+    let start = ark_std::time::Instant::now();
+    stark::verify_alignment(&statement, &proof).unwrap();
+    println!(
+        "verification time for the BabyBear STARK: {} s",
+        start.elapsed().as_secs_f64()
+    );
 }
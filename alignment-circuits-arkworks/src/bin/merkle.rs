@@ -1,47 +1,144 @@
-// Gives a performance example for Merkle Tree-based succincct data structure with big leaves (section 4.2 in the report).
+// Commits FASTA genomes to a Merkle tree and opens selected positions of them, and gives a performance
+// example for Merkle Tree-based succincct data structure with big leaves (section 4.2 in the report).
 
-use itertools::Itertools;
-use p3_baby_bear::{BabyBear, DiffusionMatrixBabyBear};
+use alignment_circuits::genome_commitment::{
+    genome_mmcs, parse_snp_panel, CommittedGenome, GenomeCommitment, GenomeOpening, GenomeRegion, DEFAULT_LEAF_SIZE,
+};
+use alignment_circuits::leaf_property::{setup_leaf_property, LeafProperty, PropertyOpening};
+use alignment_circuits::sam::{encode_sequence, FastaReference};
+use ark_bls12_381::Bls12_381;
+use ark_groth16::{ProvingKey, VerifyingKey};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use p3_baby_bear::BabyBear;
 use p3_commit::Mmcs;
-use p3_field::{AbstractField, Field};
 use p3_matrix::dense::RowMajorMatrix;
-use p3_matrix::{Dimensions, Matrix};
-use p3_poseidon2::{Poseidon2, Poseidon2ExternalMatrixGeneral};
-use p3_symmetric::{
-    CryptographicHasher, PaddingFreeSponge, PseudoCompressionFunction, TruncatedPermutation,
-};
-use rand::thread_rng;
+use p3_matrix::Dimensions;
 
 use rand::{Rng, RngCore, SeedableRng};
 use rand_chacha::ChaCha20Rng;
-use std::time::Duration;
 use std::time::Instant;
 
-use p3_merkle_tree::FieldMerkleTreeMmcs;
-
-type F = BabyBear;
-
-type Perm = Poseidon2<F, Poseidon2ExternalMatrixGeneral, DiffusionMatrixBabyBear, 16, 7>;
-type MyHash = PaddingFreeSponge<Perm, 16, 8, 8>;
-type MyCompress = TruncatedPermutation<Perm, 2, 8, 16>;
-type MyMmcs =
-    FieldMerkleTreeMmcs<<F as Field>::Packing, <F as Field>::Packing, MyHash, MyCompress, 8>;
+const BASE_NAMES: [char; 6] = ['A', 'C', 'G', 'T', 'N', '-'];
 
 fn seeded_rng() -> impl Rng {
     ChaCha20Rng::seed_from_u64(18)
 }
 
+fn usage() -> ! {
+    eprintln!("usage: merkle [bench]");
+    eprintln!("       merkle commit <reference.fa> <commitment> <prover-data> [<leaf-size>]");
+    eprintln!("       merkle open <prover-data> <opening> <region>...");
+    eprintln!("       merkle open-panel <prover-data> <opening> <panel>");
+    eprintln!("       merkle verify <commitment> <opening>");
+    eprintln!("       merkle zk-setup <leaf-size> <proving-key> <verifying-key>");
+    eprintln!("       merkle open-zk <prover-data> <proving-key> <opening> <region> <allowed-bases>");
+    eprintln!("       merkle verify-zk <commitment> <verifying-key> <opening>");
+    std::process::exit(2)
+}
+
+fn exit_on_error<T, E: std::fmt::Display>(result: Result<T, E>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("error: {}", err);
+        std::process::exit(1)
+    })
+}
+
+fn commit(fasta_path: &str, commitment_path: &str, prover_data_path: &str, leaf_size: usize) {
+    let fasta = exit_on_error(FastaReference::read(fasta_path));
+    let start = Instant::now();
+    let genome = exit_on_error(CommittedGenome::commit(&fasta, leaf_size, &mut ChaCha20Rng::from_entropy()));
+    println!("Building merkle tree took: {:?}", start.elapsed());
+    exit_on_error(genome.commitment.save(commitment_path));
+    exit_on_error(genome.save(prover_data_path));
+    println!("committed {} sequences in {} leaves, root {:?}", genome.commitment.sequences.len(), genome.commitment.num_leaves, <[BabyBear; 8]>::from(genome.commitment.root));
+}
+
+fn open(prover_data_path: &str, opening_path: &str, regions: &[GenomeRegion]) {
+    let genome = exit_on_error(CommittedGenome::load(prover_data_path));
+    let opening = exit_on_error(genome.open(regions));
+    exit_on_error(opening.save(opening_path));
+    println!("wrote {} leaves covering {} regions to {}", opening.leaves.len(), regions.len(), opening_path);
+}
+
+fn verify(commitment_path: &str, opening_path: &str) {
+    let commitment = exit_on_error(GenomeCommitment::load(commitment_path));
+    let opening = exit_on_error(GenomeOpening::load(opening_path));
+    let bases = exit_on_error(commitment.verify(&opening));
+    for (region, bases) in opening.regions.iter().zip(bases) {
+        println!("{}\t{}", region, bases.into_iter().map(|base| BASE_NAMES.get(base).copied().unwrap_or('?')).collect::<String>());
+    }
+}
+
+// Keys for proving that the base at a position is one of a set, without revealing it.
+fn zk_setup(leaf_size: usize, proving_key_path: &str, verifying_key_path: &str) {
+    let property = LeafProperty::BaseIn { offset: 0, bases: Vec::new() };
+    let (pk, vk) = setup_leaf_property(leaf_size, &property, &mut ChaCha20Rng::from_entropy());
+    let mut pk_bytes = Vec::new();
+    pk.serialize_compressed(&mut pk_bytes).unwrap();
+    exit_on_error(std::fs::write(proving_key_path, pk_bytes));
+    let mut vk_bytes = Vec::new();
+    vk.serialize_compressed(&mut vk_bytes).unwrap();
+    exit_on_error(std::fs::write(verifying_key_path, vk_bytes));
+}
+
+fn open_zk(prover_data_path: &str, proving_key_path: &str, opening_path: &str, region: &str, allowed_bases: &str) {
+    let genome = exit_on_error(CommittedGenome::load(prover_data_path));
+    let pk_bytes = exit_on_error(std::fs::read(proving_key_path));
+    let pk = exit_on_error(ProvingKey::<Bls12_381>::deserialize_compressed_unchecked(pk_bytes.as_slice()));
+    let region = exit_on_error(GenomeRegion::parse(region));
+    let allowed_bases = exit_on_error(encode_sequence(allowed_bases.as_bytes()));
+    let (index, property) = exit_on_error(LeafProperty::base_in(&genome.commitment, &region, allowed_bases));
+    let opening = exit_on_error(genome.prove_property(&pk, index, property, &mut ChaCha20Rng::from_entropy()));
+    let mut writer = std::io::BufWriter::new(exit_on_error(std::fs::File::create(opening_path)));
+    exit_on_error(bincode::serialize_into(&mut writer, &opening));
+    println!("wrote a proof about leaf {} to {}", index, opening_path);
+}
+
+fn verify_zk(commitment_path: &str, verifying_key_path: &str, opening_path: &str) {
+    let commitment = exit_on_error(GenomeCommitment::load(commitment_path));
+    let vk_bytes = exit_on_error(std::fs::read(verifying_key_path));
+    let vk = exit_on_error(VerifyingKey::<Bls12_381>::deserialize_compressed(vk_bytes.as_slice()));
+    let opening: PropertyOpening = exit_on_error(bincode::deserialize(&exit_on_error(std::fs::read(opening_path))));
+    exit_on_error(commitment.verify_property(&vk, &opening));
+    println!("leaf {} has {:?}", opening.index, opening.property);
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[1..] {
+        [] | ["bench"] => bench(),
+        ["commit", fasta, commitment, prover_data] => commit(fasta, commitment, prover_data, DEFAULT_LEAF_SIZE),
+        ["commit", fasta, commitment, prover_data, leaf_size] => {
+            commit(fasta, commitment, prover_data, leaf_size.parse().unwrap_or_else(|_| usage()))
+        }
+        ["open", prover_data, opening, ref regions @ ..] if !regions.is_empty() => {
+            let regions: Vec<_> = regions.iter().map(|region| exit_on_error(GenomeRegion::parse(region))).collect();
+            open(prover_data, opening, &regions)
+        }
+        ["open-panel", prover_data, opening, panel] => {
+            let panel = exit_on_error(parse_snp_panel(&exit_on_error(std::fs::read_to_string(panel))));
+            for (id, region) in panel.iter() {
+                println!("{}\t{}", id, region);
+            }
+            open(prover_data, opening, &panel.into_iter().map(|(_, region)| region).collect::<Vec<_>>())
+        }
+        ["verify", commitment, opening] => verify(commitment, opening),
+        ["zk-setup", leaf_size, proving_key, verifying_key] => {
+            zk_setup(leaf_size.parse().unwrap_or_else(|_| usage()), proving_key, verifying_key)
+        }
+        ["open-zk", prover_data, proving_key, opening, region, allowed_bases] => {
+            open_zk(prover_data, proving_key, opening, region, allowed_bases)
+        }
+        ["verify-zk", commitment, verifying_key, opening] => verify_zk(commitment, verifying_key, opening),
+        _ => usage(),
+    }
+}
+
+fn bench() {
     // Setup the PCS with basic parameters
     let mut rng = seeded_rng();
-    let perm = Perm::new_from_rng_128(
-        Poseidon2ExternalMatrixGeneral,
-        DiffusionMatrixBabyBear::default(),
-        &mut rng,
-    );
-    let hash = MyHash::new(perm.clone());
-    let compress = MyCompress::new(perm);
-    let mmcs = MyMmcs::new(hash, compress);
+    let mmcs = genome_mmcs();
 
     // Large enough to fit human genome in the blocks.
     static NUM_BLOCKS: usize = 200_000_000usize.next_power_of_two();
@@ -51,9 +148,9 @@ fn main() {
 
     let data: Vec<u8> = (0..NUM_BLOCKS).map(|_| rng.next_u32() as u8).collect();
     // let image_copy = image.clone();
-    let mut data_as_felts: Vec<BabyBear> = data
+    let data_as_felts: Vec<BabyBear> = data
         .into_iter()
-        .map(|chunk| BabyBear::new((chunk as u32)))
+        .map(|chunk| BabyBear::new(chunk as u32))
         .collect();
 
     let data_matrix = RowMajorMatrix::new(data_as_felts, LEAF_SIZE);
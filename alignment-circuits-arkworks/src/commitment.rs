@@ -0,0 +1,151 @@
+// Salted Poseidon commitments to packed sequences, published as public inputs of the alignment circuits.
+// The packed blocks are hashed as a chain h_0 = H(salt), h_{i+1} = H(h_i, block_i), stopping after the
+// last block that holds a base, so that a commitment does not depend on the maximum length of the
+// circuit it is later used in.
+
+use ark_crypto_primitives::sponge::constraints::CryptographicSpongeVar;
+use ark_crypto_primitives::sponge::poseidon::constraints::PoseidonSpongeVar;
+use ark_crypto_primitives::sponge::poseidon::{PoseidonConfig, PoseidonSponge};
+use ark_crypto_primitives::sponge::{Absorb, CryptographicSponge};
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSystemRef, SynthesisError};
+
+use crate::alignment::{pack_bases, unpack_bases, UnpackedBase, BASES_PER_BLOCK};
+use crate::poseidon::poseidon_parameters;
+
+/// Commitment to a sequence of bases, as computed by the circuits.
+pub fn commit_bases<F: PrimeField + Absorb>(bases: &[usize], salt: F) -> F {
+    commit_blocks(&pack_bases::<F>(bases), salt)
+}
+
+/// Commitment to packed blocks that all hold at least one base.
+pub fn commit_blocks<F: PrimeField + Absorb>(blocks: &[F], salt: F) -> F {
+    commit_blocks_with(&poseidon_parameters(), blocks, salt)
+}
+
+/// As `commit_blocks`, with the parameters built once by the caller for committing many sequences.
+pub fn commit_blocks_with<F: PrimeField + Absorb>(params: &PoseidonConfig<F>, blocks: &[F], salt: F) -> F {
+    let mut sponge = PoseidonSponge::new(params);
+    sponge.absorb(&salt);
+    let mut commitment = sponge.squeeze_field_elements::<F>(1)[0];
+    for block in blocks {
+        let mut sponge = PoseidonSponge::new(params);
+        sponge.absorb(&vec![commitment, *block]);
+        commitment = sponge.squeeze_field_elements::<F>(1)[0];
+    }
+    commitment
+}
+
+/// Commitment to the blocks before the first block that is only padding. `block_is_padding[i]` tells
+/// whether block i starts with padding, and must be false then true at most once.
+pub fn commit_blocks_var<F: PrimeField>(
+    cs: ConstraintSystemRef<F>,
+    blocks: &[FpVar<F>],
+    block_is_padding: &[Boolean<F>],
+    salt: &FpVar<F>,
+) -> Result<FpVar<F>, SynthesisError> {
+    let params = poseidon_parameters();
+    let mut sponge = PoseidonSpongeVar::new(cs.clone(), &params);
+    sponge.absorb(salt)?;
+    let mut chain = sponge.squeeze_field_elements(1)?.remove(0);
+
+    // The chain stops at block i iff the previous block held bases and block i does not.
+    let mut commitment = FpVar::zero();
+    let mut previous_holds_bases = Boolean::TRUE;
+    for (block, is_padding) in blocks.iter().zip(block_is_padding) {
+        commitment += FpVar::from(previous_holds_bases.and(is_padding)?) * &chain;
+        let mut sponge = PoseidonSpongeVar::new(cs.clone(), &params);
+        sponge.absorb(&vec![chain, block.clone()])?;
+        chain = sponge.squeeze_field_elements(1)?.remove(0);
+        previous_holds_bases = is_padding.not();
+    }
+    commitment += FpVar::from(previous_holds_bases) * &chain;
+    Ok(commitment)
+}
+
+/// A sequence unpacked by `unpack_committed_sequence`.
+pub(crate) struct UnpackedSequence<F: PrimeField> {
+    pub bases: Vec<UnpackedBase<F>>,
+    pub length_one_hot: Vec<Boolean<F>>, // length_one_hot[t] iff the length is t
+    pub length: FpVar<F>,
+    pub commitment: FpVar<F>,
+}
+
+/// Unpacks a padded sequence of `length` bases, enforcing that exactly the slots from its length on
+/// are padding, and commits to it.
+pub(crate) fn unpack_committed_sequence<F: PrimeField>(
+    cs: ConstraintSystemRef<F>,
+    blocks: &[FpVar<F>],
+    length: usize,
+    salt: F,
+) -> Result<UnpackedSequence<F>, SynthesisError> {
+    let mut bases = Vec::new();
+    for block in blocks {
+        bases.extend(unpack_bases(block)?);
+    }
+
+    // One-hot encoding of the length over 0..=capacity, its prefix sums mark the padding.
+    let mut length_one_hot = Vec::new();
+    for t in 0..=bases.len() {
+        length_one_hot.push(Boolean::new_witness(cs.clone(), || Ok(t == length))?);
+    }
+    let mut one_hot_sum = FpVar::zero();
+    let mut one_hot_value = FpVar::zero();
+    for (t, bit) in length_one_hot.iter().enumerate() {
+        one_hot_sum += FpVar::from(bit.clone());
+        one_hot_value += FpVar::from(bit.clone()) * F::from(t as u64);
+    }
+    one_hot_sum.enforce_equal(&FpVar::one())?;
+    let mut past_end = FpVar::zero();
+    for (base, bit) in bases.iter().zip(length_one_hot.iter()) {
+        past_end += FpVar::from(bit.clone());
+        FpVar::from(base.is_padding.clone()).enforce_equal(&past_end)?;
+    }
+
+    let salt_var = FpVar::new_witness(cs.clone(), || Ok(salt))?;
+    let block_is_padding: Vec<_> = bases.iter().step_by(BASES_PER_BLOCK).map(|base| base.is_padding.clone()).collect();
+    let commitment = commit_blocks_var(cs, blocks, &block_is_padding, &salt_var)?;
+    Ok(UnpackedSequence {
+        bases,
+        length_one_hot,
+        length: one_hot_value,
+        commitment,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::{AlignmentCircuit, MaxLengths, ScoringScheme};
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+    use ark_std::UniformRand;
+
+    #[test]
+    fn commitments_do_not_depend_on_padding() {
+        let rng = &mut ark_std::test_rng();
+        let (reference_salt, target_salt) = (Fr::rand(rng), Fr::rand(rng));
+        let reference: Vec<usize> = (0..100).map(|i| i % 4).collect();
+        let cigar = vec![0; 100];
+        let scheme = ScoringScheme::bwa_mem();
+        let score = scheme.score(&reference, &reference, &cigar);
+
+        let mut inputs = Vec::new();
+        for max_length in [100, 300] {
+            let max_lengths = MaxLengths { reference: max_length, target: max_length, cigar: max_length };
+            let circuit = AlignmentCircuit::<Fr>::padded(reference.clone(), reference.clone(), cigar.clone(), scheme, score, &max_lengths)
+                .with_salts(reference_salt, target_salt);
+            inputs.push(circuit.public_inputs());
+            let cs = ConstraintSystem::new_ref();
+            circuit.generate_constraints(cs.clone()).unwrap();
+            assert!(cs.is_satisfied().unwrap());
+            assert_eq!(cs.borrow().unwrap().instance_assignment[1..], inputs.last().unwrap()[..]);
+        }
+        assert_eq!(inputs[0], inputs[1]);
+        assert_eq!(inputs[0][16], commit_bases(&reference, reference_salt));
+        assert_eq!(inputs[0][17], commit_bases(&reference, target_salt));
+        assert_ne!(inputs[0][16], inputs[0][17]);
+    }
+}
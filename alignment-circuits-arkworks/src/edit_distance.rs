@@ -0,0 +1,285 @@
+// Proves the optimal banded edit distance between two committed sequences, rather than the score of a
+// claimed alignment. The circuit fills in the dynamic programming matrix D over the cells with
+// |i - j| <= band, where D(i, j) is the edit distance of the first i reference and j target bases
+// using only cells of the band. N never matches, as in AlignmentCircuit.
+//
+// Within the band neighbouring cells differ by at most one and D(i, j) - D(i - 1, j - 1) is 0 or 1, by
+// induction over the recurrence. Relative to the diagonal predecessor every candidate of the minimum
+// is thus 0, 1 or 2, so the minimum is the predecessor plus one unless some candidate is 0, which only
+// takes equality checks.
+
+use ark_crypto_primitives::sponge::Absorb;
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+
+use crate::aligner::{Aligner, Objective};
+use crate::alignment::{pack_bases_to, usize_to_felt, ScoringScheme, BASES_PER_BLOCK, BASE_N};
+use crate::commitment::{commit_bases, unpack_committed_sequence, UnpackedSequence};
+
+#[derive(Clone)]
+pub struct EditDistanceCircuit<F: PrimeField> {
+    pub reference_sequence_felts: Vec<F>, // packed and padded to the maximum reference length
+    pub reference_sequence_bases: Vec<usize>,
+    pub target_sequence_felts: Vec<F>,
+    pub target_sequence_bases: Vec<usize>,
+    pub band: usize, // fixed at setup time, as are the maximum lengths
+    pub distance: usize, // banded edit distance, a public input unless there is a threshold
+    pub threshold: Option<usize>, // if set, only distance <= threshold is public
+    pub reference_salt: F,
+    pub target_salt: F,
+}
+
+/// Banded edit distance as computed by the circuit, or None if the lengths differ by more than `band`.
+pub fn banded_edit_distance(reference: &[usize], target: &[usize], band: usize) -> Option<usize> {
+    let aligner = Aligner::new(ScoringScheme::edit_distance(), Objective::Minimize).banded(band);
+    aligner.align(reference, target).map(|alignment| alignment.score as usize)
+}
+
+impl<F: PrimeField> EditDistanceCircuit<F> {
+    /// Computes the distance of the sequences within `band`, or None if their lengths differ by more.
+    pub fn new(
+        reference_sequence_bases: Vec<usize>,
+        target_sequence_bases: Vec<usize>,
+        band: usize,
+        max_reference_length: usize,
+        max_target_length: usize,
+    ) -> Option<Self> {
+        assert!(reference_sequence_bases.len() <= max_reference_length, "reference is longer than the circuit allows");
+        assert!(target_sequence_bases.len() <= max_target_length, "target is longer than the circuit allows");
+        let distance = banded_edit_distance(&reference_sequence_bases, &target_sequence_bases, band)?;
+        Some(Self {
+            reference_sequence_felts: pack_bases_to(&reference_sequence_bases, max_reference_length),
+            reference_sequence_bases,
+            target_sequence_felts: pack_bases_to(&target_sequence_bases, max_target_length),
+            target_sequence_bases,
+            band,
+            distance,
+            threshold: None,
+            reference_salt: F::zero(),
+            target_salt: F::zero(),
+        })
+    }
+
+    /// Only proves that the distance is at most `threshold`, keeping the distance itself private. The
+    /// threshold must not exceed the sum of the maximum lengths, which bounds every distance anyway.
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        assert!(
+            threshold <= self.max_reference_length() + self.max_target_length(),
+            "threshold exceeds the sum of the maximum lengths"
+        );
+        self.threshold = Some(threshold);
+        self
+    }
+
+    pub fn with_salts(mut self, reference_salt: F, target_salt: F) -> Self {
+        self.reference_salt = reference_salt;
+        self.target_salt = target_salt;
+        self
+    }
+
+    fn max_reference_length(&self) -> usize {
+        self.reference_sequence_felts.len() * BASES_PER_BLOCK
+    }
+
+    fn max_target_length(&self) -> usize {
+        self.target_sequence_felts.len() * BASES_PER_BLOCK
+    }
+
+    // Enough bits for any difference of two distances.
+    pub(crate) fn distance_bits(&self) -> usize {
+        (usize::BITS - (self.max_reference_length() + self.max_target_length()).leading_zeros()) as usize
+    }
+}
+
+impl<F: PrimeField + Absorb> EditDistanceCircuit<F> {
+    /// Public inputs in the order the circuit allocates them.
+    pub fn public_inputs(&self) -> Vec<F> {
+        vec![
+            commit_bases(&self.reference_sequence_bases, self.reference_salt),
+            commit_bases(&self.target_sequence_bases, self.target_salt),
+            F::from(self.reference_sequence_bases.len() as u64),
+            F::from(self.target_sequence_bases.len() as u64),
+            F::from(self.threshold.unwrap_or(self.distance) as u64),
+        ]
+    }
+}
+
+impl<F: PrimeField> ConstraintSynthesizer<F> for EditDistanceCircuit<F> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+        let mut reference_sequence_vars = Vec::new();
+        for elem in self.reference_sequence_felts.iter() {
+            reference_sequence_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+
+        let mut target_sequence_vars = Vec::new();
+        for elem in self.target_sequence_felts.iter() {
+            target_sequence_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+
+        let reference = unpack_committed_sequence(cs.clone(), &reference_sequence_vars, self.reference_sequence_bases.len(), self.reference_salt)?;
+        let target = unpack_committed_sequence(cs.clone(), &target_sequence_vars, self.target_sequence_bases.len(), self.target_salt)?;
+
+        for public in [&reference.commitment, &target.commitment, &reference.length, &target.length] {
+            FpVar::new_input(cs.clone(), || public.value())?.enforce_equal(public)?;
+        }
+        let claim_var = FpVar::new_input(cs.clone(), || Ok(F::from(self.threshold.unwrap_or(self.distance) as u64)))?;
+        let distance = enforce_banded_edit_distance(&reference, &target, self.band)?;
+
+        match self.threshold {
+            None => distance.enforce_equal(&claim_var)?,
+            Some(threshold) => enforce_within(cs, &distance, &claim_var, threshold.wrapping_sub(self.distance), self.distance_bits())?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Enforces the recurrence over the band and returns D(reference length, target length), failing if
+/// the lengths differ by more than `band`.
+pub(crate) fn enforce_banded_edit_distance<F: PrimeField>(
+    reference: &UnpackedSequence<F>,
+    target: &UnpackedSequence<F>,
+    band: usize,
+) -> Result<FpVar<F>, SynthesisError> {
+    let reference_bases: Vec<_> = reference.bases.iter().map(|base| base.value.clone()).collect();
+    let target_bases: Vec<_> = target.bases.iter().map(|base| base.value.clone()).collect();
+
+    // Row i holds D(i, j) for j in columns(i).
+    let max_target_length = target_bases.len();
+    let columns = |i: usize| i.saturating_sub(band)..(i + band).min(max_target_length) + 1;
+    let minus_one = FpVar::constant(-F::one());
+    let base_n = FpVar::constant(usize_to_felt::<F>(BASE_N));
+    let mut rows: Vec<Vec<FpVar<F>>> = Vec::new();
+    let mut distance = FpVar::zero();
+    let mut end_in_band = FpVar::zero();
+    for i in 0..=reference_bases.len() {
+        let mut row: Vec<FpVar<F>> = Vec::new();
+        for j in columns(i) {
+            let cell = if i == 0 || j == 0 {
+                FpVar::constant(F::from((i + j) as u64))
+            } else {
+                let diagonal = &rows[i - 1][j - 1 - columns(i - 1).start];
+                let is_match = reference_bases[i - 1].is_eq(&target_bases[j - 1])?.and(&reference_bases[i - 1].is_neq(&base_n)?)?;
+                let mut reaches_diagonal = vec![is_match];
+                if columns(i - 1).contains(&j) {
+                    let up = &rows[i - 1][j - columns(i - 1).start];
+                    reaches_diagonal.push((up - diagonal).is_eq(&minus_one)?);
+                }
+                if j > columns(i).start {
+                    let left = &row[j - 1 - columns(i).start];
+                    reaches_diagonal.push((left - diagonal).is_eq(&minus_one)?);
+                }
+                diagonal + FpVar::one() - FpVar::from(Boolean::kary_or(&reaches_diagonal)?)
+            };
+            // Pick out D(reference length, target length).
+            if let (Some(at_reference_end), Some(at_target_end)) = (reference.length_one_hot.get(i), target.length_one_hot.get(j)) {
+                let is_end = FpVar::from(at_reference_end.and(at_target_end)?);
+                distance += &is_end * &cell;
+                end_in_band += is_end;
+            }
+            row.push(cell);
+        }
+        rows.push(row);
+    }
+    // Lengths that differ by more than the band have no distance.
+    end_in_band.enforce_equal(&FpVar::one())?;
+    Ok(distance)
+}
+
+/// Enforces distance <= threshold, given the native `slack` threshold - distance, by decomposing the
+/// slack into `num_bits` bits.
+pub(crate) fn enforce_within<F: PrimeField>(
+    cs: ConstraintSystemRef<F>,
+    distance: &FpVar<F>,
+    threshold: &FpVar<F>,
+    slack: usize,
+    num_bits: usize,
+) -> Result<(), SynthesisError> {
+    let mut slack_bits = Vec::new();
+    for k in 0..num_bits {
+        slack_bits.push(Boolean::new_witness(cs.clone(), || Ok((slack >> k) & 1 == 1))?);
+    }
+    Boolean::le_bits_to_fp_var(&slack_bits)?.enforce_equal(&(threshold - distance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::{BASE_A, BASE_T};
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::rand::Rng;
+
+    fn is_satisfied(circuit: EditDistanceCircuit<Fr>) -> bool {
+        let cs = ConstraintSystem::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn proves_the_banded_edit_distance() {
+        let rng = &mut ark_std::test_rng();
+        let mut shapes = Vec::new();
+        for (reference_length, target_length) in [(30, 30), (40, 37), (25, 29), (0, 3)] {
+            let reference: Vec<usize> = (0..reference_length).map(|_| rng.gen_range(BASE_A..=BASE_N)).collect();
+            let mut target = reference.clone();
+            target.resize(target_length, BASE_T);
+            for _ in 0..3 {
+                if !target.is_empty() {
+                    let k = rng.gen_range(0..target.len());
+                    target[k] = rng.gen_range(BASE_A..=BASE_T);
+                }
+            }
+            let circuit = EditDistanceCircuit::<Fr>::new(reference, target, 5, 50, 50).unwrap();
+            let cs = ConstraintSystem::new_ref();
+            circuit.clone().generate_constraints(cs.clone()).unwrap();
+            assert!(cs.is_satisfied().unwrap());
+            assert_eq!(cs.borrow().unwrap().instance_assignment[1..], circuit.public_inputs()[..]);
+            shapes.push(cs.num_constraints());
+
+            // Any other distance, including a larger one, is rejected.
+            for claimed in [circuit.distance + 1, circuit.distance.wrapping_sub(1)] {
+                let mut wrong = circuit.clone();
+                wrong.distance = claimed;
+                assert!(!is_satisfied(wrong));
+            }
+
+            let distance = circuit.distance;
+            assert!(is_satisfied(circuit.clone().with_threshold(distance)));
+            assert!(is_satisfied(circuit.clone().with_threshold(distance + 4)));
+            if distance > 0 {
+                let mut below = circuit.with_threshold(distance - 1);
+                below.distance = distance - 1;
+                assert!(!is_satisfied(below));
+            }
+        }
+        assert!(shapes.iter().all(|&shape| shape == shapes[0]));
+    }
+
+    #[test]
+    fn accepts_thresholds_up_to_the_sum_of_the_maximum_lengths() {
+        let reference = crate::sam::encode_sequence(b"ACGTACGT").unwrap();
+        let target = crate::sam::encode_sequence(b"ACGAACGT").unwrap();
+        let circuit = EditDistanceCircuit::<Fr>::new(reference, target, 2, 84, 84).unwrap();
+        assert!(is_satisfied(circuit.clone().with_threshold(2 * 84)));
+    }
+
+    #[test]
+    #[should_panic(expected = "threshold exceeds the sum of the maximum lengths")]
+    fn rejects_thresholds_above_the_sum_of_the_maximum_lengths() {
+        let reference = crate::sam::encode_sequence(b"ACGTACGT").unwrap();
+        EditDistanceCircuit::<Fr>::new(reference.clone(), reference, 2, 84, 84).unwrap().with_threshold(2 * 84 + 1);
+    }
+
+    #[test]
+    fn matches_unbanded_distance_when_the_band_is_wide() {
+        let reference = crate::sam::encode_sequence(b"ACGTNACGTTAGC").unwrap();
+        let target = crate::sam::encode_sequence(b"AGTNACCGTAGCA").unwrap();
+        let unbanded = Aligner::new(ScoringScheme::edit_distance(), Objective::Minimize).align(&reference, &target).unwrap();
+        assert_eq!(banded_edit_distance(&reference, &target, 13), Some(unbanded.score as usize));
+        assert!(banded_edit_distance(&reference, &target, 1).unwrap() >= unbanded.score as usize);
+        assert_eq!(banded_edit_distance(&reference, &target[..10], 2), None);
+    }
+}
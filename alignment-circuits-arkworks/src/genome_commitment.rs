@@ -0,0 +1,463 @@
+// Commits a whole FASTA genome to a FieldMerkleTreeMmcs over BabyBear and discloses selected positions
+// from it. Bases are encoded as in the alignment circuits, with the sequences laid end to end in file
+// order and cut into leaves of `leaf_size` bases. Each leaf is hidden behind its own salted Poseidon
+// commitment over BLS12-381, as computed by `commitment::commit_bases`, and the Merkle tree is built
+// over these commitments split into BabyBear limbs. The published commitment is the root together with
+// the layout.
+//
+// As every leaf has a fresh salt, the sibling digests of an opening say nothing about the bases of
+// other leaves. An opening still reveals every base of the leaves that a requested region touches; the
+// verifier only returns the requested ones. `leaf_property` proves properties of a leaf instead.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use p3_baby_bear::{BabyBear, DiffusionMatrixBabyBear};
+use p3_commit::Mmcs;
+use ark_bls12_381::Fr;
+use ark_ff::{BigInt, BigInteger, PrimeField, UniformRand};
+use p3_field::{AbstractField, Field};
+use p3_matrix::dense::RowMajorMatrix;
+use p3_matrix::Dimensions;
+use p3_merkle_tree::FieldMerkleTreeMmcs;
+use p3_poseidon2::{Poseidon2, Poseidon2ExternalMatrixGeneral};
+use p3_symmetric::{PaddingFreeSponge, TruncatedPermutation};
+use rand::{CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::alignment::{pack_bases, BASE_N};
+use crate::commitment::commit_blocks_with;
+use crate::poseidon::poseidon_parameters;
+use crate::sam::{encode_sequence, FastaReference, SamError};
+
+pub type Val = BabyBear;
+pub type Perm = Poseidon2<Val, Poseidon2ExternalMatrixGeneral, DiffusionMatrixBabyBear, 16, 7>;
+pub type GenomeHash = PaddingFreeSponge<Perm, 16, 8, 8>;
+pub type GenomeCompress = TruncatedPermutation<Perm, 2, 8, 16>;
+pub type GenomeMmcs = FieldMerkleTreeMmcs<<Val as Field>::Packing, <Val as Field>::Packing, GenomeHash, GenomeCompress, 8>;
+pub type GenomeRoot = <GenomeMmcs as Mmcs<Val>>::Commitment;
+pub type GenomeTree = <GenomeMmcs as Mmcs<Val>>::ProverData<RowMajorMatrix<Val>>;
+
+// The Poseidon2 round constants are derived from this seed, so that the prover and verifier agree.
+const PERMUTATION_SEED: u64 = 18;
+
+pub const DEFAULT_LEAF_SIZE: usize = 1 << 7;
+
+// Leaf commitments are split into 30-bit limbs, which are below the BabyBear modulus.
+const LIMB_BITS: usize = 30;
+pub const LEAF_COMMITMENT_LIMBS: usize = 9;
+
+/// The Poseidon2 permutation shared by prover and verifier.
+pub fn genome_permutation() -> Perm {
+    let mut rng = ChaCha20Rng::seed_from_u64(PERMUTATION_SEED);
+    Perm::new_from_rng_128(Poseidon2ExternalMatrixGeneral, DiffusionMatrixBabyBear::default(), &mut rng)
+}
+
+/// The Merkle tree commitment scheme shared by prover and verifier.
+pub fn genome_mmcs() -> GenomeMmcs {
+    let perm = genome_permutation();
+    GenomeMmcs::new(GenomeHash::new(perm.clone()), GenomeCompress::new(perm))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GenomeError {
+    Fasta(SamError),
+    EmptyGenome,
+    MalformedRegion(String),
+    UnknownChromosome(String),
+    RegionOutOfBounds { region: String, length: usize },
+    MissingLeaf(usize),
+    InvalidOpening(String),
+    Serialization(String),
+    Io(String),
+}
+
+impl fmt::Display for GenomeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenomeError::Fasta(err) => write!(f, "{}", err),
+            GenomeError::EmptyGenome => write!(f, "the FASTA file holds no bases"),
+            GenomeError::MalformedRegion(region) => write!(f, "malformed region {}, expected CHROM:POS or CHROM:START-END", region),
+            GenomeError::UnknownChromosome(name) => write!(f, "sequence {} is not in the committed genome", name),
+            GenomeError::RegionOutOfBounds { region, length } => {
+                write!(f, "region {} is outside its sequence of {} bases", region, length)
+            }
+            GenomeError::MissingLeaf(index) => write!(f, "opening does not include leaf {}", index),
+            GenomeError::InvalidOpening(reason) => write!(f, "opening does not match the commitment: {}", reason),
+            GenomeError::Serialization(reason) => write!(f, "could not (de)serialize: {}", reason),
+            GenomeError::Io(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for GenomeError {}
+
+impl From<SamError> for GenomeError {
+    fn from(err: SamError) -> Self {
+        GenomeError::Fasta(err)
+    }
+}
+
+impl From<std::io::Error> for GenomeError {
+    fn from(err: std::io::Error) -> Self {
+        GenomeError::Io(err.to_string())
+    }
+}
+
+impl From<bincode::Error> for GenomeError {
+    fn from(err: bincode::Error) -> Self {
+        GenomeError::Serialization(err.to_string())
+    }
+}
+
+fn save_bincode<T: Serialize>(path: impl AsRef<Path>, value: &T) -> Result<(), GenomeError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    bincode::serialize_into(&mut writer, value)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn load_bincode<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T, GenomeError> {
+    Ok(bincode::deserialize_from(BufReader::new(File::open(path)?))?)
+}
+
+/// A 1-based, inclusive range of positions on one sequence, as in `chr1:100-200`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenomeRegion {
+    pub chrom: String,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl GenomeRegion {
+    pub fn position(chrom: &str, pos: usize) -> Self {
+        Self { chrom: chrom.to_string(), start: pos, end: pos }
+    }
+
+    /// Parses `CHROM:POS` or `CHROM:START-END`.
+    pub fn parse(region: &str) -> Result<Self, GenomeError> {
+        let malformed = || GenomeError::MalformedRegion(region.to_string());
+        let (chrom, range) = region.rsplit_once(':').ok_or_else(malformed)?;
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        let start: usize = start.replace(',', "").parse().map_err(|_| malformed())?;
+        let end: usize = end.replace(',', "").parse().map_err(|_| malformed())?;
+        if chrom.is_empty() || start == 0 || end < start {
+            return Err(malformed());
+        }
+        Ok(Self { chrom: chrom.to_string(), start, end })
+    }
+}
+
+impl fmt::Display for GenomeRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}:{}", self.chrom, self.start)
+        } else {
+            write!(f, "{}:{}-{}", self.chrom, self.start, self.end)
+        }
+    }
+}
+
+/// Parses a SNP panel with one `ID CHROM POS` line per variant, such as an rsID list, separated by
+/// tabs or spaces. Empty lines and lines starting with `#` are skipped.
+pub fn parse_snp_panel(text: &str) -> Result<Vec<(String, GenomeRegion)>, GenomeError> {
+    let mut panel = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [id, chrom, pos] = fields[..] else {
+            return Err(GenomeError::MalformedRegion(line.to_string()));
+        };
+        let pos = pos.parse().map_err(|_| GenomeError::MalformedRegion(line.to_string()))?;
+        if pos == 0 {
+            return Err(GenomeError::MalformedRegion(line.to_string()));
+        }
+        panel.push((id.to_string(), GenomeRegion::position(chrom, pos)));
+    }
+    Ok(panel)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommittedSequence {
+    pub name: String,
+    pub offset: usize, // of its first base in the concatenated genome
+    pub length: usize,
+}
+
+/// The published commitment: the Merkle root and how the genome is laid out under it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenomeCommitment {
+    pub root: GenomeRoot,
+    pub leaf_size: usize,
+    pub num_leaves: usize,
+    pub sequences: Vec<CommittedSequence>,
+}
+
+/// Splits a leaf commitment into the BabyBear limbs that the Merkle tree hashes.
+pub fn leaf_commitment_limbs(commitment: Fr) -> Vec<Val> {
+    let bits = commitment.into_bigint().to_bits_le();
+    bits.chunks(LIMB_BITS)
+        .map(|limb| Val::from_canonical_u32(limb.iter().rev().fold(0, |value, &bit| 2 * value + bit as u32)))
+        .collect()
+}
+
+pub fn salt_to_words(salt: Fr) -> [u64; 4] {
+    salt.into_bigint().0
+}
+
+pub fn salt_from_words(words: [u64; 4]) -> Option<Fr> {
+    Fr::from_bigint(BigInt::new(words))
+}
+
+/// One opened leaf, the salt of its commitment and its authentication path.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LeafOpening {
+    pub index: usize,
+    pub bases: Vec<usize>,
+    pub salt: [u64; 4],
+    pub siblings: Vec<[Val; 8]>,
+}
+
+/// The leaves covering a set of regions, each opened once however many regions it covers.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenomeOpening {
+    pub regions: Vec<GenomeRegion>,
+    pub leaves: Vec<LeafOpening>,
+}
+
+impl GenomeOpening {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), GenomeError> {
+        save_bincode(path, self)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, GenomeError> {
+        load_bincode(path)
+    }
+}
+
+impl GenomeCommitment {
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), GenomeError> {
+        save_bincode(path, self)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, GenomeError> {
+        load_bincode(path)
+    }
+
+    fn genome_length(&self) -> usize {
+        self.sequences.iter().map(|sequence| sequence.length).sum()
+    }
+
+    /// Number of bases in leaf `index`, which is only short for the last leaf.
+    pub fn leaf_length(&self, index: usize) -> usize {
+        self.leaf_size.min(self.genome_length().saturating_sub(index * self.leaf_size))
+    }
+
+    /// Positions of the region in the concatenated genome.
+    fn locate(&self, region: &GenomeRegion) -> Result<std::ops::Range<usize>, GenomeError> {
+        let sequence = self
+            .sequences
+            .iter()
+            .find(|sequence| sequence.name == region.chrom)
+            .ok_or_else(|| GenomeError::UnknownChromosome(region.chrom.clone()))?;
+        if region.start == 0 || region.end < region.start || region.end > sequence.length {
+            return Err(GenomeError::RegionOutOfBounds { region: region.to_string(), length: sequence.length });
+        }
+        Ok(sequence.offset + region.start - 1..sequence.offset + region.end)
+    }
+
+    /// The leaf holding the first position of `region`, and the offset of that position in it.
+    pub fn locate_in_leaf(&self, region: &GenomeRegion) -> Result<(usize, usize), GenomeError> {
+        let position = self.locate(region)?.start;
+        Ok((position / self.leaf_size, position % self.leaf_size))
+    }
+
+    /// Checks that `commitment` is the commitment of leaf `index` under the root.
+    pub fn verify_leaf_commitment(&self, index: usize, commitment: Fr, siblings: &[[Val; 8]]) -> Result<(), GenomeError> {
+        if index >= self.num_leaves {
+            return Err(GenomeError::InvalidOpening(format!("leaf {} is past the last leaf", index)));
+        }
+        let dimensions = [Dimensions { width: LEAF_COMMITMENT_LIMBS, height: self.num_leaves }];
+        genome_mmcs()
+            .verify_batch(&self.root, &dimensions, index, &[leaf_commitment_limbs(commitment)], &siblings.to_vec())
+            .map_err(|err| GenomeError::InvalidOpening(format!("leaf {}: {:?}", index, err)))
+    }
+
+    /// Checks every opened leaf against the root and returns the bases of each region, in order.
+    pub fn verify(&self, opening: &GenomeOpening) -> Result<Vec<Vec<usize>>, GenomeError> {
+        let params = poseidon_parameters();
+        let mut leaves = BTreeMap::new();
+        for leaf in &opening.leaves {
+            if leaf.bases.len() != self.leaf_length(leaf.index) || leaf.bases.iter().any(|&base| base > BASE_N) {
+                return Err(GenomeError::InvalidOpening(format!("leaf {} does not hold {} bases", leaf.index, self.leaf_length(leaf.index))));
+            }
+            let salt = salt_from_words(leaf.salt).ok_or_else(|| GenomeError::InvalidOpening(format!("salt of leaf {} is not a field element", leaf.index)))?;
+            let commitment = commit_blocks_with(&params, &pack_bases::<Fr>(&leaf.bases), salt);
+            self.verify_leaf_commitment(leaf.index, commitment, &leaf.siblings)?;
+            leaves.insert(leaf.index, &leaf.bases);
+        }
+
+        let mut regions = Vec::new();
+        for region in &opening.regions {
+            let mut bases = Vec::new();
+            for position in self.locate(region)? {
+                let leaf = leaves.get(&(position / self.leaf_size)).ok_or(GenomeError::MissingLeaf(position / self.leaf_size))?;
+                bases.push(leaf[position % self.leaf_size]);
+            }
+            regions.push(bases);
+        }
+        Ok(regions)
+    }
+}
+
+/// A committed genome together with the Merkle tree, bases and salts needed to open it.
+#[derive(Serialize, Deserialize)]
+pub struct CommittedGenome {
+    pub commitment: GenomeCommitment,
+    tree: GenomeTree,
+    bases: Vec<u8>,
+    salts: Vec<[u64; 4]>,
+}
+
+impl CommittedGenome {
+    /// Commits to every sequence of `fasta` in file order, salting each leaf with randomness from `rng`.
+    pub fn commit<R: RngCore + CryptoRng>(fasta: &FastaReference, leaf_size: usize, rng: &mut R) -> Result<Self, GenomeError> {
+        assert!(leaf_size > 0, "leaves must hold at least one base");
+        let mut bases = Vec::new();
+        let mut sequences = Vec::new();
+        for name in fasta.names() {
+            let sequence = encode_sequence(fasta.sequence(name).unwrap_or_default())?;
+            sequences.push(CommittedSequence { name: name.clone(), offset: bases.len(), length: sequence.len() });
+            bases.extend(sequence.into_iter().map(|base| base as u8));
+        }
+        if bases.is_empty() {
+            return Err(GenomeError::EmptyGenome);
+        }
+
+        let params = poseidon_parameters();
+        let mut salts = Vec::new();
+        let mut limbs = Vec::new();
+        for leaf in bases.chunks(leaf_size) {
+            let salt = Fr::rand(rng);
+            let leaf: Vec<usize> = leaf.iter().map(|&base| base as usize).collect();
+            limbs.extend(leaf_commitment_limbs(commit_blocks_with(&params, &pack_bases::<Fr>(&leaf), salt)));
+            salts.push(salt_to_words(salt));
+        }
+        let num_leaves = salts.len();
+        let (root, tree) = genome_mmcs().commit(vec![RowMajorMatrix::new(limbs, LEAF_COMMITMENT_LIMBS)]);
+        Ok(Self {
+            commitment: GenomeCommitment { root, leaf_size, num_leaves, sequences },
+            tree,
+            bases,
+            salts,
+        })
+    }
+
+    /// The bases of leaf `index` and the salt of its commitment.
+    pub fn leaf(&self, index: usize) -> (Vec<usize>, Fr) {
+        let start = index * self.commitment.leaf_size;
+        let end = (start + self.commitment.leaf_size).min(self.bases.len());
+        let bases = self.bases[start..end].iter().map(|&base| base as usize).collect();
+        (bases, salt_from_words(self.salts[index]).expect("salts are written as field elements"))
+    }
+
+    /// The authentication path of leaf `index`.
+    pub fn siblings(&self, index: usize) -> Vec<[Val; 8]> {
+        genome_mmcs().open_batch(index, &self.tree).1
+    }
+
+    /// Opens every leaf that `regions` touch.
+    pub fn open(&self, regions: &[GenomeRegion]) -> Result<GenomeOpening, GenomeError> {
+        let mut indices = BTreeSet::new();
+        for region in regions {
+            let range = self.commitment.locate(region)?;
+            indices.extend(range.start / self.commitment.leaf_size..=(range.end - 1) / self.commitment.leaf_size);
+        }
+        let leaves = indices
+            .into_iter()
+            .map(|index| {
+                let (bases, salt) = self.leaf(index);
+                LeafOpening { index, bases, salt: salt_to_words(salt), siblings: self.siblings(index) }
+            })
+            .collect();
+        Ok(GenomeOpening { regions: regions.to_vec(), leaves })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), GenomeError> {
+        save_bincode(path, self)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, GenomeError> {
+        load_bincode(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::{BASE_A, BASE_C, BASE_G, BASE_N, BASE_T};
+    use rand::rngs::StdRng;
+
+    const FASTA: &str = ">chr1 first\nACGTACGTAC\nGTNNACGT\n>chr2\nTTTTGGGGCCCCAAAA\nACG\n";
+
+    #[test]
+    fn parses_regions_and_panels() {
+        assert_eq!(GenomeRegion::parse("chr1:1,000-2,000").unwrap(), GenomeRegion { chrom: "chr1".to_string(), start: 1000, end: 2000 });
+        assert_eq!(GenomeRegion::parse("HLA-A*01:01:5").unwrap(), GenomeRegion::position("HLA-A*01:01", 5));
+        for malformed in ["chr1", "chr1:0", "chr1:5-4", ":5", "chr1:x"] {
+            assert!(matches!(GenomeRegion::parse(malformed), Err(GenomeError::MalformedRegion(_))), "{}", malformed);
+        }
+        let panel = parse_snp_panel("# id chrom pos\nrs1\tchr1\t3\n\nrs2 chr2 17\n").unwrap();
+        assert_eq!(panel, vec![("rs1".to_string(), GenomeRegion::position("chr1", 3)), ("rs2".to_string(), GenomeRegion::position("chr2", 17))]);
+        assert!(parse_snp_panel("rs1 chr1").is_err());
+    }
+
+    #[test]
+    fn opens_and_verifies_regions() {
+        let rng = &mut StdRng::seed_from_u64(14);
+        let fasta = FastaReference::parse(FASTA).unwrap();
+        let genome = CommittedGenome::commit(&fasta, 4, rng).unwrap();
+        assert_eq!(genome.commitment.num_leaves, 10);
+        assert_eq!(genome.commitment.sequences[1], CommittedSequence { name: "chr2".to_string(), offset: 18, length: 19 });
+
+        // The first two regions share leaf 3, which is opened once.
+        let regions = vec![GenomeRegion::parse("chr1:11-13").unwrap(), GenomeRegion::position("chr1", 15), GenomeRegion::parse("chr2:17-19").unwrap()];
+        let opening = genome.open(&regions).unwrap();
+        assert_eq!(opening.leaves.iter().map(|leaf| leaf.index).collect::<Vec<_>>(), vec![2, 3, 8, 9]);
+        let bytes = bincode::serialize(&opening).unwrap();
+        let opening: GenomeOpening = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(genome.commitment.verify(&opening).unwrap(), vec![vec![BASE_G, BASE_T, BASE_N], vec![BASE_A], vec![BASE_A, BASE_C, BASE_G]]);
+
+        // The commitment survives a round trip through its prover data.
+        let bytes = bincode::serialize(&genome).unwrap();
+        let reloaded: CommittedGenome = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(reloaded.commitment, genome.commitment);
+        assert_eq!(reloaded.open(&regions).unwrap(), opening);
+
+        let mut tampered = opening.clone();
+        tampered.leaves[0].bases[2] = BASE_C;
+        assert!(matches!(genome.commitment.verify(&tampered), Err(GenomeError::InvalidOpening(_))));
+        let mut missing = opening.clone();
+        missing.leaves.remove(1);
+        assert_eq!(genome.commitment.verify(&missing), Err(GenomeError::MissingLeaf(3)));
+        let mut out_of_bounds = opening.clone();
+        out_of_bounds.regions.push(GenomeRegion::position("chr2", 20));
+        assert!(matches!(genome.commitment.verify(&out_of_bounds), Err(GenomeError::RegionOutOfBounds { length: 19, .. })));
+        assert!(matches!(genome.open(&[GenomeRegion::position("chrM", 1)]), Err(GenomeError::UnknownChromosome(_))));
+
+        let mut resalted = opening.clone();
+        resalted.leaves[0].salt = salt_to_words(Fr::rand(rng));
+        assert!(matches!(genome.commitment.verify(&resalted), Err(GenomeError::InvalidOpening(_))));
+
+        // Committing the same genome again gives a different root, so a root and the digests in an
+        // opening say nothing about unopened leaves.
+        let again = CommittedGenome::commit(&fasta, 4, rng).unwrap();
+        assert_ne!(again.commitment.root, genome.commitment.root);
+        assert!(genome.commitment.verify(&again.open(&regions).unwrap()).is_err());
+        assert_eq!(again.commitment.verify(&again.open(&regions).unwrap()).unwrap()[0], vec![BASE_G, BASE_T, BASE_N]);
+    }
+}
@@ -0,0 +1,949 @@
+// Incrementally verifiable computation of the alignment relation, for alignments far too long for one
+// AlignmentCircuit. The CIGAR is walked in segments of a fixed number of blocks, each also taking in as
+// many blocks of either sequence, and the state between segments carries the walk (indices, score and
+// the flags of the previous operation) together with the running commitments to the sequences and the
+// memcheck products.
+//
+// A segment is proven on MNT6-298 on top of the previous segment, whose proof it verifies. That proof
+// lives on MNT4-298, whose base field is the scalar field of MNT6-298 and vice versa: every segment
+// proof is wrapped by one on the other curve of the cycle that only verifies it. The statement of both
+// is a hash of the state, the segment count and the wrapping verifying key, so the last wrapped proof is
+// a single constant-size proof for the whole alignment. The 298-bit cycle gives about 80 bits of
+// security; the 753-bit cycle (ark-mnt4-753, ark-mnt6-753) has the same interface.
+//
+// The reads of the walk are checked like those of ReadOnlyMemoryVar, without timestamps because the
+// walk reads every address at most once, in order. The challenges have to be fixed after every read,
+// which is only known once the last segment is done, so the prover picks them for the first segment and
+// the last checks they are the hash of the final commitments and of a transcript of the CIGAR and the
+// values read.
+
+use std::fmt;
+
+use ark_crypto_primitives::snark::constraints::{BooleanInputVar, SNARKGadget};
+use ark_crypto_primitives::snark::{CircuitSpecificSetupSNARK, SNARK};
+use ark_crypto_primitives::sponge::constraints::CryptographicSpongeVar;
+use ark_crypto_primitives::sponge::poseidon::constraints::PoseidonSpongeVar;
+use ark_crypto_primitives::sponge::poseidon::PoseidonSponge;
+use ark_crypto_primitives::sponge::{Absorb, CryptographicSponge};
+use ark_ec::pairing::Pairing;
+use ark_ec::AffineRepr;
+use ark_ff::{BigInteger, PrimeField, ToConstraintField};
+use ark_groth16::constraints::{Groth16VerifierGadget, ProofVar, VerifyingKeyVar};
+use ark_groth16::{Groth16, Proof, ProvingKey, VerifyingKey};
+use ark_mnt4_298::constraints::PairingVar as MNT4PairingVar;
+use ark_mnt4_298::MNT4_298;
+use ark_mnt6_298::constraints::PairingVar as MNT6PairingVar;
+use ark_mnt6_298::MNT6_298;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_r1cs_std::ToConstraintFieldGadget;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_std::rand::{CryptoRng, RngCore};
+
+use crate::alignment::{
+    enforce_cigar_walk_segment, i64_to_felt, pack_bases, pack_cigar, unpack_bases, unpack_cigar, AlignmentCircuit,
+    CigarWalkState, ScoringScheme, BASES_PER_BLOCK, BASE_N, BASE_PADDING, BITS_PER_BASE, CIGAR_DELETION, CIGAR_INSERTION,
+    CIGAR_MATCH, CIGAR_MISMATCH, CIGAR_OPS_PER_BLOCK, CIGAR_PADDING, CIGAR_SOFT_CLIP,
+};
+use crate::memory::MemoryReader;
+use crate::poseidon::poseidon_parameters;
+
+type StepCurve = MNT6_298;
+type WrapCurve = MNT4_298;
+/// The field of the segment circuits, in which sequences are committed to.
+pub type StepField = <StepCurve as Pairing>::ScalarField;
+/// The field of the circuits wrapping segment proofs.
+pub type WrapField = <WrapCurve as Pairing>::ScalarField;
+// Verifies segment proofs in WrapField circuits, and wrapped proofs in StepField circuits.
+type StepVerifierGadget = Groth16VerifierGadget<StepCurve, MNT6PairingVar>;
+type WrapVerifierGadget = Groth16VerifierGadget<WrapCurve, MNT4PairingVar>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IvcError {
+    InvalidProof,
+}
+
+impl fmt::Display for IvcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IvcError::InvalidProof => write!(f, "folded alignment proof does not verify"),
+        }
+    }
+}
+
+impl std::error::Error for IvcError {}
+
+/// One segment of an alignment: `CIGAR_OPS_PER_BLOCK * blocks_per_segment` operations and as many slots
+/// of either sequence, with the bases every operation reads.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub index: u64,
+    pub cigar: Vec<usize>, // padded with CIGAR_PADDING
+    pub reference_bases: Vec<usize>, // slots index * len.. of the reference, padded with BASE_PADDING
+    pub target_bases: Vec<usize>,
+    pub reference_reads: Vec<usize>, // for every operation, the base it reads or 0 if it reads none
+    pub target_reads: Vec<usize>,
+    pub finalize: bool, // whether this is the last segment
+}
+
+impl Segment {
+    /// A segment that does nothing, for setting up the circuits.
+    pub fn blank(index: u64, blocks_per_segment: usize) -> Self {
+        let length = blocks_per_segment * CIGAR_OPS_PER_BLOCK;
+        Self {
+            index,
+            cigar: vec![CIGAR_PADDING; length],
+            reference_bases: vec![BASE_PADDING; length],
+            target_bases: vec![BASE_PADDING; length],
+            reference_reads: vec![0; length],
+            target_reads: vec![0; length],
+            finalize: false,
+        }
+    }
+}
+
+/// Splits an alignment into as many segments of `blocks_per_segment` blocks as it takes to cover the
+/// CIGAR and both sequences.
+pub fn segments(reference_sequence_bases: &[usize], target_sequence_bases: &[usize], cigar_string_bases: &[usize], blocks_per_segment: usize) -> Vec<Segment> {
+    assert_eq!(BASES_PER_BLOCK, CIGAR_OPS_PER_BLOCK, "segments take as many bases as operations");
+    let length = blocks_per_segment * CIGAR_OPS_PER_BLOCK;
+    let longest = cigar_string_bases.len().max(reference_sequence_bases.len()).max(target_sequence_bases.len());
+    let num_segments = longest.div_ceil(length).max(1);
+    let padded = |symbols: &[usize], padding: usize| {
+        let mut symbols = symbols.to_vec();
+        symbols.resize(num_segments * length, padding);
+        symbols
+    };
+    let cigar = padded(cigar_string_bases, CIGAR_PADDING);
+    let reference = padded(reference_sequence_bases, BASE_PADDING);
+    let target = padded(target_sequence_bases, BASE_PADDING);
+
+    let (mut reference_reads, mut target_reads) = (Vec::new(), Vec::new());
+    let (mut reference_index, mut target_index) = (0, 0);
+    for &op in cigar.iter() {
+        let consumes_target = matches!(op, CIGAR_MATCH | CIGAR_MISMATCH | CIGAR_INSERTION | CIGAR_SOFT_CLIP);
+        let consumes_reference = matches!(op, CIGAR_MATCH | CIGAR_MISMATCH | CIGAR_DELETION);
+        target_reads.push(if consumes_target { target_sequence_bases.get(target_index).copied().unwrap_or(0) } else { 0 });
+        reference_reads.push(if consumes_reference { reference_sequence_bases.get(reference_index).copied().unwrap_or(0) } else { 0 });
+        target_index += consumes_target as usize;
+        reference_index += consumes_reference as usize;
+    }
+
+    (0..num_segments)
+        .map(|i| {
+            let range = i * length..(i + 1) * length;
+            Segment {
+                index: i as u64,
+                cigar: cigar[range.clone()].to_vec(),
+                reference_bases: reference[range.clone()].to_vec(),
+                target_bases: target[range.clone()].to_vec(),
+                reference_reads: reference_reads[range.clone()].to_vec(),
+                target_reads: target_reads[range].to_vec(),
+                finalize: i + 1 == num_segments,
+            }
+        })
+        .collect()
+}
+
+/// The state carried from one segment to the next. Once finalized only the substitution matrix, the
+/// totals of the walk and the commitments are left, which is what the verifier knows.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SegmentState<F: PrimeField> {
+    pub substitution_matrix: Vec<F>, // row-major, as in the public inputs of AlignmentCircuit
+    pub target_index: F,
+    pub reference_index: F,
+    pub alignment_score: F,
+    pub previous_is_insertion: bool,
+    pub previous_is_deletion: bool,
+    pub previous_is_padding: bool,
+    pub aligned_so_far: bool,
+    pub clipped_after_aligned: bool,
+    pub finished: bool,
+    pub challenges: [F; 2], // memcheck challenges, picked by the prover and checked when finalizing
+    pub reference_commitment: F, // hash chains of commitment::commit_blocks over the blocks so far
+    pub target_commitment: F,
+    pub reference_cells: F, // products of the fingerprints of the present cells so far
+    pub target_cells: F,
+    pub reference_reads: F, // products of the fingerprints of the reads so far
+    pub target_reads: F,
+    pub transcript: F, // hash chain over the CIGAR blocks and the values read
+}
+
+fn hash<F: PrimeField + Absorb>(elements: &[F], num_outputs: usize) -> Vec<F> {
+    let mut sponge = PoseidonSponge::<F>::new(&poseidon_parameters());
+    sponge.absorb(&elements.to_vec());
+    sponge.squeeze_field_elements::<F>(num_outputs)
+}
+
+fn hash_var<F: PrimeField>(cs: ConstraintSystemRef<F>, elements: &[FpVar<F>], num_outputs: usize) -> Result<Vec<FpVar<F>>, SynthesisError> {
+    let mut sponge = PoseidonSpongeVar::new(cs, &poseidon_parameters());
+    sponge.absorb(&elements.to_vec())?;
+    sponge.squeeze_field_elements(num_outputs)
+}
+
+/// Packs bits as densely as the field allows, the first bit least significant.
+fn pack_bits<F: PrimeField>(bits: &[bool]) -> Vec<F> {
+    bits.chunks(F::MODULUS_BIT_SIZE as usize - 1).map(|chunk| F::from_bigint(F::BigInt::from_bits_le(chunk)).unwrap()).collect()
+}
+
+fn fingerprint<F: PrimeField>(challenges: &[F; 2], address: usize, value: usize) -> F {
+    challenges[0] + F::from(value as u64) + F::from(address as u64) * challenges[1]
+}
+
+impl<F: PrimeField + Absorb> SegmentState<F> {
+    /// The state before the first segment, committing to the sequences with the given salts.
+    pub fn fresh(substitution_matrix: Vec<F>, challenges: [F; 2], reference_salt: F, target_salt: F) -> Self {
+        Self {
+            substitution_matrix,
+            target_index: F::zero(),
+            reference_index: F::zero(),
+            alignment_score: F::zero(),
+            previous_is_insertion: false,
+            previous_is_deletion: false,
+            previous_is_padding: false,
+            aligned_so_far: false,
+            clipped_after_aligned: false,
+            finished: false,
+            challenges,
+            reference_commitment: hash(&[reference_salt], 1)[0],
+            target_commitment: hash(&[target_salt], 1)[0],
+            reference_cells: F::one(),
+            target_cells: F::one(),
+            reference_reads: F::one(),
+            target_reads: F::one(),
+            transcript: F::zero(),
+        }
+    }
+
+    /// The finished state of a proof of `statement`.
+    pub fn finished(statement: &IvcStatement<F>) -> Self {
+        Self {
+            substitution_matrix: ScoringScheme::new(0, statement.substitution_matrix, 0, 0, 0).substitution_matrix_felts(),
+            target_index: F::from(statement.target_length as u64),
+            reference_index: F::from(statement.reference_length as u64),
+            alignment_score: i64_to_felt(statement.alignment_score),
+            previous_is_insertion: false,
+            previous_is_deletion: false,
+            previous_is_padding: false,
+            aligned_so_far: false,
+            clipped_after_aligned: false,
+            finished: true,
+            challenges: [F::zero(); 2],
+            reference_commitment: statement.reference_commitment,
+            target_commitment: statement.target_commitment,
+            reference_cells: F::zero(),
+            target_cells: F::zero(),
+            reference_reads: F::zero(),
+            target_reads: F::zero(),
+            transcript: F::zero(),
+        }
+    }
+
+    /// The challenges the last segment expects, a hash of everything the prover chose.
+    pub fn derived_challenges(&self) -> [F; 2] {
+        let challenges = hash(&[self.reference_commitment, self.target_commitment, self.transcript, self.target_index, self.reference_index], 2);
+        [challenges[0], challenges[1]]
+    }
+
+    /// Walks `segment`, as the segment circuit does. A finished state stays as it is.
+    pub fn apply(&self, segment: &Segment, scoring_scheme: &ScoringScheme) -> Self {
+        if self.finished {
+            return self.clone();
+        }
+        let mut next = self.clone();
+        let offset = segment.index as usize * segment.cigar.len();
+        for (bases, commitment, cells) in [
+            (&segment.reference_bases, &mut next.reference_commitment, &mut next.reference_cells),
+            (&segment.target_bases, &mut next.target_commitment, &mut next.target_cells),
+        ] {
+            for (i, &base) in bases.iter().enumerate().filter(|(_, base)| **base != BASE_PADDING) {
+                *cells *= fingerprint(&self.challenges, offset + i, base);
+            }
+            for block in bases.chunks(BASES_PER_BLOCK).filter(|block| block[0] != BASE_PADDING) {
+                *commitment = hash(&[*commitment, pack_bases::<F>(block)[0]], 1)[0];
+            }
+        }
+
+        let (mut target_index, mut reference_index) = (self.target_index, self.reference_index);
+        for (j, &op) in segment.cigar.iter().enumerate() {
+            let (reference_base, target_base) = (segment.reference_reads[j], segment.target_reads[j]);
+            let is_insertion = op == CIGAR_INSERTION;
+            let is_deletion = op == CIGAR_DELETION;
+            let consumes_target = matches!(op, CIGAR_MATCH | CIGAR_MISMATCH | CIGAR_INSERTION | CIGAR_SOFT_CLIP);
+            let consumes_reference = matches!(op, CIGAR_MATCH | CIGAR_MISMATCH | CIGAR_DELETION);
+            if consumes_target {
+                next.target_reads *= self.challenges[0] + F::from(target_base as u64) + target_index * self.challenges[1];
+                target_index += F::one();
+            }
+            if consumes_reference {
+                next.reference_reads *= self.challenges[0] + F::from(reference_base as u64) + reference_index * self.challenges[1];
+                reference_index += F::one();
+            }
+
+            let mut score = i64_to_felt::<F>(0);
+            if (is_insertion && !next.previous_is_insertion) || (is_deletion && !next.previous_is_deletion) {
+                score += i64_to_felt::<F>(scoring_scheme.gap_open);
+            }
+            if is_insertion || is_deletion {
+                score += i64_to_felt::<F>(scoring_scheme.gap_extend);
+            }
+            if op == CIGAR_MISMATCH {
+                score += if reference_base == BASE_N || target_base == BASE_N {
+                    i64_to_felt::<F>(scoring_scheme.ambiguous_score)
+                } else {
+                    self.substitution_matrix[4 * reference_base + target_base]
+                };
+            }
+            if op == CIGAR_MATCH {
+                score += i64_to_felt::<F>(scoring_scheme.match_score);
+            }
+            next.alignment_score += score;
+
+            let is_aligned = matches!(op, CIGAR_MATCH | CIGAR_MISMATCH | CIGAR_INSERTION | CIGAR_DELETION);
+            next.clipped_after_aligned |= op == CIGAR_SOFT_CLIP && next.aligned_so_far;
+            next.aligned_so_far |= is_aligned;
+            next.previous_is_insertion = is_insertion;
+            next.previous_is_deletion = is_deletion;
+            next.previous_is_padding = op == CIGAR_PADDING;
+        }
+        next.target_index = target_index;
+        next.reference_index = reference_index;
+
+        let mut read_bits = Vec::new();
+        for reads in [&segment.reference_reads, &segment.target_reads] {
+            read_bits.extend(reads.iter().flat_map(|&base| (0..BITS_PER_BASE).map(move |j| (base >> j) & 1 == 1)));
+        }
+        let mut transcript = vec![self.transcript];
+        transcript.extend(pack_cigar::<F>(&segment.cigar));
+        transcript.extend(pack_bits::<F>(&read_bits));
+        next.transcript = hash(&transcript, 1)[0];
+        next
+    }
+
+    /// The state after the last segment, keeping only what the statement holds.
+    pub fn finalized(&self) -> Self {
+        Self {
+            substitution_matrix: self.substitution_matrix.clone(),
+            target_index: self.target_index,
+            reference_index: self.reference_index,
+            alignment_score: self.alignment_score,
+            previous_is_insertion: false,
+            previous_is_deletion: false,
+            previous_is_padding: false,
+            aligned_so_far: false,
+            clipped_after_aligned: false,
+            finished: true,
+            challenges: [F::zero(); 2],
+            reference_commitment: self.reference_commitment,
+            target_commitment: self.target_commitment,
+            reference_cells: F::zero(),
+            target_cells: F::zero(),
+            reference_reads: F::zero(),
+            target_reads: F::zero(),
+            transcript: F::zero(),
+        }
+    }
+
+    /// The state as it is hashed.
+    pub fn to_field_elements(&self) -> Vec<F> {
+        let mut elements = self.substitution_matrix.clone();
+        elements.extend([self.target_index, self.reference_index, self.alignment_score]);
+        elements.extend(
+            [self.previous_is_insertion, self.previous_is_deletion, self.previous_is_padding, self.aligned_so_far, self.clipped_after_aligned, self.finished]
+                .map(F::from),
+        );
+        elements.extend(self.challenges);
+        elements.extend([
+            self.reference_commitment,
+            self.target_commitment,
+            self.reference_cells,
+            self.target_cells,
+            self.reference_reads,
+            self.target_reads,
+            self.transcript,
+        ]);
+        elements
+    }
+}
+
+/// `SegmentState` in-circuit.
+#[derive(Clone)]
+struct SegmentStateVar<F: PrimeField> {
+    substitution_matrix: Vec<FpVar<F>>,
+    walk: CigarWalkState<F>,
+    finished: Boolean<F>,
+    challenges: Vec<FpVar<F>>,
+    reference_commitment: FpVar<F>,
+    target_commitment: FpVar<F>,
+    reference_cells: FpVar<F>,
+    target_cells: FpVar<F>,
+    reference_reads: FpVar<F>,
+    target_reads: FpVar<F>,
+    transcript: FpVar<F>,
+}
+
+impl<F: PrimeField> SegmentStateVar<F> {
+    fn new_witness(cs: ConstraintSystemRef<F>, state: &SegmentState<F>) -> Result<Self, SynthesisError> {
+        let field = |value: F| FpVar::new_witness(cs.clone(), || Ok(value));
+        let flag = |value: bool| Boolean::new_witness(cs.clone(), || Ok(value));
+        let mut substitution_matrix = Vec::new();
+        for &score in state.substitution_matrix.iter() {
+            substitution_matrix.push(field(score)?);
+        }
+        Ok(Self {
+            substitution_matrix,
+            walk: CigarWalkState {
+                target_index: field(state.target_index)?,
+                reference_index: field(state.reference_index)?,
+                alignment_score: field(state.alignment_score)?,
+                previous_is_insertion: flag(state.previous_is_insertion)?,
+                previous_is_deletion: flag(state.previous_is_deletion)?,
+                previous_is_padding: flag(state.previous_is_padding)?,
+                aligned_so_far: flag(state.aligned_so_far)?,
+                clipped_after_aligned: flag(state.clipped_after_aligned)?,
+            },
+            finished: flag(state.finished)?,
+            challenges: vec![field(state.challenges[0])?, field(state.challenges[1])?],
+            reference_commitment: field(state.reference_commitment)?,
+            target_commitment: field(state.target_commitment)?,
+            reference_cells: field(state.reference_cells)?,
+            target_cells: field(state.target_cells)?,
+            reference_reads: field(state.reference_reads)?,
+            target_reads: field(state.target_reads)?,
+            transcript: field(state.transcript)?,
+        })
+    }
+
+    fn flags(&self) -> Vec<Boolean<F>> {
+        let walk = &self.walk;
+        vec![
+            walk.previous_is_insertion.clone(),
+            walk.previous_is_deletion.clone(),
+            walk.previous_is_padding.clone(),
+            walk.aligned_so_far.clone(),
+            walk.clipped_after_aligned.clone(),
+            self.finished.clone(),
+        ]
+    }
+
+    fn to_field_elements(&self) -> Vec<FpVar<F>> {
+        let mut elements = self.substitution_matrix.clone();
+        elements.extend([self.walk.target_index.clone(), self.walk.reference_index.clone(), self.walk.alignment_score.clone()]);
+        elements.extend(self.flags().into_iter().map(FpVar::from));
+        elements.extend(self.challenges.iter().cloned());
+        elements.extend([
+            self.reference_commitment.clone(),
+            self.target_commitment.clone(),
+            self.reference_cells.clone(),
+            self.target_cells.clone(),
+            self.reference_reads.clone(),
+            self.target_reads.clone(),
+            self.transcript.clone(),
+        ]);
+        elements
+    }
+
+    /// `true_value` if `condition`, else `false_value`, field by field.
+    fn select(condition: &Boolean<F>, true_value: &Self, false_value: &Self) -> Result<Self, SynthesisError> {
+        let field = |a: &FpVar<F>, b: &FpVar<F>| FpVar::conditionally_select(condition, a, b);
+        let flag = |a: &Boolean<F>, b: &Boolean<F>| Boolean::conditionally_select(condition, a, b);
+        let fields = |a: &[FpVar<F>], b: &[FpVar<F>]| a.iter().zip(b).map(|(a, b)| field(a, b)).collect::<Result<Vec<_>, _>>();
+        let (t, f) = (&true_value.walk, &false_value.walk);
+        Ok(Self {
+            substitution_matrix: fields(&true_value.substitution_matrix, &false_value.substitution_matrix)?,
+            walk: CigarWalkState {
+                target_index: field(&t.target_index, &f.target_index)?,
+                reference_index: field(&t.reference_index, &f.reference_index)?,
+                alignment_score: field(&t.alignment_score, &f.alignment_score)?,
+                previous_is_insertion: flag(&t.previous_is_insertion, &f.previous_is_insertion)?,
+                previous_is_deletion: flag(&t.previous_is_deletion, &f.previous_is_deletion)?,
+                previous_is_padding: flag(&t.previous_is_padding, &f.previous_is_padding)?,
+                aligned_so_far: flag(&t.aligned_so_far, &f.aligned_so_far)?,
+                clipped_after_aligned: flag(&t.clipped_after_aligned, &f.clipped_after_aligned)?,
+            },
+            finished: flag(&true_value.finished, &false_value.finished)?,
+            challenges: fields(&true_value.challenges, &false_value.challenges)?,
+            reference_commitment: field(&true_value.reference_commitment, &false_value.reference_commitment)?,
+            target_commitment: field(&true_value.target_commitment, &false_value.target_commitment)?,
+            reference_cells: field(&true_value.reference_cells, &false_value.reference_cells)?,
+            target_cells: field(&true_value.target_cells, &false_value.target_cells)?,
+            reference_reads: field(&true_value.reference_reads, &false_value.reference_reads)?,
+            target_reads: field(&true_value.target_reads, &false_value.target_reads)?,
+            transcript: field(&true_value.transcript, &false_value.transcript)?,
+        })
+    }
+
+    /// If `condition`, enforces that this is a fresh state committing with salts the prover knows.
+    fn enforce_fresh(&self, cs: ConstraintSystemRef<F>, salts: [F; 2], condition: &Boolean<F>) -> Result<(), SynthesisError> {
+        // Indices, score and flags are zero.
+        let mut expected = vec![FpVar::zero(); 9];
+        let mut actual = vec![self.walk.target_index.clone(), self.walk.reference_index.clone(), self.walk.alignment_score.clone()];
+        actual.extend(self.flags().into_iter().map(FpVar::from));
+        for (salt, commitment) in salts.into_iter().zip([&self.reference_commitment, &self.target_commitment]) {
+            let salt = FpVar::new_witness(cs.clone(), || Ok(salt))?;
+            expected.push(hash_var(cs.clone(), &[salt], 1)?.remove(0));
+            actual.push(commitment.clone());
+        }
+        expected.extend(vec![FpVar::one(); 4]);
+        actual.extend([self.reference_cells.clone(), self.target_cells.clone(), self.reference_reads.clone(), self.target_reads.clone()]);
+        expected.push(FpVar::zero());
+        actual.push(self.transcript.clone());
+        for (actual, expected) in actual.iter().zip(expected.iter()) {
+            actual.conditional_enforce_equal(expected, condition)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads of a segment, each multiplying its fingerprint into a running product that the last segment
+/// compares with the product over the present cells.
+struct SegmentReads<'a, F: PrimeField> {
+    cs: ConstraintSystemRef<F>,
+    challenges: &'a [FpVar<F>],
+    product: FpVar<F>,
+    values: std::slice::Iter<'a, usize>, // native values, in the order of the reads
+    bits: Vec<Boolean<F>>,
+}
+
+impl<F: PrimeField> MemoryReader<F> for SegmentReads<'_, F> {
+    fn read(&mut self, address: &FpVar<F>, enabled: &Boolean<F>) -> Result<FpVar<F>, SynthesisError> {
+        let value = self.values.next().copied().unwrap_or(0);
+        let mut value_bits = Vec::new();
+        for j in 0..BITS_PER_BASE {
+            value_bits.push(Boolean::new_witness(self.cs.clone(), || Ok((value >> j) & 1 == 1))?);
+        }
+        let value = Boolean::le_bits_to_fp_var(&value_bits)?;
+        let fingerprint = &self.challenges[0] + &value + address * &self.challenges[1];
+        self.product *= FpVar::from(enabled.clone()) * (fingerprint - F::one()) + F::one();
+        self.bits.extend(value_bits);
+        Ok(value)
+    }
+}
+
+/// Walks `segment` from `state` in-circuit, as `SegmentState::apply` and, for the last segment,
+/// `SegmentState::finalized` do natively. A finished state is passed on unchanged.
+fn enforce_segment<F: PrimeField>(
+    cs: ConstraintSystemRef<F>,
+    scoring_scheme: &ScoringScheme,
+    index: &FpVar<F>,
+    state: &SegmentStateVar<F>,
+    segment: &Segment,
+) -> Result<SegmentStateVar<F>, SynthesisError> {
+    let witness_blocks = |blocks: Vec<F>| blocks.into_iter().map(|block| FpVar::new_witness(cs.clone(), || Ok(block))).collect::<Result<Vec<_>, _>>();
+    let cigar_blocks = witness_blocks(pack_cigar::<F>(&segment.cigar))?;
+    let mut cigar_chars = Vec::new();
+    for block in cigar_blocks.iter() {
+        cigar_chars.extend(unpack_cigar(block)?);
+    }
+
+    // Cells of this segment sit at index * len + i.
+    let mut next = state.clone();
+    let offset = index * F::from(segment.cigar.len() as u64);
+    for (bases, commitment, cells) in [
+        (&segment.reference_bases, &mut next.reference_commitment, &mut next.reference_cells),
+        (&segment.target_bases, &mut next.target_commitment, &mut next.target_cells),
+    ] {
+        for (b, block) in witness_blocks(pack_bases::<F>(bases))?.iter().enumerate() {
+            let block_bases = unpack_bases(block)?;
+            for (k, base) in block_bases.iter().enumerate() {
+                let address = &offset + F::from((b * BASES_PER_BLOCK + k) as u64);
+                let fingerprint = &state.challenges[0] + &base.value + address * &state.challenges[1];
+                *cells *= FpVar::from(base.is_padding.not()) * (fingerprint - F::one()) + F::one();
+            }
+            // Padding only trails, so a block holds bases iff its first slot does.
+            let chained = hash_var(cs.clone(), &[commitment.clone(), block.clone()], 1)?.remove(0);
+            *commitment = FpVar::conditionally_select(&block_bases[0].is_padding, commitment, &chained)?;
+        }
+    }
+
+    let mut reference = SegmentReads { cs: cs.clone(), challenges: &state.challenges, product: state.reference_reads.clone(), values: segment.reference_reads.iter(), bits: Vec::new() };
+    let mut target = SegmentReads { cs: cs.clone(), challenges: &state.challenges, product: state.target_reads.clone(), values: segment.target_reads.iter(), bits: Vec::new() };
+    let (walk, _) = enforce_cigar_walk_segment(cs.clone(), &cigar_chars, state.walk.clone(), &mut reference, &mut target, scoring_scheme, &state.substitution_matrix)?;
+    next.walk = walk;
+    next.reference_reads = reference.product;
+    next.target_reads = target.product;
+
+    let mut transcript = vec![state.transcript.clone()];
+    transcript.extend(cigar_blocks);
+    let read_bits: Vec<_> = reference.bits.into_iter().chain(target.bits).collect();
+    for chunk in read_bits.chunks(F::MODULUS_BIT_SIZE as usize - 1) {
+        transcript.push(Boolean::le_bits_to_fp_var(chunk)?);
+    }
+    next.transcript = hash_var(cs.clone(), &transcript, 1)?.remove(0);
+
+    // The last segment checks the reads and the challenges, and clears everything else.
+    let finalize = Boolean::new_witness(cs.clone(), || Ok(segment.finalize))?.and(&state.finished.not())?;
+    next.reference_reads.conditional_enforce_equal(&next.reference_cells, &finalize)?;
+    next.target_reads.conditional_enforce_equal(&next.target_cells, &finalize)?;
+    let derived = hash_var(
+        cs.clone(),
+        &[next.reference_commitment.clone(), next.target_commitment.clone(), next.transcript.clone(), next.walk.target_index.clone(), next.walk.reference_index.clone()],
+        2,
+    )?;
+    for (challenge, derived) in state.challenges.iter().zip(derived.iter()) {
+        challenge.conditional_enforce_equal(derived, &finalize)?;
+    }
+    let mut finalized = next.clone();
+    let totals = &next.walk;
+    finalized.walk = CigarWalkState {
+        target_index: totals.target_index.clone(),
+        alignment_score: totals.alignment_score.clone(),
+        ..CigarWalkState::start(&totals.reference_index)
+    };
+    finalized.finished = Boolean::TRUE;
+    finalized.challenges = vec![FpVar::zero(); 2];
+    for value in [&mut finalized.reference_cells, &mut finalized.target_cells, &mut finalized.reference_reads, &mut finalized.target_reads, &mut finalized.transcript] {
+        *value = FpVar::zero();
+    }
+    let next = SegmentStateVar::select(&finalize, &finalized, &next)?;
+    SegmentStateVar::select(&state.finished, state, &next)
+}
+
+fn verifying_key_elements<E: Pairing, F: PrimeField>(vk: &VerifyingKey<E>) -> Vec<F>
+where
+    E::G1Affine: ToConstraintField<F>,
+    E::G2Affine: ToConstraintField<F>,
+{
+    let mut elements = vk.alpha_g1.to_field_elements().unwrap();
+    for point in [&vk.beta_g2, &vk.gamma_g2, &vk.delta_g2] {
+        elements.extend(point.to_field_elements().unwrap());
+    }
+    for point in vk.gamma_abc_g1.iter() {
+        elements.extend(point.to_field_elements().unwrap());
+    }
+    elements
+}
+
+/// The statement of the proofs after `num_segments` segments ending in `state`.
+fn statement_hash(wrap_verifying_key_hash: StepField, num_segments: u64, state: &SegmentState<StepField>) -> StepField {
+    let mut elements = vec![wrap_verifying_key_hash, StepField::from(num_segments)];
+    elements.extend(state.to_field_elements());
+    hash(&elements, 1)[0]
+}
+
+/// The public inputs of a wrapped proof of `statement_hash`, its bits packed into WrapField elements.
+fn wrap_inputs(statement_hash: StepField) -> Vec<WrapField> {
+    let bits = statement_hash.into_bigint().to_bits_le();
+    pack_bits::<WrapField>(&bits[..StepField::MODULUS_BIT_SIZE as usize])
+}
+
+fn blank_proof<E: Pairing>() -> Proof<E> {
+    Proof { a: E::G1Affine::generator(), b: E::G2Affine::generator(), c: E::G1Affine::generator() }
+}
+
+/// Proves one segment on top of the wrapped proof of the segments before it.
+#[derive(Clone)]
+pub struct SegmentCircuit {
+    pub scoring_scheme: ScoringScheme,
+    pub wrap_verifying_key: VerifyingKey<WrapCurve>,
+    pub previous_proof: Proof<WrapCurve>, // ignored for the first segment
+    pub state: SegmentState<StepField>, // before the segment
+    pub segment: Segment,
+    pub salts: [StepField; 2], // of the reference and target commitments, only used by the first segment
+}
+
+impl SegmentCircuit {
+    /// A circuit of the right shape for setting up the keys.
+    pub fn blank(scoring_scheme: ScoringScheme, blocks_per_segment: usize) -> Self {
+        let num_inputs = wrap_inputs(StepField::from(0u64)).len();
+        let point = <WrapCurve as Pairing>::G1Affine::generator();
+        let wrap_verifying_key = VerifyingKey {
+            alpha_g1: point,
+            beta_g2: <WrapCurve as Pairing>::G2Affine::generator(),
+            gamma_g2: <WrapCurve as Pairing>::G2Affine::generator(),
+            delta_g2: <WrapCurve as Pairing>::G2Affine::generator(),
+            gamma_abc_g1: vec![point; num_inputs + 1],
+        };
+        let state = SegmentState::fresh(scoring_scheme.substitution_matrix_felts(), [StepField::from(0u64); 2], StepField::from(0u64), StepField::from(0u64));
+        Self {
+            scoring_scheme,
+            wrap_verifying_key,
+            previous_proof: blank_proof(),
+            state,
+            segment: Segment::blank(0, blocks_per_segment),
+            salts: [StepField::from(0u64); 2],
+        }
+    }
+}
+
+impl ConstraintSynthesizer<StepField> for SegmentCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<StepField>) -> Result<(), SynthesisError> {
+        let wrap_verifying_key = VerifyingKeyVar::<WrapCurve, MNT4PairingVar>::new_witness(cs.clone(), || Ok(self.wrap_verifying_key.clone()))?;
+        let mut key_elements = wrap_verifying_key.alpha_g1.to_constraint_field()?;
+        for point in [&wrap_verifying_key.beta_g2, &wrap_verifying_key.gamma_g2, &wrap_verifying_key.delta_g2] {
+            key_elements.extend(point.to_constraint_field()?);
+        }
+        for point in wrap_verifying_key.gamma_abc_g1.iter() {
+            key_elements.extend(point.to_constraint_field()?);
+        }
+        let wrap_verifying_key_hash = hash_var(cs.clone(), &key_elements, 1)?.remove(0);
+
+        let index = FpVar::new_witness(cs.clone(), || Ok(StepField::from(self.segment.index)))?;
+        let state = SegmentStateVar::new_witness(cs.clone(), &self.state)?;
+        let is_first = index.is_zero()?;
+        state.enforce_fresh(cs.clone(), self.salts, &is_first)?;
+
+        // Every segment but the first needs a wrapped proof of the state it starts from.
+        let mut statement = vec![wrap_verifying_key_hash.clone(), index.clone()];
+        statement.extend(state.to_field_elements());
+        let statement_hash = hash_var(cs.clone(), &statement, 1)?.remove(0);
+        let statement_bits = statement_hash.to_bits_le()?;
+        let inputs = BooleanInputVar::new(statement_bits.chunks(WrapField::MODULUS_BIT_SIZE as usize - 1).map(|chunk| chunk.to_vec()).collect());
+        let previous_proof = ProofVar::<WrapCurve, MNT4PairingVar>::new_witness(cs.clone(), || Ok(self.previous_proof.clone()))?;
+        let verified = <WrapVerifierGadget as SNARKGadget<_, _, Groth16<WrapCurve>>>::verify(&wrap_verifying_key, &inputs, &previous_proof)?;
+        verified.or(&is_first)?.enforce_equal(&Boolean::TRUE)?;
+
+        let next = enforce_segment(cs.clone(), &self.scoring_scheme, &index, &state, &self.segment)?;
+        let mut statement = vec![wrap_verifying_key_hash, index + StepField::from(1u64)];
+        statement.extend(next.to_field_elements());
+        let next_statement_hash = hash_var(cs.clone(), &statement, 1)?.remove(0);
+        let next_statement_hash_var = FpVar::new_input(cs.clone(), || next_statement_hash.value())?;
+        next_statement_hash_var.enforce_equal(&next_statement_hash)
+    }
+}
+
+/// Re-proves a segment proof on the other curve of the cycle, with the same statement.
+#[derive(Clone)]
+pub struct WrapCircuit {
+    pub step_verifying_key: VerifyingKey<StepCurve>,
+    pub proof: Proof<StepCurve>,
+    pub statement_hash: StepField,
+}
+
+impl ConstraintSynthesizer<WrapField> for WrapCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<WrapField>) -> Result<(), SynthesisError> {
+        let mut statement_bits = Vec::new();
+        let native_bits = self.statement_hash.into_bigint().to_bits_le();
+        for (chunk, input) in native_bits[..StepField::MODULUS_BIT_SIZE as usize].chunks(WrapField::MODULUS_BIT_SIZE as usize - 1).zip(wrap_inputs(self.statement_hash)) {
+            let input_var = FpVar::new_input(cs.clone(), || Ok(input))?;
+            let mut bits = Vec::new();
+            for &bit in chunk {
+                bits.push(Boolean::new_witness(cs.clone(), || Ok(bit))?);
+            }
+            Boolean::le_bits_to_fp_var(&bits)?.enforce_equal(&input_var)?;
+            statement_bits.extend(bits);
+        }
+        let step_verifying_key = VerifyingKeyVar::<StepCurve, MNT6PairingVar>::new_constant(cs.clone(), self.step_verifying_key)?;
+        let proof = ProofVar::<StepCurve, MNT6PairingVar>::new_witness(cs.clone(), || Ok(self.proof))?;
+        let inputs = BooleanInputVar::new(vec![statement_bits]);
+        <StepVerifierGadget as SNARKGadget<_, _, Groth16<StepCurve>>>::verify(&step_verifying_key, &inputs, &proof)?.enforce_equal(&Boolean::TRUE)
+    }
+}
+
+/// Keys for proving alignments of any length in segments of `blocks_per_segment` blocks.
+#[derive(Clone)]
+pub struct IvcProvingKey {
+    pub scoring_scheme: ScoringScheme, // everything but the substitution matrix is fixed at setup
+    pub blocks_per_segment: usize,
+    pub step: ProvingKey<StepCurve>,
+    pub wrap: ProvingKey<WrapCurve>,
+}
+
+#[derive(Clone)]
+pub struct IvcVerifyingKey {
+    pub wrap: VerifyingKey<WrapCurve>,
+}
+
+/// The public side of a folded alignment proof, as in the public inputs of AlignmentCircuit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IvcStatement<F: PrimeField> {
+    pub substitution_matrix: [[i64; 4]; 4],
+    pub reference_commitment: F,
+    pub target_commitment: F,
+    pub reference_length: usize,
+    pub target_length: usize,
+    pub alignment_score: i64,
+}
+
+/// The last wrapped proof, which stands for all `num_segments` segments.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IvcProof {
+    pub num_segments: u64,
+    pub proof: Proof<WrapCurve>,
+}
+
+/// Sets up the segment and wrapping circuits. The segment circuit verifies wrapped proofs under a
+/// verifying key it hashes into its statement, so that it can be set up before the wrapping circuit.
+pub fn setup<R: RngCore + CryptoRng>(scoring_scheme: ScoringScheme, blocks_per_segment: usize, rng: &mut R) -> (IvcProvingKey, IvcVerifyingKey) {
+    let (step, step_verifying_key) = Groth16::<StepCurve>::setup(SegmentCircuit::blank(scoring_scheme, blocks_per_segment), rng).unwrap();
+    let wrap_circuit = WrapCircuit { step_verifying_key, proof: blank_proof(), statement_hash: StepField::from(0u64) };
+    let (wrap, wrap_verifying_key) = Groth16::<WrapCurve>::setup(wrap_circuit, rng).unwrap();
+    (IvcProvingKey { scoring_scheme, blocks_per_segment, step, wrap }, IvcVerifyingKey { wrap: wrap_verifying_key })
+}
+
+/// Proves the alignment of `circuit` segment by segment, returning the statement it proves. The proof
+/// only verifies if the alignment is valid. Quality-weighted scores are not supported.
+pub fn prove<R: RngCore + CryptoRng>(pk: &IvcProvingKey, circuit: &AlignmentCircuit<StepField>, rng: &mut R) -> (IvcStatement<StepField>, IvcProof) {
+    assert!(circuit.target_qualities.is_none(), "folded proofs do not weight scores by quality");
+    assert_eq!(pk.scoring_scheme, ScoringScheme { substitution_matrix: pk.scoring_scheme.substitution_matrix, ..circuit.scoring_scheme }, "the keys fix the scoring scheme");
+    let segments = segments(&circuit.reference_sequence_bases, &circuit.target_sequence_bases, &circuit.cigar_string_bases, pk.blocks_per_segment);
+    let salts = [circuit.reference_salt, circuit.target_salt];
+    let substitution_matrix = circuit.scoring_scheme.substitution_matrix_felts::<StepField>();
+
+    // The challenges do not change the transcript or the commitments, so one pass with any challenges
+    // finds the ones the last segment expects.
+    let mut state = SegmentState::fresh(substitution_matrix.clone(), [StepField::from(0u64); 2], salts[0], salts[1]);
+    for segment in segments.iter() {
+        state = state.apply(segment, &circuit.scoring_scheme);
+    }
+    let challenges = state.derived_challenges();
+
+    let wrap_verifying_key_hash = hash(&verifying_key_elements::<WrapCurve, StepField>(&pk.wrap.vk), 1)[0];
+    let mut state = SegmentState::fresh(substitution_matrix, challenges, salts[0], salts[1]);
+    let mut previous_proof = blank_proof();
+    for segment in segments.iter() {
+        let mut next = state.apply(segment, &circuit.scoring_scheme);
+        if segment.finalize {
+            next = next.finalized();
+        }
+        let segment_circuit = SegmentCircuit {
+            scoring_scheme: circuit.scoring_scheme,
+            wrap_verifying_key: pk.wrap.vk.clone(),
+            previous_proof,
+            state,
+            segment: segment.clone(),
+            salts,
+        };
+        let proof = Groth16::<StepCurve>::prove(&pk.step, segment_circuit, rng).unwrap();
+        let statement_hash = statement_hash(wrap_verifying_key_hash, segment.index + 1, &next);
+        let wrap_circuit = WrapCircuit { step_verifying_key: pk.step.vk.clone(), proof, statement_hash };
+        previous_proof = Groth16::<WrapCurve>::prove(&pk.wrap, wrap_circuit, rng).unwrap();
+        state = next;
+    }
+
+    let statement = IvcStatement {
+        substitution_matrix: circuit.scoring_scheme.substitution_matrix,
+        reference_commitment: state.reference_commitment,
+        target_commitment: state.target_commitment,
+        reference_length: circuit.reference_sequence_bases.len(),
+        target_length: circuit.target_sequence_bases.len(),
+        alignment_score: circuit.alignment_score,
+    };
+    (statement, IvcProof { num_segments: segments.len() as u64, proof: previous_proof })
+}
+
+/// Verifies `proof` against `statement`, checking a single proof whatever the number of segments.
+pub fn verify(vk: &IvcVerifyingKey, statement: &IvcStatement<StepField>, proof: &IvcProof) -> Result<(), IvcError> {
+    let wrap_verifying_key_hash = hash(&verifying_key_elements::<WrapCurve, StepField>(&vk.wrap), 1)[0];
+    let statement_hash = statement_hash(wrap_verifying_key_hash, proof.num_segments, &SegmentState::finished(statement));
+    match Groth16::<WrapCurve>::verify(&vk.wrap, &wrap_inputs(statement_hash), &proof.proof) {
+        Ok(true) => Ok(()),
+        _ => Err(IvcError::InvalidProof),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aligner::{Aligner, Objective};
+    use crate::alignment::MaxLengths;
+    use crate::commitment::commit_bases;
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::rand::{Rng, SeedableRng};
+    use ark_std::UniformRand;
+
+    fn random_alignment(rng: &mut impl Rng, length: usize) -> (Vec<usize>, Vec<usize>, Vec<usize>, i64) {
+        let reference: Vec<usize> = (0..length).map(|_| rng.gen_range(0..4)).collect();
+        let mut target = reference[5..length - 5].to_vec();
+        target[30] = (target[30] + 1) % 4;
+        target.drain(70..73);
+        target.insert(120, BASE_N);
+        let max_lengths = MaxLengths { reference: length, target: length, cigar: 2 * length };
+        let aligner = Aligner::new(ScoringScheme::bwa_mem(), Objective::Maximize).local();
+        let (_, circuit) = aligner.circuit::<Fr>(&reference, &target, &max_lengths).unwrap();
+        (circuit.reference_sequence_bases, circuit.target_sequence_bases, circuit.cigar_string_bases, circuit.alignment_score)
+    }
+
+    // Walks all segments in-circuit, checking each against the native transition.
+    fn walks_segments(segments: &[Segment], salts: [Fr; 2], challenges: Option<[Fr; 2]>) -> (SegmentState<Fr>, bool) {
+        let scheme = ScoringScheme::bwa_mem();
+        let fresh = |challenges| SegmentState::fresh(scheme.substitution_matrix_felts(), challenges, salts[0], salts[1]);
+        let challenges = challenges.unwrap_or_else(|| segments.iter().fold(fresh([Fr::from(0u64); 2]), |state, segment| state.apply(segment, &scheme)).derived_challenges());
+
+        let mut state = fresh(challenges);
+        let mut satisfied = true;
+        for segment in segments {
+            let mut next = state.apply(segment, &scheme);
+            if segment.finalize {
+                next = next.finalized();
+            }
+            let cs = ConstraintSystem::<Fr>::new_ref();
+            let index = FpVar::new_witness(cs.clone(), || Ok(Fr::from(segment.index))).unwrap();
+            let state_var = SegmentStateVar::new_witness(cs.clone(), &state).unwrap();
+            state_var.enforce_fresh(cs.clone(), salts, &index.is_zero().unwrap()).unwrap();
+            let next_var = enforce_segment(cs.clone(), &scheme, &index, &state_var, segment).unwrap();
+            assert_eq!(next_var.to_field_elements().value().unwrap(), next.to_field_elements());
+            satisfied &= cs.is_satisfied().unwrap();
+            state = next;
+        }
+        (state, satisfied)
+    }
+
+    #[test]
+    fn segments_carry_the_walk() {
+        let rng = &mut ark_std::test_rng();
+        let (reference, target, cigar, score) = random_alignment(rng, 200);
+        let segments = segments(&reference, &target, &cigar, 1);
+        assert_eq!(segments.len(), cigar.len().div_ceil(CIGAR_OPS_PER_BLOCK));
+        let salts = [Fr::rand(rng), Fr::rand(rng)];
+        let (state, satisfied) = walks_segments(&segments, salts, None);
+        assert!(satisfied);
+        assert!(state.finished);
+        assert_eq!(state.alignment_score, i64_to_felt::<Fr>(score));
+        assert_eq!(state.reference_index, Fr::from(reference.len() as u64));
+        assert_eq!(state.target_index, Fr::from(target.len() as u64));
+
+        // The commitments are those of AlignmentCircuit, however the sequences are split.
+        assert_eq!(state.reference_commitment, commit_bases(&reference, salts[0]));
+        assert_eq!(state.target_commitment, commit_bases(&target, salts[1]));
+    }
+
+    #[test]
+    fn rejects_forged_reads_and_challenges() {
+        let rng = &mut ark_std::test_rng();
+        let (reference, target, cigar, _) = random_alignment(rng, 200);
+        let mut segments = segments(&reference, &target, &cigar, 1);
+        // A mismatch read as a match scores higher, but is not what the target holds.
+        let (segment, op) = segments
+            .iter()
+            .enumerate()
+            .find_map(|(s, segment)| segment.cigar.iter().position(|&op| op == CIGAR_MISMATCH).map(|op| (s, op)))
+            .unwrap();
+        let forged = &mut segments[segment];
+        forged.cigar[op] = CIGAR_MATCH;
+        forged.target_reads[op] = forged.reference_reads[op];
+        let salts = [Fr::rand(rng), Fr::rand(rng)];
+        assert!(!walks_segments(&segments, salts, None).1);
+
+        let segments = super::segments(&reference, &target, &cigar, 1);
+        assert!(walks_segments(&segments, salts, None).1);
+        assert!(!walks_segments(&segments, salts, Some([Fr::from(1u64), Fr::from(2u64)])).1);
+    }
+
+    #[test]
+    #[ignore = "sets up and proves on the MNT cycle, which takes minutes; run with --release"]
+    fn folds_an_alignment_into_one_proof() {
+        let rng = &mut ark_std::rand::rngs::StdRng::seed_from_u64(0);
+        let reference: Vec<usize> = (0..120).map(|_| rng.gen_range(0..4)).collect();
+        let mut target = reference.clone();
+        target[50] = (target[50] + 1) % 4;
+        let mut cigar = vec![CIGAR_MATCH; 120];
+        cigar[50] = CIGAR_MISMATCH;
+        let scheme = ScoringScheme::bwa_mem();
+        let score = scheme.score(&reference, &target, &cigar);
+        let circuit = AlignmentCircuit::<StepField>::padded(reference, target, cigar, scheme, score, &MaxLengths { reference: 120, target: 120, cigar: 120 })
+            .with_salts(StepField::rand(rng), StepField::rand(rng));
+
+        let (pk, vk) = setup(scheme, 1, rng);
+        let (statement, proof) = prove(&pk, &circuit, rng);
+        assert_eq!(proof.num_segments, 2);
+        verify(&vk, &statement, &proof).unwrap();
+
+        let mut wrong_score = statement.clone();
+        wrong_score.alignment_score += 1;
+        assert_eq!(verify(&vk, &wrong_score, &proof), Err(IvcError::InvalidProof));
+        let wrong_count = IvcProof { num_segments: 1, ..proof.clone() };
+        assert_eq!(verify(&vk, &statement, &wrong_count), Err(IvcError::InvalidProof));
+    }
+}
@@ -0,0 +1,307 @@
+// Screens the k-mers of a committed sequence, such as a synthetic DNA order, without revealing the
+// sequence or where in it a k-mer occurs. A k-mer is coded as the integer whose 3-bit digits are its
+// bases, first base least significant, as bases are packed. The circuit either proves that no k-mer is
+// in a committed set of hazard k-mers, or that some k-mer equals a public one.
+//
+// For non-membership the hazards are sorted and stored as h + 1 in a read-only memory between the
+// sentinels 0 and 2^(3k) + 1, and the circuit enforces that the cells are increasing. Every k-mer x
+// then reads two neighbouring cells at a private index and shows cell_i < x + 1 < cell_{i+1} with
+// range checks, so x falls strictly between two hazards. Memory checking costs one pass over the set
+// rather than a Merkle path per k-mer, which suits sets up to the size of a long order.
+
+use ark_crypto_primitives::sponge::constraints::CryptographicSpongeVar;
+use ark_crypto_primitives::sponge::poseidon::constraints::PoseidonSpongeVar;
+use ark_crypto_primitives::sponge::poseidon::PoseidonSponge;
+use ark_crypto_primitives::sponge::{Absorb, CryptographicSponge};
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+
+use crate::alignment::{pack_bases_to, BASES_PER_BLOCK, BITS_PER_BASE};
+use crate::commitment::{commit_bases, unpack_committed_sequence};
+use crate::memory::{MemoryCell, ReadOnlyMemoryVar};
+use crate::poseidon::poseidon_parameters;
+
+/// Longest k-mer whose code, shifted past the sentinels, fits in a u128.
+pub const MAX_K: usize = 42;
+
+/// What a screening proof shows about the k-mers of the sequence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KmerClaim<F: PrimeField> {
+    Avoids { hazards: Vec<u128>, hazard_salt: F }, // sorted distinct codes, their number is fixed at setup time
+    Contains(u128), // the code of a public k-mer
+}
+
+#[derive(Clone)]
+pub struct KmerScreeningCircuit<F: PrimeField> {
+    pub sequence_felts: Vec<F>, // packed to the maximum length
+    pub sequence_bases: Vec<usize>,
+    pub sequence_salt: F,
+    pub k: usize, // fixed at setup time
+    pub claim: KmerClaim<F>,
+}
+
+/// Code of a k-mer, as computed by the circuit.
+pub fn kmer_code(bases: &[usize]) -> u128 {
+    bases.iter().rev().fold(0, |code, &base| (code << BITS_PER_BASE) | base as u128)
+}
+
+/// Codes of every k-mer of `bases`, in order of position.
+pub fn kmer_codes(bases: &[usize], k: usize) -> Vec<u128> {
+    if bases.len() < k {
+        return Vec::new();
+    }
+    bases.windows(k).map(kmer_code).collect()
+}
+
+/// The sorted distinct codes of the k-mers of `sequences`, as committed for a hazard database.
+pub fn kmer_set(sequences: &[Vec<usize>], k: usize) -> Vec<u128> {
+    let mut codes: Vec<u128> = sequences.iter().flat_map(|sequence| kmer_codes(sequence, k)).collect();
+    codes.sort_unstable();
+    codes.dedup();
+    codes
+}
+
+/// Commitment to a set of hazard codes.
+pub fn commit_kmer_set<F: PrimeField + Absorb>(hazards: &[u128], salt: F) -> F {
+    let mut sponge = PoseidonSponge::<F>::new(&poseidon_parameters());
+    sponge.absorb(&salt);
+    sponge.absorb(&hazards.iter().map(|&code| F::from(code)).collect::<Vec<_>>());
+    sponge.squeeze_field_elements::<F>(1)[0]
+}
+
+impl<F: PrimeField> KmerScreeningCircuit<F> {
+    /// Packs `sequence_bases` for a sequence of at most `max_length` bases, or returns None if the claim
+    /// does not hold for its k-mers.
+    pub fn new(sequence_bases: Vec<usize>, k: usize, max_length: usize, claim: KmerClaim<F>) -> Option<Self> {
+        assert!((1..=MAX_K).contains(&k), "k-mers must have between 1 and MAX_K bases");
+        assert!(sequence_bases.len() <= max_length, "sequence is longer than the circuit allows");
+        if let KmerClaim::Avoids { hazards, .. } = &claim {
+            assert!(hazards.windows(2).all(|pair| pair[0] < pair[1]), "hazards must be sorted and distinct");
+        }
+        let codes = kmer_codes(&sequence_bases, k);
+        let holds = match &claim {
+            KmerClaim::Avoids { hazards, .. } => codes.iter().all(|code| hazards.binary_search(code).is_err()),
+            KmerClaim::Contains(code) => codes.contains(code),
+        };
+        holds.then(|| Self {
+            sequence_felts: pack_bases_to(&sequence_bases, max_length),
+            sequence_bases,
+            sequence_salt: F::zero(),
+            k,
+            claim,
+        })
+    }
+
+    pub fn with_sequence_salt(mut self, sequence_salt: F) -> Self {
+        self.sequence_salt = sequence_salt;
+        self
+    }
+
+    // Bits of a code shifted past the lower sentinel, and of the gap between two such codes.
+    fn shifted_code_bits(&self) -> usize {
+        BITS_PER_BASE * self.k + 2
+    }
+}
+
+impl<F: PrimeField + Absorb> KmerScreeningCircuit<F> {
+    /// Public inputs in the order the circuit allocates them.
+    pub fn public_inputs(&self) -> Vec<F> {
+        let mut inputs = vec![
+            commit_bases(&self.sequence_bases, self.sequence_salt),
+            F::from(self.sequence_bases.len() as u64),
+        ];
+        match &self.claim {
+            KmerClaim::Avoids { hazards, hazard_salt } => inputs.push(commit_kmer_set(hazards, *hazard_salt)),
+            KmerClaim::Contains(code) => inputs.push(F::from(*code)),
+        }
+        inputs
+    }
+}
+
+/// Enforces that `value` fits in `num_bits` bits, given its native value.
+fn enforce_fits<F: PrimeField>(cs: ConstraintSystemRef<F>, value: &FpVar<F>, native: u128, num_bits: usize) -> Result<(), SynthesisError> {
+    let mut bits = Vec::new();
+    for j in 0..num_bits {
+        bits.push(Boolean::new_witness(cs.clone(), || Ok((native >> j) & 1 == 1))?);
+    }
+    Boolean::le_bits_to_fp_var(&bits)?.enforce_equal(value)
+}
+
+impl<F: PrimeField> ConstraintSynthesizer<F> for KmerScreeningCircuit<F> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+        let mut sequence_vars = Vec::new();
+        for elem in self.sequence_felts.iter() {
+            sequence_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+
+        let sequence = unpack_committed_sequence(cs.clone(), &sequence_vars, self.sequence_bases.len(), self.sequence_salt)?;
+        for public in [&sequence.commitment, &sequence.length] {
+            FpVar::new_input(cs.clone(), || public.value())?.enforce_equal(public)?;
+        }
+
+        // The k-mer at every position that fits in the maximum length, present iff its last base is.
+        let max_length = self.sequence_felts.len() * BASES_PER_BLOCK;
+        let positions = (max_length + 1).saturating_sub(self.k);
+        let native_codes = kmer_codes(&self.sequence_bases, self.k);
+        let mut kmers = Vec::new();
+        for p in 0..positions {
+            let mut code = FpVar::zero();
+            for (t, base) in sequence.bases[p..p + self.k].iter().enumerate() {
+                code += &base.value * F::from(1u128 << (BITS_PER_BASE * t));
+            }
+            let present = sequence.bases[p + self.k - 1].is_padding.not();
+            kmers.push((code, present, native_codes.get(p).copied()));
+        }
+
+        match &self.claim {
+            KmerClaim::Contains(claimed) => {
+                let claimed_var = FpVar::new_input(cs.clone(), || Ok(F::from(*claimed)))?;
+                let position = native_codes.iter().position(|code| code == claimed);
+                let mut one_hot_sum = FpVar::zero();
+                let mut selected = FpVar::zero();
+                for (p, (code, present, _)) in kmers.iter().enumerate() {
+                    let bit = Boolean::new_witness(cs.clone(), || Ok(position == Some(p)))?;
+                    bit.and(&present.not())?.enforce_equal(&Boolean::FALSE)?;
+                    one_hot_sum += FpVar::from(bit.clone());
+                    selected += FpVar::from(bit) * code;
+                }
+                one_hot_sum.enforce_equal(&FpVar::one())?;
+                selected.enforce_equal(&claimed_var)?;
+            }
+            KmerClaim::Avoids { hazards, hazard_salt } => {
+                let gap_bits = self.shifted_code_bits() - 1;
+                let upper_sentinel = (1u128 << (BITS_PER_BASE * self.k)) + 1;
+                let mut hazard_vars = Vec::new();
+                for &code in hazards.iter() {
+                    hazard_vars.push(FpVar::new_witness(cs.clone(), || Ok(F::from(code)))?);
+                }
+                let hazard_salt_var = FpVar::new_witness(cs.clone(), || Ok(*hazard_salt))?;
+                let params = poseidon_parameters();
+                let mut hazard_sponge = PoseidonSpongeVar::new(cs.clone(), &params);
+                hazard_sponge.absorb(&hazard_salt_var)?;
+                hazard_sponge.absorb(&hazard_vars)?;
+                let hazard_commitment = hazard_sponge.squeeze_field_elements(1)?.remove(0);
+                let hazard_commitment_var = FpVar::new_input(cs.clone(), || hazard_commitment.value())?;
+                hazard_commitment_var.enforce_equal(&hazard_commitment)?;
+
+                // Cells hold the shifted hazards between the sentinels, each above the one before.
+                let mut cell_values = vec![FpVar::zero()];
+                cell_values.extend(hazard_vars.iter().map(|hazard| hazard + F::one()));
+                cell_values.push(FpVar::constant(F::from(upper_sentinel)));
+                let mut native_cells = vec![0];
+                native_cells.extend(hazards.iter().map(|code| code + 1));
+                native_cells.push(upper_sentinel);
+                for (pair, native_pair) in cell_values.windows(2).zip(native_cells.windows(2)) {
+                    enforce_fits(cs.clone(), &(&pair[1] - &pair[0] - F::one()), native_pair[1].wrapping_sub(native_pair[0] + 1), gap_bits)?;
+                }
+                let cells = cell_values
+                    .iter()
+                    .enumerate()
+                    .map(|(j, value)| MemoryCell { address: FpVar::constant(F::from(j as u64)), value: value.clone(), present: FpVar::one() })
+                    .collect();
+                let mut memory = ReadOnlyMemoryVar::new(cs.clone(), cells, self.shifted_code_bits(), 2 * positions as u64);
+
+                // Each k-mer reads the cells around it at an index whose bits go into the transcript.
+                let index_bits = (usize::BITS - hazards.len().leading_zeros()) as usize;
+                let mut chosen_bits = Vec::new();
+                for (code, present, native) in kmers.iter() {
+                    let index = native.map_or(0, |code| hazards.partition_point(|&hazard| hazard < code));
+                    let mut bits = Vec::new();
+                    for j in 0..index_bits {
+                        bits.push(Boolean::new_witness(cs.clone(), || Ok((index >> j) & 1 == 1))?);
+                    }
+                    let index_var = Boolean::le_bits_to_fp_var(&bits)?;
+                    chosen_bits.extend(bits);
+                    let below = memory.read(&index_var, present)?;
+                    let above = memory.read(&(&index_var + F::one()), present)?;
+
+                    // Absent k-mers check nothing.
+                    let shifted = code + F::one();
+                    let enabled = FpVar::from(present.clone());
+                    let (native_below, native_above) = (native_cells[index], native_cells[index + 1]);
+                    let native_shifted = native.map_or(0, |code| code + 1);
+                    enforce_fits(cs.clone(), &(&enabled * (&shifted - &below - F::one())), native_shifted.wrapping_sub(native_below + 1) * native.is_some() as u128, gap_bits)?;
+                    enforce_fits(cs.clone(), &(&enabled * (&above - &shifted - F::one())), native_above.wrapping_sub(native_shifted + 1) * native.is_some() as u128, gap_bits)?;
+                }
+
+                let mut transcript = vec![sequence.commitment.clone(), sequence.length.clone(), hazard_commitment_var];
+                for chunk in chosen_bits.chunks(F::MODULUS_BIT_SIZE as usize - 1) {
+                    transcript.push(Boolean::le_bits_to_fp_var(chunk)?);
+                }
+                memory.finalize(&transcript)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sam::encode_sequence;
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::UniformRand;
+
+    fn is_satisfied(circuit: KmerScreeningCircuit<Fr>) -> bool {
+        let cs = ConstraintSystem::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn codes_kmers_as_packed() {
+        let bases = encode_sequence(b"ACGTNA").unwrap();
+        assert_eq!(kmer_code(&bases[..3]), 0b010_001_000);
+        assert_eq!(kmer_codes(&bases, 5), vec![kmer_code(&bases[..5]), kmer_code(&bases[1..])]);
+        assert!(kmer_codes(&bases, 7).is_empty());
+        let set = kmer_set(&[bases.clone(), bases[..4].to_vec()], 3);
+        assert_eq!(set.len(), 4);
+        assert!(set.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn screens_an_order_against_hazards() {
+        let rng = &mut ark_std::test_rng();
+        let k = 6;
+        let hazard_sequences = [encode_sequence(b"TTGACCATGGCAAT").unwrap(), encode_sequence(b"GGGCCCAAATTT").unwrap()];
+        let hazards = kmer_set(&hazard_sequences, k);
+        let hazard_salt = Fr::rand(rng);
+        let order = encode_sequence(b"ACGTACGTNNACGTTGCAGGATCCAGT").unwrap();
+        let avoids = KmerClaim::Avoids { hazards: hazards.clone(), hazard_salt };
+
+        let circuit = KmerScreeningCircuit::<Fr>::new(order.clone(), k, 100, avoids.clone()).unwrap().with_sequence_salt(Fr::rand(rng));
+        let cs = ConstraintSystem::new_ref();
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.borrow().unwrap().instance_assignment[1..], circuit.public_inputs()[..]);
+
+        // An order carrying a hazard k-mer has no proof, and forcing one fails.
+        let mut tainted = order.clone();
+        tainted.splice(10..10, hazard_sequences[1][3..9].iter().copied());
+        assert!(KmerScreeningCircuit::<Fr>::new(tainted.clone(), k, 100, avoids.clone()).is_none());
+        let mut forced = circuit.clone();
+        forced.sequence_felts = pack_bases_to(&tainted, 100);
+        forced.sequence_bases = tainted.clone();
+        assert!(!is_satisfied(forced));
+
+        // So does a hazard set that is not sorted.
+        let mut unsorted = circuit.clone();
+        let mut swapped = hazards.clone();
+        swapped.swap(0, 1);
+        unsorted.claim = KmerClaim::Avoids { hazards: swapped, hazard_salt };
+        assert!(!is_satisfied(unsorted));
+
+        // The tainted order does contain the hazard k-mer, wherever it is.
+        let code = kmer_code(&hazard_sequences[1][3..9]);
+        let contains = KmerScreeningCircuit::<Fr>::new(tainted, k, 100, KmerClaim::Contains(code)).unwrap();
+        let cs = ConstraintSystem::new_ref();
+        contains.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.borrow().unwrap().instance_assignment[1..], contains.public_inputs()[..]);
+        assert!(KmerScreeningCircuit::<Fr>::new(order, k, 100, KmerClaim::Contains(code)).is_none());
+    }
+}
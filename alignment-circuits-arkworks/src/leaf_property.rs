@@ -0,0 +1,295 @@
+// Zero-knowledge openings of the Merkle genome commitment: instead of revealing the bases and salt of a
+// leaf, the prover reveals only its salted commitment, which is authenticated by the Merkle path as
+// usual, and a Groth16 proof that the bases behind it have a public property. The circuit reuses the
+// salted commitment gadget of the alignment circuits, so its shape only depends on the leaf size and
+// the kind of property.
+
+use ark_bls12_381::{Bls12_381, Fr};
+use ark_crypto_primitives::snark::{CircuitSpecificSetupSNARK, SNARK};
+use ark_crypto_primitives::sponge::Absorb;
+use ark_ff::PrimeField;
+use ark_groth16::{Groth16, ProvingKey, VerifyingKey};
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use ark_std::rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::alignment::{base_lagrange_basis, pack_bases_to, BASE_A, BASE_C, BASE_G, NUM_BASES};
+use crate::commitment::{commit_bases, unpack_committed_sequence};
+use crate::genome_commitment::{salt_from_words, salt_to_words, CommittedGenome, GenomeCommitment, GenomeError, GenomeRegion, Val};
+
+// Bits of the slack between the GC count and its public bounds.
+const GC_SLACK_BITS: usize = 32;
+
+/// A statement about the bases of one leaf.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LeafProperty {
+    BaseIn { offset: usize, bases: Vec<usize> }, // the base at `offset` in the leaf is one of `bases`
+    GcCountBetween { min: usize, max: usize },   // min <= number of C and G bases <= max
+}
+
+impl LeafProperty {
+    /// The property that the base at `region`'s first position is one of `bases`, with the leaf it
+    /// concerns.
+    pub fn base_in(commitment: &GenomeCommitment, region: &GenomeRegion, bases: Vec<usize>) -> Result<(usize, Self), GenomeError> {
+        let (index, offset) = commitment.locate_in_leaf(region)?;
+        Ok((index, LeafProperty::BaseIn { offset, bases }))
+    }
+
+    pub fn holds(&self, leaf: &[usize]) -> bool {
+        match self {
+            LeafProperty::BaseIn { offset, bases } => leaf.get(*offset).is_some_and(|base| bases.contains(base)),
+            LeafProperty::GcCountBetween { min, max } => {
+                let count = leaf.iter().filter(|&&base| base == BASE_C || base == BASE_G).count();
+                (*min..=*max).contains(&count)
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct LeafPropertyCircuit<F: PrimeField> {
+    pub leaf_felts: Vec<F>, // packed and padded to the leaf size
+    pub leaf_bases: Vec<usize>,
+    pub salt: F,
+    pub property: LeafProperty,
+}
+
+impl<F: PrimeField> LeafPropertyCircuit<F> {
+    pub fn new(leaf_bases: Vec<usize>, salt: F, property: LeafProperty, leaf_size: usize) -> Self {
+        assert!(leaf_bases.len() <= leaf_size, "leaf is longer than the circuit allows");
+        Self {
+            leaf_felts: pack_bases_to(&leaf_bases, leaf_size),
+            leaf_bases,
+            salt,
+            property,
+        }
+    }
+
+    /// A circuit of the same shape as any for `leaf_size` and properties of the same kind, for setup.
+    pub fn blank(leaf_size: usize, property: &LeafProperty) -> Self {
+        let property = match property {
+            LeafProperty::BaseIn { .. } => LeafProperty::BaseIn { offset: 0, bases: vec![BASE_A] },
+            LeafProperty::GcCountBetween { .. } => LeafProperty::GcCountBetween { min: 0, max: leaf_size },
+        };
+        Self::new(vec![BASE_A; leaf_size], F::zero(), property, leaf_size)
+    }
+}
+
+impl<F: PrimeField + Absorb> LeafPropertyCircuit<F> {
+    /// Public inputs in the order the circuit allocates them: the leaf commitment and length, then the
+    /// offset and a flag per base for `BaseIn`, or the bounds for `GcCountBetween`.
+    pub fn public_inputs(&self) -> Vec<F> {
+        let mut inputs = vec![commit_bases(&self.leaf_bases, self.salt), F::from(self.leaf_bases.len() as u64)];
+        match &self.property {
+            LeafProperty::BaseIn { offset, bases } => {
+                inputs.push(F::from(*offset as u64));
+                inputs.extend((0..NUM_BASES).map(|base| F::from(bases.contains(&base))));
+            }
+            LeafProperty::GcCountBetween { min, max } => inputs.extend([F::from(*min as u64), F::from(*max as u64)]),
+        }
+        inputs
+    }
+}
+
+impl<F: PrimeField> ConstraintSynthesizer<F> for LeafPropertyCircuit<F> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+        let mut leaf_vars = Vec::new();
+        for elem in self.leaf_felts.iter() {
+            leaf_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+        let leaf = unpack_committed_sequence(cs.clone(), &leaf_vars, self.leaf_bases.len(), self.salt)?;
+        let commitment_var = FpVar::new_input(cs.clone(), || leaf.commitment.value())?;
+        commitment_var.enforce_equal(&leaf.commitment)?;
+        let length_var = FpVar::new_input(cs.clone(), || leaf.length.value())?;
+        length_var.enforce_equal(&leaf.length)?;
+
+        match &self.property {
+            LeafProperty::BaseIn { offset, bases } => {
+                let offset_var = FpVar::new_input(cs.clone(), || Ok(F::from(*offset as u64)))?;
+                let mut allowed_vars = Vec::new();
+                for base in 0..NUM_BASES {
+                    allowed_vars.push(Boolean::new_input(cs.clone(), || Ok(bases.contains(&base)))?);
+                }
+                // Select the base at the offset, which must be before the end of the leaf.
+                let mut at_offset_count = FpVar::zero();
+                let mut selected_base = FpVar::zero();
+                let mut selected_is_padding = FpVar::zero();
+                for (k, base) in leaf.bases.iter().enumerate() {
+                    let at_offset = offset_var.is_eq(&FpVar::constant(F::from(k as u64)))?;
+                    at_offset_count += FpVar::from(at_offset.clone());
+                    selected_base += FpVar::from(at_offset.clone()) * &base.value;
+                    selected_is_padding += FpVar::from(at_offset.and(&base.is_padding)?);
+                }
+                at_offset_count.enforce_equal(&FpVar::one())?;
+                selected_is_padding.enforce_equal(&FpVar::zero())?;
+                let mut is_allowed = FpVar::zero();
+                for (indicator, allowed) in base_lagrange_basis(&selected_base)?.iter().zip(allowed_vars.iter()) {
+                    is_allowed += FpVar::from(allowed.clone()) * indicator;
+                }
+                is_allowed.enforce_equal(&FpVar::one())?;
+            }
+            LeafProperty::GcCountBetween { min, max } => {
+                let min_var = FpVar::new_input(cs.clone(), || Ok(F::from(*min as u64)))?;
+                let max_var = FpVar::new_input(cs.clone(), || Ok(F::from(*max as u64)))?;
+                let mut gc_count = FpVar::zero();
+                for base in leaf.bases.iter() {
+                    let basis = base_lagrange_basis(&base.value)?;
+                    gc_count += base.is_padding.select(&FpVar::zero(), &(&basis[BASE_C] + &basis[BASE_G]))?;
+                }
+                let count = self.leaf_bases.iter().filter(|&&base| base == BASE_C || base == BASE_G).count() as u64;
+                for (slack, difference) in [(count.wrapping_sub(*min as u64), &gc_count - &min_var), ((*max as u64).wrapping_sub(count), &max_var - &gc_count)] {
+                    let mut slack_bits = Vec::new();
+                    for k in 0..GC_SLACK_BITS {
+                        slack_bits.push(Boolean::new_witness(cs.clone(), || Ok((slack >> k) & 1 == 1))?);
+                    }
+                    Boolean::le_bits_to_fp_var(&slack_bits)?.enforce_equal(&difference)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A leaf commitment, its authentication path and a proof that the bases behind it have `property`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PropertyOpening {
+    pub index: usize,
+    pub commitment: [u64; 4],
+    pub siblings: Vec<[Val; 8]>,
+    pub property: LeafProperty,
+    pub proof: Vec<u8>, // compressed Groth16 proof
+}
+
+/// Groth16 keys for properties of the same kind as `property` on leaves of `leaf_size` bases.
+pub fn setup_leaf_property<R: RngCore + CryptoRng>(
+    leaf_size: usize,
+    property: &LeafProperty,
+    rng: &mut R,
+) -> (ProvingKey<Bls12_381>, VerifyingKey<Bls12_381>) {
+    Groth16::<Bls12_381>::setup(LeafPropertyCircuit::<Fr>::blank(leaf_size, property), rng).unwrap()
+}
+
+impl CommittedGenome {
+    /// Proves that leaf `index` has `property` without revealing its bases.
+    pub fn prove_property<R: RngCore + CryptoRng>(
+        &self,
+        proving_key: &ProvingKey<Bls12_381>,
+        index: usize,
+        property: LeafProperty,
+        rng: &mut R,
+    ) -> Result<PropertyOpening, GenomeError> {
+        if index >= self.commitment.num_leaves {
+            return Err(GenomeError::MissingLeaf(index));
+        }
+        let (bases, salt) = self.leaf(index);
+        if !property.holds(&bases) {
+            return Err(GenomeError::InvalidOpening(format!("leaf {} does not have property {:?}", index, property)));
+        }
+        let circuit = LeafPropertyCircuit::new(bases, salt, property.clone(), self.commitment.leaf_size);
+        let commitment = circuit.public_inputs()[0];
+        let proof = Groth16::<Bls12_381>::prove(proving_key, circuit, rng).map_err(|err| GenomeError::InvalidOpening(err.to_string()))?;
+        let mut proof_bytes = Vec::new();
+        proof.serialize_compressed(&mut proof_bytes).map_err(|err| GenomeError::Serialization(err.to_string()))?;
+        Ok(PropertyOpening {
+            index,
+            commitment: salt_to_words(commitment),
+            siblings: self.siblings(index),
+            property,
+            proof: proof_bytes,
+        })
+    }
+}
+
+impl GenomeCommitment {
+    /// Checks the leaf commitment of `opening` against the root and its property proof against it.
+    pub fn verify_property(&self, verifying_key: &VerifyingKey<Bls12_381>, opening: &PropertyOpening) -> Result<(), GenomeError> {
+        let commitment = salt_from_words(opening.commitment).ok_or_else(|| GenomeError::InvalidOpening("leaf commitment is not a field element".to_string()))?;
+        self.verify_leaf_commitment(opening.index, commitment, &opening.siblings)?;
+
+        let mut public_inputs = vec![commitment, Fr::from(self.leaf_length(opening.index) as u64)];
+        match &opening.property {
+            LeafProperty::BaseIn { offset, bases } => {
+                public_inputs.push(Fr::from(*offset as u64));
+                public_inputs.extend((0..NUM_BASES).map(|base| Fr::from(bases.contains(&base))));
+            }
+            LeafProperty::GcCountBetween { min, max } => public_inputs.extend([Fr::from(*min as u64), Fr::from(*max as u64)]),
+        }
+        let proof = CanonicalDeserialize::deserialize_compressed(opening.proof.as_slice()).map_err(|err| GenomeError::Serialization(err.to_string()))?;
+        match Groth16::<Bls12_381>::verify(verifying_key, &public_inputs, &proof) {
+            Ok(true) => Ok(()),
+            _ => Err(GenomeError::InvalidOpening(format!("property proof for leaf {} does not verify", opening.index))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::BASE_T;
+    use crate::sam::FastaReference;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::rand::rngs::StdRng;
+    use ark_std::rand::SeedableRng;
+
+    fn is_satisfied(circuit: LeafPropertyCircuit<Fr>) -> bool {
+        let cs = ConstraintSystem::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn constrains_leaf_properties() {
+        let leaf = vec![BASE_A, BASE_C, BASE_G, BASE_T, BASE_C];
+        let salt = Fr::from(7u64);
+        let base_in = |offset, bases: Vec<usize>| LeafPropertyCircuit::new(leaf.clone(), salt, LeafProperty::BaseIn { offset, bases }, 8);
+        let circuit = base_in(2, vec![BASE_A, BASE_G]);
+        let cs = ConstraintSystem::new_ref();
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.borrow().unwrap().instance_assignment[1..], circuit.public_inputs()[..]);
+        assert!(!is_satisfied(base_in(2, vec![BASE_C, BASE_T])));
+        // Offsets past the end of the leaf or of the circuit select nothing.
+        assert!(!is_satisfied(base_in(6, vec![BASE_A, BASE_C, BASE_G, BASE_T])));
+        assert!(!is_satisfied(base_in(8, vec![BASE_A, BASE_C, BASE_G, BASE_T])));
+
+        let gc = |min, max| LeafPropertyCircuit::new(leaf.clone(), salt, LeafProperty::GcCountBetween { min, max }, 8);
+        assert!(is_satisfied(gc(3, 3)));
+        assert!(is_satisfied(gc(0, 8)));
+        assert!(!is_satisfied(gc(4, 8)));
+        assert!(!is_satisfied(gc(0, 2)));
+    }
+
+    #[test]
+    fn proves_properties_of_committed_leaves() {
+        let rng = &mut StdRng::seed_from_u64(15);
+        let fasta = FastaReference::parse(">chr1\nACGTACGTACGTNNACGT\n>chr2\nTTTTGGGGCCCCAAAAACG\n").unwrap();
+        let genome = CommittedGenome::commit(&fasta, 8, rng).unwrap();
+
+        // chr2:5 is the G of TTTTG, in the last but one leaf.
+        let (index, property) = LeafProperty::base_in(&genome.commitment, &GenomeRegion::position("chr2", 5), vec![BASE_G, BASE_T]).unwrap();
+        assert_eq!((index, &property), (2, &LeafProperty::BaseIn { offset: 6, bases: vec![BASE_G, BASE_T] }));
+        let (proving_key, verifying_key) = setup_leaf_property(8, &property, rng);
+        let opening = genome.prove_property(&proving_key, index, property, rng).unwrap();
+        genome.commitment.verify_property(&verifying_key, &opening).unwrap();
+
+        // The same keys prove other positions, including in the short last leaf.
+        let (index, property) = LeafProperty::base_in(&genome.commitment, &GenomeRegion::position("chr2", 18), vec![BASE_C]).unwrap();
+        assert_eq!(index, 4);
+        let last = genome.prove_property(&proving_key, index, property, rng).unwrap();
+        genome.commitment.verify_property(&verifying_key, &last).unwrap();
+
+        // A false property cannot be proven, and a proof does not carry over to another claim or leaf.
+        let (_, false_property) = LeafProperty::base_in(&genome.commitment, &GenomeRegion::position("chr2", 5), vec![BASE_A]).unwrap();
+        assert!(genome.prove_property(&proving_key, 2, false_property.clone(), rng).is_err());
+        let mut other_claim = opening.clone();
+        other_claim.property = false_property;
+        assert!(genome.commitment.verify_property(&verifying_key, &other_claim).is_err());
+        let mut other_leaf = opening;
+        other_leaf.index = 1;
+        assert!(genome.commitment.verify_property(&verifying_key, &other_leaf).is_err());
+    }
+}
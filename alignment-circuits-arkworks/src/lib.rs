@@ -1,7 +1,28 @@
+pub mod aligner;
+pub mod alignment;
+pub mod artifact;
+pub mod batch;
+pub mod commitment;
+pub mod edit_distance;
+pub mod genome_commitment;
+pub mod ivc;
+pub mod kmer;
+pub mod leaf_property;
+pub mod memory;
+pub mod msa;
+pub mod panel;
+pub mod poseidon;
+pub mod reference_tree;
+pub mod sam;
+pub mod stark;
+pub mod variant;
+pub mod window;
+
 use ark_ff::PrimeField;
 use ark_crypto_primitives::sponge::poseidon::PoseidonConfig;
 
-/// Generate default parameters (bls381-fr-only) for alpha = 17, state-size = 8
+/// Hard-coded parameters (bls381-fr-only) for alpha = 17, state-size = 8, kept for compatibility. The
+/// circuits use the generated `poseidon::poseidon_parameters`.
 pub fn poseidon_parameters_for_test<F: PrimeField>() -> PoseidonConfig<F> {
     let alpha = 17;
     let mds = vec![
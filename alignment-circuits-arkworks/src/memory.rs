@@ -0,0 +1,234 @@
+// Offline memory checking for read-only memory, as in Spartan's Spark. Every cell starts out with
+// timestamp 0, a read of a cell at timestamp t writes it back with timestamp t + 1, and once all reads
+// are done the multiset of initial and written (address, value, timestamp) tuples must equal the
+// multiset of read and final tuples. The multisets are compared with grand products of fingerprints.
+//
+// The fingerprint challenges are derived from the caller's transcript, which has to fix the memory
+// contents and the read addresses, together with every value and timestamp the prover chose for the
+// reads. Otherwise a prover who knows the challenges could solve for a read value that balances the
+// products.
+
+use std::collections::HashMap;
+
+use ark_crypto_primitives::sponge::constraints::CryptographicSpongeVar;
+use ark_crypto_primitives::sponge::poseidon::constraints::PoseidonSpongeVar;
+use ark_ff::{BigInteger, PrimeField};
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSystemRef, SynthesisError};
+
+use crate::alignment::{UnpackedBase, BITS_PER_BASE};
+use crate::poseidon::poseidon_parameters;
+
+/// A cell of a read-only memory. Addresses of the present cells must be distinct.
+pub struct MemoryCell<F: PrimeField> {
+    pub address: FpVar<F>,
+    pub value: FpVar<F>,
+    pub present: FpVar<F>, // 1 if the cell is part of the memory, 0 for padding
+}
+
+struct MemoryRead<F: PrimeField> {
+    address: FpVar<F>,
+    value_bits: Vec<Boolean<F>>,
+    timestamp_bits: Vec<Boolean<F>>,
+    enabled: Boolean<F>,
+}
+
+/// Anything the CIGAR walk can read bases from. How the reads are checked is up to the implementation.
+pub trait MemoryReader<F: PrimeField> {
+    /// Reads the cell at `address` if `enabled`, returning its value.
+    fn read(&mut self, address: &FpVar<F>, enabled: &Boolean<F>) -> Result<FpVar<F>, SynthesisError>;
+}
+
+/// A read-only memory whose reads are checked all at once by `finalize`.
+pub struct ReadOnlyMemoryVar<F: PrimeField> {
+    cs: ConstraintSystemRef<F>,
+    cells: Vec<MemoryCell<F>>,
+    value_bits: usize, // values are below 2^value_bits
+    timestamp_bits: usize, // read timestamps are below 2^timestamp_bits, final ones at most one more
+    max_reads_per_cell: u64,
+    contents: HashMap<F, F>, // native address to value of the present cells, empty during setup
+    read_counts: HashMap<F, u64>,
+    reads: Vec<MemoryRead<F>>,
+}
+
+fn bit_length(x: u64) -> usize {
+    (u64::BITS - x.leading_zeros()) as usize
+}
+
+fn witness_bits<F: PrimeField>(cs: ConstraintSystemRef<F>, value: Option<u64>, num_bits: usize) -> Result<Vec<Boolean<F>>, SynthesisError> {
+    witness_field_bits(cs, value.map(F::from), num_bits)
+}
+
+fn witness_field_bits<F: PrimeField>(cs: ConstraintSystemRef<F>, value: Option<F>, num_bits: usize) -> Result<Vec<Boolean<F>>, SynthesisError> {
+    let value_bits = value.map(|value| value.into_bigint().to_bits_le());
+    let mut bits = Vec::with_capacity(num_bits);
+    for j in 0..num_bits {
+        bits.push(Boolean::new_witness(cs.clone(), || value_bits.as_ref().map(|bits| bits[j]).ok_or(SynthesisError::AssignmentMissing))?);
+    }
+    Ok(bits)
+}
+
+impl<F: PrimeField> ReadOnlyMemoryVar<F> {
+    /// A memory holding `cells`, whose values fit in `value_bits` bits. The shape of the circuit depends
+    /// on `max_reads_per_cell`, which bounds how often a single cell may be read.
+    pub fn new(cs: ConstraintSystemRef<F>, cells: Vec<MemoryCell<F>>, value_bits: usize, max_reads_per_cell: u64) -> Self {
+        let mut contents = HashMap::new();
+        for cell in cells.iter() {
+            if let (Ok(address), Ok(value), Ok(present)) = (cell.address.value(), cell.value.value(), cell.present.value())
+                && present.is_one()
+            {
+                contents.insert(address, value);
+            }
+        }
+        Self {
+            cs,
+            cells,
+            value_bits,
+            timestamp_bits: bit_length(max_reads_per_cell.saturating_sub(1)),
+            max_reads_per_cell,
+            contents,
+            read_counts: HashMap::new(),
+            reads: Vec::new(),
+        }
+    }
+
+    /// A memory holding the unpadded slots of an unpacked sequence at addresses 0, 1, ...
+    pub fn from_bases(cs: ConstraintSystemRef<F>, bases: &[UnpackedBase<F>], max_reads_per_cell: u64) -> Self {
+        let cells = bases
+            .iter()
+            .enumerate()
+            .map(|(i, base)| MemoryCell {
+                address: FpVar::constant(F::from(i as u64)),
+                value: base.value.clone(),
+                present: FpVar::from(base.is_padding.not()),
+            })
+            .collect();
+        Self::new(cs, cells, BITS_PER_BASE, max_reads_per_cell)
+    }
+
+    /// Reads the cell at `address` if `enabled`, returning its value. A disabled read returns an
+    /// unconstrained value below 2^value_bits and is left out of the check.
+    pub fn read(&mut self, address: &FpVar<F>, enabled: &Boolean<F>) -> Result<FpVar<F>, SynthesisError> {
+        let mut native = None;
+        if let (Ok(address), Ok(enabled)) = (address.value(), enabled.value()) {
+            native = Some((F::zero(), 0));
+            if enabled {
+                let count = self.read_counts.entry(address).or_insert(0);
+                let value = self.contents.get(&address).copied().unwrap_or_default();
+                native = Some((value, *count));
+                *count += 1;
+            }
+        }
+        let value_bits = witness_field_bits(self.cs.clone(), native.map(|(value, _)| value), self.value_bits)?;
+        let timestamp_bits = witness_bits(self.cs.clone(), native.map(|(_, timestamp)| timestamp), self.timestamp_bits)?;
+        let value = Boolean::le_bits_to_fp_var(&value_bits)?;
+        self.reads.push(MemoryRead { address: address.clone(), value_bits, timestamp_bits, enabled: enabled.clone() });
+        Ok(value)
+    }
+
+    /// Enforces that every enabled read returned the value of a present cell. `transcript` must fix the
+    /// cells and the read addresses, for instance by holding commitments to them. Fails with
+    /// `SynthesisError::Unsatisfiable` if a cell was read more often than allowed.
+    pub fn finalize(self, transcript: &[FpVar<F>]) -> Result<(), SynthesisError> {
+        let mut final_timestamps = Vec::new();
+        for cell in self.cells.iter() {
+            let count = cell.address.value().ok().map(|address| self.read_counts.get(&address).copied().unwrap_or(0));
+            if count.unwrap_or(0) > self.max_reads_per_cell {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+            final_timestamps.push(witness_bits(self.cs.clone(), count, bit_length(self.max_reads_per_cell))?);
+        }
+
+        // Bind everything the prover chose to the challenges, packing the bits as densely as the field
+        // allows.
+        let mut chosen_bits = Vec::new();
+        for read in self.reads.iter() {
+            chosen_bits.extend(read.value_bits.iter().cloned());
+            chosen_bits.extend(read.timestamp_bits.iter().cloned());
+        }
+        chosen_bits.extend(final_timestamps.iter().flatten().cloned());
+        let mut packed = Vec::new();
+        for chunk in chosen_bits.chunks(F::MODULUS_BIT_SIZE as usize - 1) {
+            packed.push(Boolean::le_bits_to_fp_var(chunk)?);
+        }
+        let params = poseidon_parameters();
+        let mut sponge = PoseidonSpongeVar::new(self.cs.clone(), &params);
+        sponge.absorb(&transcript.to_vec())?;
+        sponge.absorb(&packed)?;
+        let challenges = sponge.squeeze_field_elements(2)?;
+        let timestamp_challenge = challenges[1].square()?;
+
+        // fingerprint(a, v, t) = challenge_0 + v + a * challenge_1 + t * challenge_1^2, multiplied in only
+        // where `mask` is 1.
+        let fingerprint = |address: &FpVar<F>, value: &FpVar<F>, timestamp: &FpVar<F>| -> Result<FpVar<F>, SynthesisError> {
+            Ok(&challenges[0] + value + address * &challenges[1] + timestamp * &timestamp_challenge)
+        };
+        let masked = |mask: &FpVar<F>, term: FpVar<F>| -> FpVar<F> { mask * (term - F::one()) + F::one() };
+
+        let mut initial_and_written = FpVar::one();
+        let mut read_and_final = FpVar::one();
+        for (cell, final_timestamp) in self.cells.iter().zip(final_timestamps.iter()) {
+            let final_timestamp = Boolean::le_bits_to_fp_var(final_timestamp)?;
+            initial_and_written *= masked(&cell.present, fingerprint(&cell.address, &cell.value, &FpVar::zero())?);
+            read_and_final *= masked(&cell.present, fingerprint(&cell.address, &cell.value, &final_timestamp)?);
+        }
+        for read in self.reads.iter() {
+            let enabled = FpVar::from(read.enabled.clone());
+            let value = Boolean::le_bits_to_fp_var(&read.value_bits)?;
+            let timestamp = Boolean::le_bits_to_fp_var(&read.timestamp_bits)?;
+            let read_term = fingerprint(&read.address, &value, &timestamp)?;
+            let written_term = &read_term + &timestamp_challenge;
+            read_and_final *= masked(&enabled, read_term);
+            initial_and_written *= masked(&enabled, written_term);
+        }
+        initial_and_written.enforce_equal(&read_and_final)
+    }
+}
+
+impl<F: PrimeField> MemoryReader<F> for ReadOnlyMemoryVar<F> {
+    fn read(&mut self, address: &FpVar<F>, enabled: &Boolean<F>) -> Result<FpVar<F>, SynthesisError> {
+        ReadOnlyMemoryVar::read(self, address, enabled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::{pack_bases, unpack_bases};
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::ConstraintSystem;
+
+    fn reads_memory(reads: &[(u64, bool)], forged: Option<(u64, u64)>) -> Result<bool, SynthesisError> {
+        let cs = ConstraintSystem::<Fr>::new_ref();
+        let bases = [0, 3, 1, 4, 2];
+        let block = FpVar::new_witness(cs.clone(), || Ok(pack_bases::<Fr>(&bases)[0])).unwrap();
+        let unpacked = unpack_bases(&block).unwrap();
+        let mut memory = ReadOnlyMemoryVar::from_bases(cs.clone(), &unpacked, 3);
+        if let Some((address, value)) = forged {
+            memory.contents.insert(Fr::from(address), Fr::from(value));
+        }
+        for &(address, enabled) in reads {
+            let address_var = FpVar::new_witness(cs.clone(), || Ok(Fr::from(address))).unwrap();
+            let enabled_var = Boolean::new_witness(cs.clone(), || Ok(enabled)).unwrap();
+            let value = memory.read(&address_var, &enabled_var).unwrap();
+            if enabled && forged.is_none() && address < 4 {
+                assert_eq!(value.value().unwrap(), Fr::from(bases[address as usize] as u64));
+            }
+        }
+        memory.finalize(&[FpVar::one()])?;
+        cs.is_satisfied()
+    }
+
+    #[test]
+    fn checks_repeated_and_disabled_reads() {
+        let reads = [(1, true), (4, true), (1, true), (1, true), (7, false), (0, true)];
+        assert!(reads_memory(&reads, None).unwrap());
+        assert!(reads_memory(&[], None).unwrap());
+        // Reading a value the cell does not hold fails, as does reading past the sequence.
+        assert!(!reads_memory(&reads, Some((4, 3))).unwrap());
+        assert!(!reads_memory(&[(5, true)], None).unwrap());
+        // A cell read more often than allowed cannot be represented.
+        assert!(matches!(reads_memory(&[(2, true); 4], None), Err(SynthesisError::Unsatisfiable)));
+    }
+}
@@ -0,0 +1,348 @@
+// Proves a multiple sequence alignment of K committed sequences and its sum-of-pairs score. The MSA is
+// a matrix of K gapped rows over a common set of columns, packed like sequences with MSA_GAP in the
+// slot of BASE_PADDING, so that all-gap columns fill every row up to the circuit's maximum width
+// without changing the alignment. It is committed as a whole with a salted hash.
+//
+// Each row is checked against its sequence as the CIGAR walk checks a target: a running index moves
+// on at every base of the row, which is read from the sequence's read-only memory at that index and
+// must equal the row's symbol, and the index ends at the sequence's public length. The score is the
+// sum over every pair of rows i < j of the score of the pairwise alignment they induce, with row i as
+// the reference. Columns where both rows are gaps are dropped from the pair, so a gap in one row
+// opens unless the previous column kept for the pair had a gap in the same row, the "natural" gap
+// cost of affine sum-of-pairs scoring.
+
+use ark_crypto_primitives::sponge::constraints::CryptographicSpongeVar;
+use ark_crypto_primitives::sponge::poseidon::constraints::PoseidonSpongeVar;
+use ark_crypto_primitives::sponge::poseidon::PoseidonSponge;
+use ark_crypto_primitives::sponge::{Absorb, CryptographicSponge};
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+
+use crate::alignment::{
+    base_lagrange_basis, i64_to_felt, pack_bases_to, unpack_bases, usize_to_felt, ScoringScheme, BASE_N, BASE_PADDING,
+    CIGAR_DELETION, CIGAR_INSERTION, CIGAR_MATCH, CIGAR_MISMATCH,
+};
+use crate::commitment::{commit_bases, unpack_committed_sequence};
+use crate::memory::ReadOnlyMemoryVar;
+use crate::poseidon::poseidon_parameters;
+
+/// A gap in a row of the MSA.
+pub const MSA_GAP: usize = BASE_PADDING;
+
+#[derive(Clone)]
+pub struct MultipleAlignmentCircuit<F: PrimeField> {
+    pub rows: Vec<Vec<usize>>, // the gapped rows, all as wide as the MSA; their number is fixed at setup time
+    pub row_felts: Vec<Vec<F>>, // packed to the maximum width
+    pub sequences: Vec<Vec<usize>>, // the rows with their gaps removed
+    pub sequence_felts: Vec<Vec<F>>, // packed to the maximum sequence length
+    pub sequence_salts: Vec<F>,
+    pub msa_salt: F,
+    pub scoring_scheme: ScoringScheme,
+    pub sum_of_pairs_score: i64,
+}
+
+/// The sequence of a row, without its gaps.
+pub fn ungapped(row: &[usize]) -> Vec<usize> {
+    row.iter().copied().filter(|&symbol| symbol != MSA_GAP).collect()
+}
+
+/// The CIGAR of the pairwise alignment of `target_row` against `reference_row`, dropping the columns
+/// where both are gaps.
+pub fn induced_cigar(reference_row: &[usize], target_row: &[usize]) -> Vec<usize> {
+    reference_row
+        .iter()
+        .zip(target_row)
+        .filter_map(|(&reference, &target)| match (reference == MSA_GAP, target == MSA_GAP) {
+            (true, true) => None,
+            (true, false) => Some(CIGAR_INSERTION),
+            (false, true) => Some(CIGAR_DELETION),
+            (false, false) if reference == target && reference != BASE_N => Some(CIGAR_MATCH),
+            (false, false) => Some(CIGAR_MISMATCH),
+        })
+        .collect()
+}
+
+/// Sum over every pair of rows i < j of the score of the alignment they induce, row i being the
+/// reference, as computed by the circuit.
+pub fn sum_of_pairs_score(scoring_scheme: &ScoringScheme, rows: &[Vec<usize>]) -> i64 {
+    let mut score = 0;
+    for (i, reference_row) in rows.iter().enumerate() {
+        for target_row in rows[i + 1..].iter() {
+            score += scoring_scheme.score(&ungapped(reference_row), &ungapped(target_row), &induced_cigar(reference_row, target_row));
+        }
+    }
+    score
+}
+
+/// Commitment to the packed rows of an MSA. Unlike a sequence commitment it depends on the maximum
+/// width the rows are packed to.
+pub fn commit_msa<F: PrimeField + Absorb>(row_felts: &[Vec<F>], salt: F) -> F {
+    let mut sponge = PoseidonSponge::<F>::new(&poseidon_parameters());
+    sponge.absorb(&salt);
+    for blocks in row_felts {
+        sponge.absorb(blocks);
+    }
+    sponge.squeeze_field_elements::<F>(1)[0]
+}
+
+impl<F: PrimeField> MultipleAlignmentCircuit<F> {
+    /// Packs `rows` for an MSA of at most `max_columns` columns over sequences of at most
+    /// `max_sequence_length` bases, and scores it.
+    pub fn new(rows: Vec<Vec<usize>>, scoring_scheme: ScoringScheme, max_sequence_length: usize, max_columns: usize) -> Self {
+        assert!(rows.len() >= 2, "an MSA needs at least two rows");
+        let width = rows[0].len();
+        assert!(rows.iter().all(|row| row.len() == width), "MSA rows differ in width");
+        assert!(width <= max_columns, "MSA is wider than the circuit allows");
+        let sequences: Vec<Vec<usize>> = rows.iter().map(|row| ungapped(row)).collect();
+        assert!(sequences.iter().all(|sequence| sequence.len() <= max_sequence_length), "sequence is longer than the circuit allows");
+        Self {
+            row_felts: rows.iter().map(|row| pack_bases_to(row, max_columns)).collect(),
+            sequence_felts: sequences.iter().map(|sequence| pack_bases_to(sequence, max_sequence_length)).collect(),
+            sequence_salts: vec![F::zero(); rows.len()],
+            msa_salt: F::zero(),
+            sum_of_pairs_score: sum_of_pairs_score(&scoring_scheme, &rows),
+            rows,
+            sequences,
+            scoring_scheme,
+        }
+    }
+
+    pub fn with_salts(mut self, sequence_salts: Vec<F>, msa_salt: F) -> Self {
+        assert_eq!(sequence_salts.len(), self.rows.len(), "one salt per sequence");
+        self.sequence_salts = sequence_salts;
+        self.msa_salt = msa_salt;
+        self
+    }
+}
+
+impl<F: PrimeField + Absorb> MultipleAlignmentCircuit<F> {
+    /// Public inputs in the order the circuit allocates them.
+    pub fn public_inputs(&self) -> Vec<F> {
+        let mut inputs = self.scoring_scheme.substitution_matrix_felts::<F>();
+        inputs.extend(self.sequences.iter().zip(self.sequence_salts.iter()).map(|(sequence, &salt)| commit_bases(sequence, salt)));
+        inputs.extend(self.sequences.iter().map(|sequence| F::from(sequence.len() as u64)));
+        inputs.push(commit_msa(&self.row_felts, self.msa_salt));
+        inputs.push(i64_to_felt::<F>(self.sum_of_pairs_score));
+        inputs
+    }
+}
+
+/// A column of a row, with what the pairs it takes part in need to know about it.
+struct RowSymbol<F: PrimeField> {
+    value: FpVar<F>,
+    is_gap: Boolean<F>,
+    is_n: Boolean<F>,
+    basis: Vec<FpVar<F>>, // Lagrange basis over the bases, meaningless at a gap
+    substitution_rows: Vec<FpVar<F>>, // substitution_rows[a] scores reference base a against this one as target
+}
+
+impl<F: PrimeField> ConstraintSynthesizer<F> for MultipleAlignmentCircuit<F> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+        let mut row_vars = Vec::new();
+        for row_felts in self.row_felts.iter() {
+            let mut blocks = Vec::new();
+            for elem in row_felts.iter() {
+                blocks.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+            }
+            row_vars.push(blocks);
+        }
+
+        let mut sequence_vars = Vec::new();
+        for sequence_felts in self.sequence_felts.iter() {
+            let mut blocks = Vec::new();
+            for elem in sequence_felts.iter() {
+                blocks.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+            }
+            sequence_vars.push(blocks);
+        }
+
+        let msa_salt_var = FpVar::new_witness(cs.clone(), || Ok(self.msa_salt))?;
+
+        let params = poseidon_parameters();
+
+        let mut sequences = Vec::new();
+        for ((blocks, sequence), &salt) in sequence_vars.iter().zip(self.sequences.iter()).zip(self.sequence_salts.iter()) {
+            sequences.push(unpack_committed_sequence(cs.clone(), blocks, sequence.len(), salt)?);
+        }
+
+        let mut msa_sponge = PoseidonSpongeVar::new(cs.clone(), &params);
+        msa_sponge.absorb(&msa_salt_var)?;
+        for blocks in row_vars.iter() {
+            msa_sponge.absorb(blocks)?;
+        }
+        let msa_commitment = msa_sponge.squeeze_field_elements(1)?.remove(0);
+
+        let mut substitution_matrix_vars = Vec::new();
+        for elem in self.scoring_scheme.substitution_matrix_felts::<F>() {
+            substitution_matrix_vars.push(FpVar::new_input(cs.clone(), || Ok(elem))?);
+        }
+        let mut publics = Vec::new();
+        for sequence in sequences.iter() {
+            publics.push(FpVar::new_input(cs.clone(), || sequence.commitment.value())?);
+            sequence.commitment.enforce_equal(publics.last().unwrap())?;
+        }
+        for sequence in sequences.iter() {
+            publics.push(FpVar::new_input(cs.clone(), || sequence.length.value())?);
+            sequence.length.enforce_equal(publics.last().unwrap())?;
+        }
+        let msa_commitment_var = FpVar::new_input(cs.clone(), || msa_commitment.value())?;
+        msa_commitment_var.enforce_equal(&msa_commitment)?;
+
+        let match_score = i64_to_felt::<F>(self.scoring_scheme.match_score);
+        let ambiguous_score = i64_to_felt::<F>(self.scoring_scheme.ambiguous_score);
+        let gap_open = i64_to_felt::<F>(self.scoring_scheme.gap_open);
+        let gap_extend = i64_to_felt::<F>(self.scoring_scheme.gap_extend);
+        let base_n = FpVar::constant(usize_to_felt::<F>(BASE_N));
+
+        // Walk every row through the memory of its sequence. The lookup of the substitution score only
+        // depends on the reference base through its basis, so the target side is summed once per symbol
+        // rather than once per pair.
+        let mut rows: Vec<Vec<RowSymbol<F>>> = Vec::new();
+        let mut memories = Vec::new();
+        for (blocks, sequence) in row_vars.iter().zip(sequences.iter()) {
+            let mut memory = ReadOnlyMemoryVar::from_bases(cs.clone(), &sequence.bases, 1);
+            let mut index = FpVar::zero();
+            let mut row = Vec::new();
+            for block in blocks.iter() {
+                for symbol in unpack_bases(block)? {
+                    let is_base = symbol.is_padding.not();
+                    let read = memory.read(&index, &is_base)?;
+                    read.conditional_enforce_equal(&symbol.value, &is_base)?;
+                    index += FpVar::from(is_base);
+
+                    let basis = base_lagrange_basis(&symbol.value)?;
+                    let mut substitution_rows = Vec::new();
+                    for a in 0..4 {
+                        let mut row_score = &basis[BASE_N] * ambiguous_score;
+                        for (b, weight) in basis.iter().take(4).enumerate() {
+                            row_score += &substitution_matrix_vars[4 * a + b] * weight;
+                        }
+                        substitution_rows.push(row_score);
+                    }
+                    row.push(RowSymbol {
+                        is_n: symbol.value.is_eq(&base_n)?,
+                        value: symbol.value,
+                        is_gap: symbol.is_padding,
+                        basis,
+                        substitution_rows,
+                    });
+                }
+            }
+            index.enforce_equal(&sequence.length)?;
+            rows.push(row);
+            memories.push(memory);
+        }
+
+        // The MSA commitment fixes every address read.
+        let mut transcript = publics;
+        transcript.push(msa_commitment_var);
+        transcript.extend(substitution_matrix_vars.iter().cloned());
+        for memory in memories {
+            memory.finalize(&transcript)?;
+        }
+
+        let mut score = FpVar::zero();
+        for (i, reference_row) in rows.iter().enumerate() {
+            for target_row in rows[i + 1..].iter() {
+                let mut previous_is_insertion = Boolean::FALSE;
+                let mut previous_is_deletion = Boolean::FALSE;
+                for (reference, target) in reference_row.iter().zip(target_row.iter()) {
+                    let is_aligned = reference.is_gap.not().and(&target.is_gap.not())?;
+                    let is_insertion = reference.is_gap.and(&target.is_gap.not())?;
+                    let is_deletion = reference.is_gap.not().and(&target.is_gap)?;
+                    let both_gaps = reference.is_gap.and(&target.is_gap)?;
+                    let opens_insertion = is_insertion.and(&previous_is_insertion.not())?;
+                    let opens_deletion = is_deletion.and(&previous_is_deletion.not())?;
+                    let bases_match = is_aligned.and(&reference.value.is_eq(&target.value)?)?.and(&reference.is_n.not())?;
+                    let is_mismatch = is_aligned.and(&bases_match.not())?;
+
+                    let mut substitution_score = &reference.basis[BASE_N] * ambiguous_score;
+                    for (weight, row_score) in reference.basis.iter().zip(target.substitution_rows.iter()) {
+                        substitution_score += weight * row_score;
+                    }
+                    score += (FpVar::from(opens_insertion) + FpVar::from(opens_deletion)) * gap_open
+                        + (FpVar::from(is_insertion.clone()) + FpVar::from(is_deletion.clone())) * gap_extend
+                        + FpVar::from(bases_match) * match_score
+                        + FpVar::from(is_mismatch) * substitution_score;
+
+                    // Columns where both rows are gaps leave the pair as it was.
+                    previous_is_insertion = is_insertion.or(&both_gaps.and(&previous_is_insertion)?)?;
+                    previous_is_deletion = is_deletion.or(&both_gaps.and(&previous_is_deletion)?)?;
+                }
+            }
+        }
+        FpVar::new_input(cs.clone(), || Ok(i64_to_felt::<F>(self.sum_of_pairs_score)))?.enforce_equal(&score)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sam::encode_sequence;
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::UniformRand;
+
+    fn row(text: &[u8]) -> Vec<usize> {
+        text.split(|&c| c == b'-')
+            .map(|run| encode_sequence(run).unwrap())
+            .collect::<Vec<_>>()
+            .join(&MSA_GAP)
+    }
+
+    fn is_satisfied(circuit: MultipleAlignmentCircuit<Fr>) -> bool {
+        let cs = ConstraintSystem::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn scores_pairs_with_natural_gaps() {
+        let rows = [row(b"AC-T"), row(b"A-GT")];
+        assert_eq!(induced_cigar(&rows[0], &rows[1]), vec![CIGAR_MATCH, CIGAR_DELETION, CIGAR_INSERTION, CIGAR_MATCH]);
+        assert_eq!(sum_of_pairs_score(&ScoringScheme::edit_distance(), &rows), 2);
+        // The column where both rows of the first pair are gaps does not split their gap.
+        let rows = [row(b"A---T"), row(b"AG-CT"), row(b"AGACT")];
+        assert_eq!(induced_cigar(&rows[0], &rows[1]), vec![CIGAR_MATCH, CIGAR_INSERTION, CIGAR_INSERTION, CIGAR_MATCH]);
+        let scheme = ScoringScheme::bwa_mem();
+        assert_eq!(sum_of_pairs_score(&scheme, &rows), (2 - 7 - 1) + (2 - 7 - 1 - 1) + (4 - 7));
+    }
+
+    #[test]
+    fn proves_rows_and_sum_of_pairs_score() {
+        let rng = &mut ark_std::test_rng();
+        let rows = vec![
+            row(b"ACGTNAC--GTTAGC-A"),
+            row(b"ACGT-ACCAGTTAGCTA"),
+            row(b"A-GTNAC--GATAG--A"),
+            row(b"ACGGNAC--GTTAGCTA"),
+        ];
+        let salts: Vec<Fr> = (0..rows.len()).map(|_| Fr::rand(rng)).collect();
+        let msa = MultipleAlignmentCircuit::<Fr>::new(rows.clone(), ScoringScheme::bwa_mem(), 20, 100).with_salts(salts, Fr::rand(rng));
+        let cs = ConstraintSystem::new_ref();
+        msa.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.borrow().unwrap().instance_assignment[1..], msa.public_inputs()[..]);
+
+        let mut wrong_score = msa.clone();
+        wrong_score.sum_of_pairs_score += 1;
+        assert!(!is_satisfied(wrong_score));
+
+        // A row whose bases differ from its committed sequence fails, as does one with a base too many.
+        let mut substituted = msa.clone();
+        substituted.row_felts[1] = pack_bases_to(&row(b"ACGT-ACCAGTTAGCTT"), 100);
+        assert!(!is_satisfied(substituted));
+        let mut extra_base = msa.clone();
+        extra_base.row_felts[1] = pack_bases_to(&row(b"ACGTTACCAGTTAGCTA"), 100);
+        assert!(!is_satisfied(extra_base));
+
+        // So does a row dropping the last base of its sequence.
+        let mut dropped = msa;
+        dropped.row_felts[0] = pack_bases_to(&row(b"ACGTNAC--GTTAGC--"), 100);
+        assert!(!is_satisfied(dropped));
+    }
+}
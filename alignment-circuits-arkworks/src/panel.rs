@@ -0,0 +1,144 @@
+// Proves that a committed read is within a banded edit distance of one entry of a public panel of
+// reference commitments, such as the alleles of a screening panel, without revealing which entry. This
+// is the in-circuit counterpart of the 1-out-of-N membership proof of the Dorian crate
+// (`commitment::gk_mem`): the ring is the list of panel commitments, and the prover selects its entry
+// with a private one-hot vector, so that the reference fed to the distance check is bound to some entry
+// while the verifier only sees the whole panel. Entries may differ in length up to the maximum
+// reference length, and the length of the selected entry stays private.
+
+use ark_crypto_primitives::sponge::Absorb;
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+
+use crate::commitment::{commit_bases, unpack_committed_sequence};
+use crate::edit_distance::{enforce_banded_edit_distance, enforce_within, EditDistanceCircuit};
+
+#[derive(Clone)]
+pub struct PanelMembershipCircuit<F: PrimeField> {
+    pub panel: Vec<F>, // public commitments of the entries, their number is fixed at setup time
+    pub index: usize, // private, the entry the read is compared against
+    pub threshold: usize, // public bound on the distance to the entry
+    pub alignment: EditDistanceCircuit<F>, // read against the bases of the selected entry
+}
+
+impl<F: PrimeField + Absorb> PanelMembershipCircuit<F> {
+    /// Moves `alignment` onto the `panel` entry committing to its reference, or returns None if no entry
+    /// does. The circuit keeps the band and maximum lengths `alignment` was built for.
+    pub fn new(panel: Vec<F>, alignment: EditDistanceCircuit<F>, threshold: usize) -> Option<Self> {
+        let commitment = commit_bases(&alignment.reference_sequence_bases, alignment.reference_salt);
+        let index = panel.iter().position(|&entry| entry == commitment)?;
+        Some(Self {
+            panel,
+            index,
+            threshold,
+            alignment,
+        })
+    }
+
+    /// Public inputs in the order the circuit allocates them.
+    pub fn public_inputs(&self) -> Vec<F> {
+        let mut inputs = self.panel.clone();
+        inputs.push(commit_bases(&self.alignment.target_sequence_bases, self.alignment.target_salt));
+        inputs.push(F::from(self.alignment.target_sequence_bases.len() as u64));
+        inputs.push(F::from(self.threshold as u64));
+        inputs
+    }
+}
+
+impl<F: PrimeField> ConstraintSynthesizer<F> for PanelMembershipCircuit<F> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+        let alignment = &self.alignment;
+        let mut reference_sequence_vars = Vec::new();
+        for elem in alignment.reference_sequence_felts.iter() {
+            reference_sequence_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+
+        let mut target_sequence_vars = Vec::new();
+        for elem in alignment.target_sequence_felts.iter() {
+            target_sequence_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+
+        let reference = unpack_committed_sequence(cs.clone(), &reference_sequence_vars, alignment.reference_sequence_bases.len(), alignment.reference_salt)?;
+        let target = unpack_committed_sequence(cs.clone(), &target_sequence_vars, alignment.target_sequence_bases.len(), alignment.target_salt)?;
+
+        let mut panel_vars = Vec::new();
+        for entry in self.panel.iter() {
+            panel_vars.push(FpVar::new_input(cs.clone(), || Ok(entry))?);
+        }
+        for public in [&target.commitment, &target.length] {
+            FpVar::new_input(cs.clone(), || public.value())?.enforce_equal(public)?;
+        }
+        let threshold_var = FpVar::new_input(cs.clone(), || Ok(F::from(self.threshold as u64)))?;
+
+        // The reference commitment is the entry picked out by a one-hot selector.
+        let mut one_hot_sum = FpVar::zero();
+        let mut selected = FpVar::zero();
+        for (i, entry) in panel_vars.iter().enumerate() {
+            let bit = Boolean::new_witness(cs.clone(), || Ok(i == self.index))?;
+            one_hot_sum += FpVar::from(bit.clone());
+            selected += FpVar::from(bit) * entry;
+        }
+        one_hot_sum.enforce_equal(&FpVar::one())?;
+        selected.enforce_equal(&reference.commitment)?;
+
+        let distance = enforce_banded_edit_distance(&reference, &target, alignment.band)?;
+        enforce_within(cs, &distance, &threshold_var, self.threshold.wrapping_sub(alignment.distance), alignment.distance_bits())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::{BASE_A, BASE_T};
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::rand::Rng;
+    use ark_std::UniformRand;
+
+    fn is_satisfied(circuit: PanelMembershipCircuit<Fr>) -> bool {
+        let cs = ConstraintSystem::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn proves_the_read_is_close_to_some_entry() {
+        let rng = &mut ark_std::test_rng();
+        let alleles: Vec<Vec<usize>> = (0..4).map(|k| (0..30 + k).map(|_| rng.gen_range(BASE_A..=BASE_T)).collect()).collect();
+        let salts: Vec<Fr> = (0..4).map(|_| Fr::rand(rng)).collect();
+        let panel: Vec<Fr> = alleles.iter().zip(salts.iter()).map(|(allele, &salt)| commit_bases(allele, salt)).collect();
+        let mut read = alleles[2].clone();
+        read[7] = (read[7] + 1) % 4;
+        read.push(BASE_T);
+        let target_salt = Fr::rand(rng);
+        let against = |k: usize| {
+            EditDistanceCircuit::<Fr>::new(alleles[k].clone(), read.clone(), 4, 40, 40).unwrap().with_salts(salts[k], target_salt)
+        };
+
+        let circuit = PanelMembershipCircuit::new(panel.clone(), against(2), 2).unwrap();
+        assert_eq!(circuit.index, 2);
+        let cs = ConstraintSystem::new_ref();
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.borrow().unwrap().instance_assignment[1..], circuit.public_inputs()[..]);
+        // The public inputs do not depend on the entry, only on the read.
+        assert_eq!(circuit.public_inputs(), PanelMembershipCircuit::new(panel.clone(), against(0), 2).unwrap().public_inputs());
+
+        // A threshold below the distance fails, as does a far entry.
+        assert!(!is_satisfied(PanelMembershipCircuit::new(panel.clone(), against(2), 1).unwrap()));
+        assert!(!is_satisfied(PanelMembershipCircuit::new(panel.clone(), against(0), 2).unwrap()));
+
+        // So does pointing the selector at another entry than the one aligned against.
+        let mut wrong_index = circuit.clone();
+        wrong_index.index = 1;
+        assert!(!is_satisfied(wrong_index));
+
+        // And aligning against an allele outside the panel, which the constructor refuses.
+        let mut outside = circuit;
+        outside.panel[2] = Fr::rand(rng);
+        assert!(PanelMembershipCircuit::new(outside.panel.clone(), against(2), 2).is_none());
+        assert!(!is_satisfied(outside));
+    }
+}
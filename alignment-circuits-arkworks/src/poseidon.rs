@@ -0,0 +1,228 @@
+// Poseidon parameters generated as in the reference implementation of the Poseidon paper
+// (https://eprint.iacr.org/2019/458): the S-box is x^alpha for the smallest alpha coprime to p - 1,
+// the round numbers are the cheapest that satisfy the paper's security bounds plus the 7.5% margin on
+// partial rounds, and the round constants and Cauchy MDS matrix are drawn from the Grain LFSR seeded
+// with the field size, state width and round numbers.
+//
+// Matrices that admit invariant subspace trails through the partial rounds are skipped, as in the
+// reference, see `is_secure_mds`.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+use ark_crypto_primitives::sponge::poseidon::{find_poseidon_ark_and_mds, PoseidonConfig};
+use ark_ff::{Field, PrimeField};
+
+/// Security level of `poseidon_config`, in bits.
+pub const SECURITY_BITS: usize = 128;
+
+/// The smallest alpha >= 3 for which x^alpha permutes the field.
+pub fn sbox_exponent<F: PrimeField>() -> u64 {
+    let modulus = F::MODULUS;
+    (3u64..)
+        .find(|&alpha| {
+            // (p - 1) mod alpha, from the limbs of p.
+            let remainder = modulus.as_ref().iter().rev().fold(0u128, |acc, &limb| ((acc << 64) | limb as u128) % alpha as u128);
+            gcd((remainder + alpha as u128 - 1) % alpha as u128, alpha as u128) == 1
+        })
+        .unwrap()
+}
+
+fn gcd(a: u128, b: u128) -> u128 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// log2 of the modulus of F.
+pub fn log2_modulus<F: PrimeField>() -> f64 {
+    F::MODULUS.as_ref().iter().rev().fold(0f64, |acc, &limb| acc * 2f64.powi(64) + limb as f64).log2()
+}
+
+// log2 of the binomial coefficient (n choose k).
+fn log2_binomial(n: f64, k: f64) -> f64 {
+    (0..k as usize).map(|i| ((n - i as f64) / (k - i as f64)).log2()).sum()
+}
+
+// The statistical, interpolation and Groebner basis bounds of the Poseidon paper, and the bound of
+// https://eprint.iacr.org/2023/537, for `full_rounds` and `partial_rounds` rounds of width `t`.
+fn rounds_are_secure(log2_modulus: f64, t: usize, full_rounds: usize, partial_rounds: usize, alpha: u64, security_bits: usize) -> bool {
+    let (t_f, r_f, r_p, alpha_f, m) = (t as f64, full_rounds as f64, partial_rounds as f64, alpha as f64, security_bits as f64);
+    let log_alpha = |x: f64| x.ln() / alpha_f.ln();
+    let statistical = if m <= (log2_modulus - (alpha_f - 1.0) / 2.0).floor() * (t_f + 1.0) { 6.0 } else { 10.0 };
+    let interpolation = 1.0 + (log_alpha(2.0) * m.min(log2_modulus.ceil())).ceil() + log_alpha(t_f).ceil() - r_p;
+    let groebner_1 = log_alpha(2.0) * m.min(log2_modulus) - r_p;
+    let groebner_2 = t_f - 1.0 + log_alpha(2.0) * (m / (t_f + 1.0)).min(log2_modulus / 2.0) - r_p;
+    let groebner_3 = (t_f - 2.0 + m / (2.0 * alpha_f.log2()) - r_p) / (t_f - 1.0);
+    let required = [statistical, interpolation, groebner_1, groebner_2, groebner_3].iter().map(|bound| bound.ceil()).fold(f64::MIN, f64::max);
+
+    let r_temp = (t_f / 3.0).floor();
+    let over = (r_f - 1.0) * t_f + r_p + r_temp + r_temp * (r_f / 2.0) + r_p + alpha_f;
+    let under = r_temp * (r_f / 2.0) + r_p + alpha_f;
+    let binomial_cost = (2.0 * log2_binomial(over, under)).ceil();
+    r_f >= required && binomial_cost >= m
+}
+
+/// The numbers of full and partial rounds for a width `t` permutation with S-box x^alpha over a field
+/// of `log2_modulus` bits, minimising the S-boxes evaluated. As in the reference, the full rounds get
+/// two more and the partial rounds 7.5% more than the bounds require.
+pub fn round_numbers(log2_modulus: f64, t: usize, alpha: u64, security_bits: usize) -> (usize, usize) {
+    let mut best = None;
+    for partial_rounds in 1..500 {
+        let Some(full_rounds) = (4..100).step_by(2).find(|&full_rounds| rounds_are_secure(log2_modulus, t, full_rounds, partial_rounds, alpha, security_bits)) else {
+            continue;
+        };
+        let rounds = (full_rounds + 2, (partial_rounds as f64 * 1.075).ceil() as usize);
+        let cost = t * rounds.0 + rounds.1;
+        if best.is_none_or(|(best_cost, _)| cost < best_cost) {
+            best = Some((cost, rounds));
+        }
+    }
+    best.expect("no secure round numbers").1
+}
+
+/// Parameters for a sponge of the given rate and capacity with explicit round numbers. The round
+/// constants and the MDS matrix come from the Grain LFSR.
+pub fn generate_config<F: PrimeField>(rate: usize, capacity: usize, alpha: u64, full_rounds: usize, partial_rounds: usize) -> PoseidonConfig<F> {
+    assert!(rate > 0 && capacity > 0, "a sponge needs a positive rate and capacity");
+    let t = rate + capacity;
+    // The LFSR only sees the width, so the first t - 1 elements stand in for arkworks' rate.
+    let prime_bits = F::MODULUS_BIT_SIZE as u64;
+    let (mut ark, mut mds) = (Vec::new(), Vec::new());
+    for skip_matrices in 0.. {
+        (ark, mds) = find_poseidon_ark_and_mds::<F>(prime_bits, t - 1, full_rounds as u64, partial_rounds as u64, skip_matrices);
+        if is_secure_mds(&mds) {
+            break;
+        }
+    }
+    PoseidonConfig { full_rounds, partial_rounds, alpha, ark, mds, rate, capacity }
+}
+
+/// Parameters for a sponge of the given rate and capacity at SECURITY_BITS bits of security.
+pub fn poseidon_config<F: PrimeField>(rate: usize, capacity: usize) -> PoseidonConfig<F> {
+    let alpha = sbox_exponent::<F>();
+    let (full_rounds, partial_rounds) = round_numbers(log2_modulus::<F>(), rate + capacity, alpha, SECURITY_BITS);
+    generate_config(rate, capacity, alpha, full_rounds, partial_rounds)
+}
+
+/// The rate 2, capacity 1 parameters used by every commitment and transcript of the circuits,
+/// generated once per field.
+pub fn poseidon_parameters<F: PrimeField>() -> PoseidonConfig<F> {
+    static CACHE: OnceLock<Mutex<HashMap<TypeId, Box<dyn Any + Send>>>> = OnceLock::new();
+    let mut cache = CACHE.get_or_init(Default::default).lock().unwrap();
+    let config = cache.entry(TypeId::of::<F>()).or_insert_with(|| Box::new(poseidon_config::<F>(2, 1)));
+    config.downcast_ref::<PoseidonConfig<F>>().unwrap().clone()
+}
+
+// A Cauchy matrix 1 / (x_i + y_j) is MDS iff the x_i are distinct, the y_j are distinct and no
+// x_i + y_j is zero, which arkworks would invert to zero. Beyond that, no power M^l may leave a nonzero
+// subspace of {x : x_0 = 0} invariant, or differences in that subspace would never reach the S-box of
+// the partial rounds. Such a subspace exists iff the rows e_0 M^(l i), i < t, are linearly dependent.
+fn is_secure_mds<F: PrimeField>(mds: &[Vec<F>]) -> bool {
+    let t = mds.len();
+    let entries_are_distinct = |entries: Vec<F>| (0..t).all(|i| (0..i).all(|j| entries[i] != entries[j]));
+    if mds.iter().flatten().any(|entry| entry.is_zero()) || !entries_are_distinct(mds.iter().map(|row| row[0]).collect()) || !entries_are_distinct(mds[0].clone()) {
+        return false;
+    }
+    let mut power = mds.to_vec();
+    for _ in 0..2 * t {
+        let mut rows = vec![power[0].clone()];
+        for i in 1..t {
+            rows.push(vector_times_matrix(&rows[i - 1], &power));
+        }
+        if rank(rows) < t {
+            return false;
+        }
+        power = matrix_product(&power, mds);
+    }
+    true
+}
+
+fn vector_times_matrix<F: Field>(v: &[F], m: &[Vec<F>]) -> Vec<F> {
+    (0..m.len()).map(|j| v.iter().zip(m).map(|(x, row)| *x * row[j]).sum()).collect()
+}
+
+fn matrix_product<F: Field>(a: &[Vec<F>], b: &[Vec<F>]) -> Vec<Vec<F>> {
+    a.iter().map(|row| vector_times_matrix(row, b)).collect()
+}
+
+fn rank<F: Field>(mut rows: Vec<Vec<F>>) -> usize {
+    let mut rank = 0;
+    for column in 0..rows.first().map_or(0, |row| row.len()) {
+        let Some(pivot) = (rank..rows.len()).find(|&i| !rows[i][column].is_zero()) else {
+            continue;
+        };
+        rows.swap(rank, pivot);
+        let inverse = rows[rank][column].inverse().unwrap();
+        for i in rank + 1..rows.len() {
+            let factor = rows[i][column] * inverse;
+            for j in column..rows[i].len() {
+                let subtrahend = factor * rows[rank][j];
+                rows[i][j] -= subtrahend;
+            }
+        }
+        rank += 1;
+    }
+    rank
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ark_crypto_primitives::sponge::poseidon::PoseidonSponge;
+    use ark_crypto_primitives::sponge::{Absorb, CryptographicSponge};
+
+    // The permutation of (0, 1, ..., t - 1), by absorbing 1, ..., t - 1 into the rate of a fresh sponge.
+    fn permute_iota<F: PrimeField + Absorb>(config: &PoseidonConfig<F>) -> Vec<F> {
+        let mut sponge = PoseidonSponge::new(config);
+        sponge.absorb(&(1..=config.rate as u64).map(F::from).collect::<Vec<_>>());
+        sponge.squeeze_field_elements::<F>(1);
+        sponge.state
+    }
+
+    fn felts<F: PrimeField>(decimals: &[&str]) -> Vec<F> {
+        decimals.iter().map(|decimal| F::from_str(decimal).map_err(|_| ()).unwrap()).collect()
+    }
+
+    #[test]
+    fn reproduces_reference_round_numbers() {
+        // Plonky3 ships these for Poseidon2, whose bounds are the same.
+        assert_eq!(round_numbers(31.0, 16, 7, 128), (8, 13));
+        assert_eq!(round_numbers(31.0, 24, 3, 128), (8, 23));
+        assert_eq!(round_numbers(64.0, 8, 7, 128), (8, 22));
+        assert_eq!(round_numbers(64.0, 12, 3, 128), (8, 42));
+
+        assert_eq!(sbox_exponent::<ark_bls12_381::Fr>(), 5);
+        assert_eq!(sbox_exponent::<ark_bn254::Fr>(), 5);
+        assert_eq!(round_numbers(log2_modulus::<ark_bls12_381::Fr>(), 3, 5, SECURITY_BITS), (8, 56));
+        assert_eq!(round_numbers(log2_modulus::<ark_bn254::Fr>(), 5, 5, SECURITY_BITS), (8, 56));
+        let config = poseidon_parameters::<ark_bls12_381::Fr>();
+        assert_eq!((config.alpha, config.full_rounds, config.partial_rounds, config.ark.len()), (5, 8, 56, 64));
+    }
+
+    #[test]
+    fn reproduces_reference_permutations() {
+        // Test vectors of the Poseidon reference implementation, which publishes 57 partial rounds for
+        // width 3 and 60 for width 5.
+        let state = permute_iota(&generate_config::<ark_bn254::Fr>(2, 1, 5, 8, 57));
+        let expected = felts(&[
+            "7853200120776062878684798364095072458815029376092732009249414926327459813530",
+            "7142104613055408817911962100316808866448378443474503659992478482890339429929",
+            "6549537674122432311777789598043107870002137484850126429160507761192163713804",
+        ]);
+        assert_eq!(state, expected);
+
+        let state = permute_iota(&generate_config::<ark_bls12_381::Fr>(2, 1, 5, 8, 57));
+        let expected = felts(&[
+            "18456658763349757341014058622209659766100673761449600566550821987295786346378",
+            "37068251774887509885063625701815026138353041152735229476479055620962268601796",
+            "26763157702141528937904191329664859174584798817251788852101947537759678822298",
+        ]);
+        assert_eq!(state, expected);
+
+        let state = permute_iota(&generate_config::<ark_bn254::Fr>(4, 1, 5, 8, 60));
+        assert_eq!(state[0], felts::<ark_bn254::Fr>(&["18821383157269793795438455681495246036402687001665670618754263018637548127333"])[0]);
+
+        let config = generate_config::<ark_bn254::Fr>(2, 2, 5, 8, 60);
+        assert_eq!((config.mds.len(), config.ark[0].len()), (4, 4));
+    }
+}
@@ -0,0 +1,221 @@
+// Poseidon Merkle tree over the packed blocks of a chromosome or a whole genome, so that a circuit only
+// has to open the window a read aligns to instead of hashing the whole reference.
+
+use ark_crypto_primitives::crh::poseidon::constraints::{CRHGadget, CRHParametersVar, TwoToOneCRHGadget};
+use ark_crypto_primitives::crh::poseidon::{TwoToOneCRH, CRH};
+use ark_crypto_primitives::merkle_tree::constraints::{ConfigGadget, PathVar};
+use ark_crypto_primitives::merkle_tree::{Config, IdentityDigestConverter, MerkleTree, Path};
+use ark_crypto_primitives::sponge::Absorb;
+use ark_crypto_primitives::sponge::poseidon::PoseidonConfig;
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSystemRef, SynthesisError};
+use ark_std::marker::PhantomData;
+
+use crate::alignment::{pack_bases, pack_bases_to, unpack_bases, UnpackedBase, BASES_PER_BLOCK, BASE_N, BITS_PER_BASE};
+use crate::memory::{MemoryCell, ReadOnlyMemoryVar};
+use crate::poseidon::poseidon_parameters;
+use crate::sam::{encode_sequence, FastaReference, SamError};
+
+pub struct ReferenceTreeConfig<F: PrimeField + Absorb>(PhantomData<F>);
+
+impl<F: PrimeField + Absorb> Config for ReferenceTreeConfig<F> {
+    type Leaf = [F];
+    type LeafDigest = F;
+    type LeafInnerDigestConverter = IdentityDigestConverter<F>;
+    type InnerDigest = F;
+    type LeafHash = CRH<F>;
+    type TwoToOneHash = TwoToOneCRH<F>;
+}
+
+pub struct ReferenceTreeConfigVar<F: PrimeField + Absorb>(PhantomData<F>);
+
+impl<F: PrimeField + Absorb> ConfigGadget<ReferenceTreeConfig<F>, F> for ReferenceTreeConfigVar<F> {
+    type Leaf = [FpVar<F>];
+    type LeafDigest = FpVar<F>;
+    type LeafInnerConverter = IdentityDigestConverter<FpVar<F>>;
+    type InnerDigest = FpVar<F>;
+    type LeafHash = CRHGadget<F>;
+    type TwoToOneHash = TwoToOneCRHGadget<F>;
+}
+
+pub type ReferencePath<F> = Path<ReferenceTreeConfig<F>>;
+pub type ReferencePathVar<F> = PathVar<ReferenceTreeConfig<F>, F, ReferenceTreeConfigVar<F>>;
+
+/// A chromosome committed block by block: leaf i is the Poseidon hash of the i-th packed block of
+/// BASES_PER_BLOCK bases. The leaves are padded with blocks of BASE_PADDING to a power of two, leaving
+/// enough spares that a window of the maximum length the tree was built for can be opened from any
+/// block. Padding is not a base, so no window can align against the spare leaves.
+pub struct ReferenceTree<F: PrimeField + Absorb> {
+    tree: MerkleTree<ReferenceTreeConfig<F>>,
+    blocks: Vec<F>,
+    bases: Vec<usize>,
+    chromosomes: Vec<(String, usize, usize)>, // name, offset and length of each sequence of a genome tree
+}
+
+/// The blocks covering a window of the reference, with their membership proofs.
+#[derive(Clone, Debug)]
+pub struct ReferenceWindow<F: PrimeField + Absorb> {
+    pub start: usize, // 0-based position of the first base of the window
+    pub blocks: Vec<F>,
+    pub paths: Vec<ReferencePath<F>>,
+}
+
+/// A window together with the root and height of the tree it was opened from.
+#[derive(Clone, Debug)]
+pub struct ReferenceOpening<F: PrimeField + Absorb> {
+    pub root: F,
+    pub tree_height: usize,
+    pub window: ReferenceWindow<F>,
+}
+
+impl<F: PrimeField + Absorb> ReferenceTree<F> {
+    /// Commits to `bases`, with room for windows of up to `max_window_length` bases.
+    pub fn new(bases: Vec<usize>, max_window_length: usize) -> Self {
+        let mut blocks = pack_bases::<F>(&bases);
+        let num_leaves = (blocks.len() + Self::window_blocks(max_window_length)).next_power_of_two().max(2);
+        blocks.resize(num_leaves, pack_bases_to::<F>(&[], BASES_PER_BLOCK)[0]);
+        let params = poseidon_parameters::<F>();
+        let tree = MerkleTree::new(&params, &params, blocks.iter().map(std::slice::from_ref)).unwrap();
+        Self { tree, blocks, bases, chromosomes: Vec::new() }
+    }
+
+    /// Commits every sequence of `fasta` under one root, in file order. Each sequence starts on a block
+    /// boundary and the gap before the next one is filled with N, so that no window can align across
+    /// two sequences without paying for the Ns.
+    pub fn from_fasta(fasta: &FastaReference, max_window_length: usize) -> Result<Self, SamError> {
+        let mut bases = Vec::new();
+        let mut chromosomes = Vec::new();
+        for name in fasta.names() {
+            let sequence = encode_sequence(fasta.sequence(name).unwrap_or_default())?;
+            chromosomes.push((name.clone(), bases.len(), sequence.len()));
+            bases.extend(sequence);
+            bases.resize(bases.len().next_multiple_of(BASES_PER_BLOCK), BASE_N);
+        }
+        Ok(Self { chromosomes, ..Self::new(bases, max_window_length) })
+    }
+
+    /// Position in the tree of the 0-based position `pos` of sequence `chrom`. A tree built with `new`
+    /// holds a single unnamed sequence and takes positions as they are.
+    pub fn genome_position(&self, chrom: &str, pos: usize) -> Result<usize, SamError> {
+        if self.chromosomes.is_empty() {
+            return Ok(pos);
+        }
+        let (_, offset, length) = self
+            .chromosomes
+            .iter()
+            .find(|(name, _, _)| name == chrom)
+            .ok_or_else(|| SamError::UnknownReference(chrom.to_string()))?;
+        if pos >= *length {
+            return Err(SamError::ReferenceOutOfBounds { start: pos, end: pos + 1, length: *length });
+        }
+        Ok(offset + pos)
+    }
+
+    pub fn root(&self) -> F {
+        self.tree.root()
+    }
+
+    /// Number of layers including the leaves, as in `MerkleTree::height`.
+    pub fn height(&self) -> usize {
+        self.tree.height()
+    }
+
+    pub fn bases(&self) -> &[usize] {
+        &self.bases
+    }
+
+    /// Number of blocks opened for a window of `len` bases. A window may start anywhere in its first
+    /// block so this is one more than `len` needs when aligned.
+    pub fn window_blocks(len: usize) -> usize {
+        len.div_ceil(BASES_PER_BLOCK) + 1
+    }
+
+    /// Opens the blocks covering bases `start..start + len`, as many as a window of `max_len` bases
+    /// needs so that the number of blocks does not depend on `len`. `max_len` must not exceed the
+    /// maximum window length of the tree.
+    pub fn open_window(&self, start: usize, len: usize, max_len: usize) -> ReferenceWindow<F> {
+        assert!(start + len <= self.bases.len(), "window runs past the end of the reference");
+        assert!(len <= max_len, "window is longer than its maximum length");
+        let first_block = start / BASES_PER_BLOCK;
+        let block_range = first_block..first_block + Self::window_blocks(max_len);
+        assert!(block_range.end <= self.blocks.len(), "window is longer than the tree was built for");
+        ReferenceWindow {
+            start,
+            blocks: self.blocks[block_range.clone()].to_vec(),
+            paths: block_range.map(|i| self.tree.generate_proof(i).unwrap()).collect(),
+        }
+    }
+}
+
+/// Enforces that `block_vars` are the leaves `first_block..first_block + block_vars.len()` of the tree
+/// with root `root_var`, for a `first_block_var` that may be a witness. Each leaf position is decomposed
+/// into `tree_height - 1` bits tied to `first_block_var`, so the paths reveal nothing about where the
+/// window is.
+#[allow(clippy::too_many_arguments)]
+pub fn enforce_window_membership<F: PrimeField + Absorb>(
+    cs: ConstraintSystemRef<F>,
+    params: &PoseidonConfig<F>,
+    root_var: &FpVar<F>,
+    first_block_var: &FpVar<F>,
+    first_block: usize,
+    block_vars: &[FpVar<F>],
+    paths: &[ReferencePath<F>],
+    tree_height: usize,
+) -> Result<(), SynthesisError> {
+    let leaf_params = CRHParametersVar::new_constant(cs.clone(), params.clone())?;
+    for (i, (block_var, path)) in block_vars.iter().zip(paths.iter()).enumerate() {
+        let mut leaf_position = Vec::new();
+        for j in 0..tree_height - 1 {
+            leaf_position.push(Boolean::new_witness(cs.clone(), || Ok(((first_block + i) >> j) & 1 == 1))?);
+        }
+        Boolean::le_bits_to_fp_var(&leaf_position)?.enforce_equal(&(first_block_var + F::from(i as u64)))?;
+        let mut path_var = ReferencePathVar::new_witness(cs.clone(), || Ok(path))?;
+        path_var.set_leaf_position(leaf_position);
+        path_var
+            .verify_membership(&leaf_params, &leaf_params, root_var, std::slice::from_ref(block_var))?
+            .enforce_equal(&Boolean::TRUE)?;
+    }
+    Ok(())
+}
+
+/// Enforces that the unpadded slots of `bases` are the bases of the window in `opening`, whose blocks
+/// are opened against `root_var`. The window starts at a private offset into its first block, and
+/// `transcript` must fix `bases`, for instance by holding their commitment.
+pub fn enforce_window_contents<F: PrimeField + Absorb>(
+    cs: ConstraintSystemRef<F>,
+    opening: &ReferenceOpening<F>,
+    root_var: &FpVar<F>,
+    bases: &[UnpackedBase<F>],
+    transcript: &[FpVar<F>],
+) -> Result<(), SynthesisError> {
+    let window = &opening.window;
+    let first_block = window.start / BASES_PER_BLOCK;
+    let first_block_var = FpVar::new_witness(cs.clone(), || Ok(F::from(first_block as u64)))?;
+    let offset_var = FpVar::new_witness(cs.clone(), || Ok(F::from((window.start % BASES_PER_BLOCK) as u64)))?;
+    let mut block_vars = Vec::new();
+    for elem in window.blocks.iter() {
+        block_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+    }
+    enforce_window_membership(cs.clone(), &poseidon_parameters(), root_var, &first_block_var, first_block, &block_vars, &window.paths, opening.tree_height)?;
+
+    // Slot k of the opened blocks sits at address k - offset. Base i reads address i, which only
+    // exists if the whole window lies inside the opened blocks, so the offset needs no range check.
+    let mut cells = Vec::new();
+    for block in block_vars.iter() {
+        for slot in unpack_bases(block)? {
+            let address = FpVar::constant(F::from(cells.len() as u64)) - &offset_var;
+            cells.push(MemoryCell { address, value: slot.value, present: FpVar::one() });
+        }
+    }
+    let mut memory = ReadOnlyMemoryVar::new(cs.clone(), cells, BITS_PER_BASE, 1);
+    for (i, base) in bases.iter().enumerate() {
+        let present = base.is_padding.not();
+        let value = memory.read(&FpVar::constant(F::from(i as u64)), &present)?;
+        (value - &base.value).mul_equals(&FpVar::from(present), &FpVar::zero())?;
+    }
+    let mut transcript = transcript.to_vec();
+    transcript.extend([root_var.clone(), first_block_var, offset_var]);
+    memory.finalize(&transcript)
+}
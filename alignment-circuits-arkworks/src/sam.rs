@@ -0,0 +1,455 @@
+// Builds AlignmentCircuit witnesses from SAM records aligned against a FASTA reference.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use ark_crypto_primitives::sponge::Absorb;
+use ark_ff::PrimeField;
+
+use crate::alignment::{
+    pack_bases, pack_cigar, AlignmentCircuit, ScoringScheme, BASE_A, BASE_C, BASE_G, BASE_N, BASE_T,
+    CIGAR_DELETION, CIGAR_INSERTION, CIGAR_MATCH, CIGAR_MISMATCH, CIGAR_SOFT_CLIP, MAX_QUALITY,
+};
+
+const FLAG_UNMAPPED: u16 = 0x4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SamError {
+    MalformedRecord(String),
+    MalformedCigar(String),
+    UnsupportedCigarOp(char),
+    InvalidBase(char),
+    InvalidQuality(char),
+    Unmapped,
+    UnknownReference(String),
+    DuplicateReference(String),
+    ReferenceOutOfBounds { start: usize, end: usize, length: usize },
+    SequenceLengthMismatch { cigar: usize, sequence: usize },
+    QualityLengthMismatch { quality: usize, sequence: usize },
+    Io(String),
+}
+
+impl fmt::Display for SamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SamError::MalformedRecord(reason) => write!(f, "malformed SAM record: {}", reason),
+            SamError::MalformedCigar(cigar) => write!(f, "malformed CIGAR string: {}", cigar),
+            SamError::UnsupportedCigarOp(op) => write!(f, "CIGAR operation {} is not supported by the alignment circuit", op),
+            SamError::InvalidBase(base) => write!(f, "{:?} is not a nucleotide or IUPAC ambiguity code", base),
+            SamError::InvalidQuality(quality) => write!(f, "{:?} is not a Phred+33 quality", quality),
+            SamError::Unmapped => write!(f, "record is unmapped"),
+            SamError::UnknownReference(name) => write!(f, "reference sequence {} is not in the FASTA file", name),
+            SamError::DuplicateReference(name) => write!(f, "reference sequence {} appears more than once in the FASTA file", name),
+            SamError::ReferenceOutOfBounds { start, end, length } => {
+                write!(f, "alignment covers reference positions {}..{} but the reference has {} bases", start, end, length)
+            }
+            SamError::SequenceLengthMismatch { cigar, sequence } => {
+                write!(f, "CIGAR consumes {} read bases but SEQ has {}", cigar, sequence)
+            }
+            SamError::QualityLengthMismatch { quality, sequence } => {
+                write!(f, "QUAL has {} qualities but SEQ has {} bases", quality, sequence)
+            }
+            SamError::Io(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SamError {}
+
+/// The fields of a SAM alignment line that the alignment circuit needs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SamRecord {
+    pub qname: String,
+    pub flag: u16,
+    pub rname: String,
+    pub pos: usize, // 1-based leftmost reference position, 0 if unmapped
+    pub cigar: String,
+    pub seq: String,
+    pub qual: String, // Phred+33, or * if absent
+}
+
+impl SamRecord {
+    /// Parses one tab-separated SAM alignment line.
+    pub fn parse(line: &str) -> Result<Self, SamError> {
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        if fields.len() < 11 {
+            return Err(SamError::MalformedRecord(format!("expected 11 fields, found {}", fields.len())));
+        }
+        let flag = fields[1].parse().map_err(|_| SamError::MalformedRecord(format!("bad FLAG {}", fields[1])))?;
+        let pos = fields[3].parse().map_err(|_| SamError::MalformedRecord(format!("bad POS {}", fields[3])))?;
+        Ok(Self {
+            qname: fields[0].to_string(),
+            flag,
+            rname: fields[2].to_string(),
+            pos,
+            cigar: fields[5].to_string(),
+            seq: fields[9].to_string(),
+            qual: fields[10].to_string(),
+        })
+    }
+
+    /// The base qualities, capped at MAX_QUALITY, or None if the record has none.
+    pub fn qualities(&self) -> Result<Option<Vec<usize>>, SamError> {
+        if self.qual == "*" {
+            return Ok(None);
+        }
+        let qualities = encode_qualities(self.qual.as_bytes())?;
+        if qualities.len() != self.seq.len() {
+            return Err(SamError::QualityLengthMismatch { quality: qualities.len(), sequence: self.seq.len() });
+        }
+        Ok(Some(qualities))
+    }
+
+    pub fn is_unmapped(&self) -> bool {
+        self.flag & FLAG_UNMAPPED != 0 || self.pos == 0 || self.cigar == "*"
+    }
+}
+
+/// Reference sequences from a FASTA file, keyed by the first word of their header.
+#[derive(Clone, Debug, Default)]
+pub struct FastaReference {
+    sequences: HashMap<String, Vec<u8>>,
+    names: Vec<String>, // in file order
+}
+
+impl FastaReference {
+    pub fn parse(text: &str) -> Result<Self, SamError> {
+        let mut sequences = HashMap::new();
+        let mut names = Vec::new();
+        let mut current: Option<(String, Vec<u8>)> = None;
+        for line in text.lines() {
+            let line = line.trim_end();
+            if let Some(header) = line.strip_prefix('>') {
+                if let Some((name, sequence)) = current.take() {
+                    names.push(name.clone());
+                    sequences.insert(name, sequence);
+                }
+                let name = header.split_whitespace().next().unwrap_or_default().to_string();
+                if sequences.contains_key(&name) {
+                    return Err(SamError::DuplicateReference(name));
+                }
+                current = Some((name, Vec::new()));
+            } else if !line.is_empty() {
+                match current.as_mut() {
+                    Some((_, sequence)) => sequence.extend(line.bytes().map(|b| b.to_ascii_uppercase())),
+                    None => return Err(SamError::MalformedRecord("FASTA sequence before the first header".to_string())),
+                }
+            }
+        }
+        if let Some((name, sequence)) = current {
+            names.push(name.clone());
+            sequences.insert(name, sequence);
+        }
+        Ok(Self { sequences, names })
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, SamError> {
+        let text = std::fs::read_to_string(path.as_ref()).map_err(|e| SamError::Io(format!("{}: {}", path.as_ref().display(), e)))?;
+        Self::parse(&text)
+    }
+
+    pub fn sequence(&self, name: &str) -> Option<&[u8]> {
+        self.sequences.get(name).map(|sequence| sequence.as_slice())
+    }
+
+    /// Names of the sequences in the order they appear in the file.
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+}
+
+/// Encodes a nucleotide (either case) as the base used by the circuit. N and the other IUPAC ambiguity
+/// codes all become BASE_N.
+pub fn encode_base(base: u8) -> Result<usize, SamError> {
+    match base.to_ascii_uppercase() {
+        b'A' => Ok(BASE_A),
+        b'C' => Ok(BASE_C),
+        b'G' => Ok(BASE_G),
+        b'T' => Ok(BASE_T),
+        b'N' | b'R' | b'Y' | b'S' | b'W' | b'K' | b'M' | b'B' | b'D' | b'H' | b'V' => Ok(BASE_N),
+        _ => Err(SamError::InvalidBase(base as char)),
+    }
+}
+
+pub fn encode_sequence(sequence: &[u8]) -> Result<Vec<usize>, SamError> {
+    sequence.iter().map(|&base| encode_base(base)).collect()
+}
+
+/// Decodes Phred+33 qualities, capping them at MAX_QUALITY, which is beyond what sequencers report.
+pub fn encode_qualities(qualities: &[u8]) -> Result<Vec<usize>, SamError> {
+    qualities
+        .iter()
+        .map(|&quality| match quality {
+            b'!'..=b'~' => Ok(((quality - b'!') as usize).min(MAX_QUALITY)),
+            _ => Err(SamError::InvalidQuality(quality as char)),
+        })
+        .collect()
+}
+
+/// Splits a run-length CIGAR such as `50M2I30M` into (length, operation) pairs.
+pub fn parse_cigar(cigar: &str) -> Result<Vec<(usize, char)>, SamError> {
+    let mut runs = Vec::new();
+    let mut length = String::new();
+    for c in cigar.chars() {
+        if c.is_ascii_digit() {
+            length.push(c);
+        } else {
+            let run = length.parse().map_err(|_| SamError::MalformedCigar(cigar.to_string()))?;
+            runs.push((run, c));
+            length.clear();
+        }
+    }
+    if runs.is_empty() || !length.is_empty() {
+        return Err(SamError::MalformedCigar(cigar.to_string()));
+    }
+    Ok(runs)
+}
+
+/// Expands a run-length CIGAR into the per-base operation vector of the circuit. `M` positions become
+/// matches or mismatches depending on the bases they align, with N never matching. Hard clips are
+/// dropped since their bases are not in SEQ. As in the SAM specification, hard clips may only be the
+/// first or last operation, and soft clips may only have hard clips between them and the ends.
+pub fn expand_cigar(cigar: &str, reference_bases: &[usize], target_bases: &[usize]) -> Result<Vec<usize>, SamError> {
+    let runs = parse_cigar(cigar)?;
+    let is_op = |run: Option<&(usize, char)>, clip: char| run.is_some_and(|&(_, op)| op == clip);
+    let mut inner = runs.as_slice();
+    for clip in ['H', 'S'] {
+        if is_op(inner.first(), clip) {
+            inner = &inner[1..];
+        }
+        if is_op(inner.last(), clip) {
+            inner = &inner[..inner.len() - 1];
+        }
+    }
+    if inner.iter().any(|&(_, op)| op == 'H' || op == 'S') {
+        return Err(SamError::MalformedCigar(cigar.to_string()));
+    }
+
+    let mut ops = Vec::new();
+    let mut target_index = 0usize;
+    let mut reference_index = 0usize;
+    for (run, op) in runs {
+        if op == 'H' {
+            continue;
+        }
+        for _ in 0..run {
+            let aligned = match op {
+                'M' | '=' | 'X' => {
+                    let pair = reference_bases.get(reference_index).zip(target_bases.get(target_index));
+                    let (reference_base, target_base) = pair.ok_or(SamError::MalformedCigar(cigar.to_string()))?;
+                    target_index += 1;
+                    reference_index += 1;
+                    Some(reference_base == target_base && *reference_base != BASE_N)
+                }
+                'I' | 'S' => {
+                    target_index += 1;
+                    None
+                }
+                'D' => {
+                    reference_index += 1;
+                    None
+                }
+                'N' | 'P' => return Err(SamError::UnsupportedCigarOp(op)),
+                _ => return Err(SamError::MalformedCigar(cigar.to_string())),
+            };
+            ops.push(match (op, aligned) {
+                ('I', _) => CIGAR_INSERTION,
+                ('S', _) => CIGAR_SOFT_CLIP,
+                ('D', _) => CIGAR_DELETION,
+                ('=', Some(false)) | ('X', Some(true)) => return Err(SamError::MalformedCigar(cigar.to_string())),
+                (_, Some(true)) => CIGAR_MATCH,
+                _ => CIGAR_MISMATCH,
+            });
+        }
+    }
+    Ok(ops)
+}
+
+/// Number of reference bases a CIGAR spans.
+pub fn reference_span(cigar: &str) -> Result<usize, SamError> {
+    Ok(parse_cigar(cigar)?.into_iter().filter(|(_, op)| matches!(op, 'M' | '=' | 'X' | 'D' | 'N')).map(|(run, _)| run).sum())
+}
+
+/// Builds the circuit proving that `record` aligns to its window of `reference` with the score
+/// `scoring_scheme` assigns it.
+pub fn alignment_circuit_from_sam<F: PrimeField + Absorb>(
+    record: &SamRecord,
+    reference: &FastaReference,
+    scoring_scheme: ScoringScheme,
+) -> Result<AlignmentCircuit<F>, SamError> {
+    if record.is_unmapped() {
+        return Err(SamError::Unmapped);
+    }
+    let chromosome = reference.sequence(&record.rname).ok_or_else(|| SamError::UnknownReference(record.rname.clone()))?;
+    let start = record.pos - 1;
+    let end = start + reference_span(&record.cigar)?;
+    if end > chromosome.len() {
+        return Err(SamError::ReferenceOutOfBounds { start, end, length: chromosome.len() });
+    }
+
+    let reference_sequence_bases = encode_sequence(&chromosome[start..end])?;
+    let target_sequence_bases = encode_sequence(record.seq.as_bytes())?;
+    let cigar_string_bases = expand_cigar(&record.cigar, &reference_sequence_bases, &target_sequence_bases)?;
+    let consumed = cigar_string_bases.iter().filter(|&&op| op != CIGAR_DELETION).count();
+    if consumed != target_sequence_bases.len() {
+        return Err(SamError::SequenceLengthMismatch { cigar: consumed, sequence: target_sequence_bases.len() });
+    }
+
+    let alignment_score = scoring_scheme.score(&reference_sequence_bases, &target_sequence_bases, &cigar_string_bases);
+    Ok(AlignmentCircuit::new(
+        pack_bases(&reference_sequence_bases),
+        reference_sequence_bases,
+        pack_bases(&target_sequence_bases),
+        target_sequence_bases,
+        pack_cigar(&cigar_string_bases),
+        cigar_string_bases,
+        scoring_scheme,
+        alignment_score,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::MaxLengths;
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+
+    const FASTA: &str = ">chr1 test chromosome\nTTTTACGTACGTAC\nGTACGTACGGGG\n>chr2\nACNNGT\n";
+
+    #[test]
+    fn expands_run_length_cigar() {
+        assert_eq!(parse_cigar("50M2I30M").unwrap(), vec![(50, 'M'), (2, 'I'), (30, 'M')]);
+        let reference = encode_sequence(b"ACGTACG").unwrap();
+        let target = encode_sequence(b"ACCGGTC").unwrap();
+        let ops = expand_cigar("2M1I2M1D2M", &reference, &target).unwrap();
+        assert_eq!(ops, vec![0, 0, CIGAR_INSERTION, 0, CIGAR_MISMATCH, CIGAR_DELETION, CIGAR_MISMATCH, CIGAR_MISMATCH]);
+    }
+
+    #[test]
+    fn rejects_unsupported_input() {
+        let reference = FastaReference::parse(FASTA).unwrap();
+        let scheme = ScoringScheme::bwa_mem();
+        let spliced = SamRecord::parse("r1\t0\tchr1\t5\t60\t2M3N2M\t*\t0\t0\tACAC\t*").unwrap();
+        assert_eq!(alignment_circuit_from_sam::<Fr>(&spliced, &reference, scheme).err(), Some(SamError::UnsupportedCigarOp('N')));
+        let gapped = SamRecord::parse("r2\t0\tchr1\t5\t60\t4M\t*\t0\t0\tAC-T\t*").unwrap();
+        assert_eq!(alignment_circuit_from_sam::<Fr>(&gapped, &reference, scheme).err(), Some(SamError::InvalidBase('-')));
+        let duplicated = format!("{}>chr1\nACGT\n", FASTA);
+        assert_eq!(FastaReference::parse(&duplicated).err(), Some(SamError::DuplicateReference("chr1".to_string())));
+    }
+
+    #[test]
+    fn builds_satisfied_circuit() {
+        let reference = FastaReference::parse(FASTA).unwrap();
+        let record = SamRecord::parse("r1\t0\tchr1\t5\t60\t3M2I4M1D5M\t*\t0\t0\tACGAATACGACGTC\t*").unwrap();
+        let circuit = alignment_circuit_from_sam::<Fr>(&record, &reference, ScoringScheme::bwa_mem()).unwrap();
+        assert_eq!(circuit.alignment_score, 11 - 4 - (6 + 2) - (6 + 1));
+        let cs = ConstraintSystem::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn soft_clips_only_at_the_ends() {
+        let reference = FastaReference::parse(FASTA).unwrap();
+        let record = SamRecord::parse("r4\t0\tchr1\t5\t60\t3H2S4M1S\t*\t0\t0\tGGACGTC\t*").unwrap();
+        let circuit = alignment_circuit_from_sam::<Fr>(&record, &reference, ScoringScheme::bwa_mem()).unwrap();
+        assert_eq!(circuit.cigar_string_bases, vec![CIGAR_SOFT_CLIP, CIGAR_SOFT_CLIP, 0, 0, 0, 0, CIGAR_SOFT_CLIP]);
+        assert_eq!(circuit.alignment_score, 4);
+        let cs = ConstraintSystem::new_ref();
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // Clips anywhere but the ends are rejected before building a circuit.
+        for cigar in ["2S2M1S2M", "2M3H5M", "3S4H4M", "2M1H2S3M", "4M1S2M1S"] {
+            assert_eq!(expand_cigar(cigar, &encode_sequence(b"ACGTACGT").unwrap(), &encode_sequence(b"GGACGTC").unwrap()), Err(SamError::MalformedCigar(cigar.to_string())));
+        }
+
+        // Clipping the middle of GGACGTC as 2S2M1S2M must fail even though the bases line up.
+        let mut forced = circuit;
+        forced.reference_sequence_bases = encode_sequence(b"ACTC").unwrap();
+        forced.reference_sequence_felts = pack_bases(&forced.reference_sequence_bases);
+        forced.cigar_string_bases = vec![CIGAR_SOFT_CLIP, CIGAR_SOFT_CLIP, 0, 0, CIGAR_SOFT_CLIP, 0, 0];
+        forced.cigar_string_felts = pack_cigar(&forced.cigar_string_bases);
+        let cs = ConstraintSystem::new_ref();
+        forced.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn ambiguous_bases_never_match() {
+        let reference = FastaReference::parse(FASTA).unwrap();
+        let record = SamRecord::parse("r3\t0\tchr2\t1\t60\t6M\t*\t0\t0\tACNRGT\t*").unwrap();
+        let circuit = alignment_circuit_from_sam::<Fr>(&record, &reference, ScoringScheme::bwa_mem()).unwrap();
+        assert_eq!(circuit.cigar_string_bases, vec![0, 0, CIGAR_MISMATCH, CIGAR_MISMATCH, 0, 0]);
+        assert_eq!(circuit.alignment_score, 4 - 1 - 1);
+        let cs = ConstraintSystem::new_ref();
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        let mut forced = circuit;
+        forced.cigar_string_bases[2] = CIGAR_MATCH;
+        forced.cigar_string_felts = pack_cigar(&forced.cigar_string_bases);
+        forced.alignment_score = 5 - 1;
+        let cs = ConstraintSystem::new_ref();
+        forced.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn weights_score_by_base_quality() {
+        let reference = FastaReference::parse(FASTA).unwrap();
+        let record = SamRecord::parse("r1\t0\tchr1\t5\t60\t3M2I4M1D5M\t*\t0\t0\tACGAATACGACGTC\tIII?+III5IIII#").unwrap();
+        let qualities = record.qualities().unwrap().unwrap();
+        assert_eq!(&qualities[2..5], &[40, 30, 10]);
+        let circuit = alignment_circuit_from_sam::<Fr>(&record, &reference, ScoringScheme::bwa_mem()).unwrap();
+        let max_lengths = MaxLengths { reference: 100, target: 100, cigar: 100 };
+        let weighted = circuit.with_qualities(qualities, Fr::from(7u64)).with_min_mean_quality(33).pad_to(&max_lengths);
+        // The insertion opens at quality 30 and extends at 10, the deletion follows a base of quality 20
+        // and the mismatch is at quality 2. The qualities sum to 462, a mean of 33.
+        assert_eq!(weighted.alignment_score, 11 - 7 * 30 - 10 - 7 * 20 - 4 * 2);
+        let cs = ConstraintSystem::new_ref();
+        weighted.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.num_instance_variables(), weighted.public_inputs().len() + 1);
+
+        let mut unweighted_score = weighted.clone();
+        unweighted_score.alignment_score = -8;
+        let mut too_high_bound = weighted;
+        too_high_bound.min_mean_quality = Some(34);
+        for circuit in [unweighted_score, too_high_bound] {
+            let cs = ConstraintSystem::new_ref();
+            circuit.generate_constraints(cs.clone()).unwrap();
+            assert!(!cs.is_satisfied().unwrap());
+        }
+
+        let truncated = SamRecord::parse("r1\t0\tchr1\t5\t60\t4M\t*\t0\t0\tACGT\tIII").unwrap();
+        assert_eq!(truncated.qualities(), Err(SamError::QualityLengthMismatch { quality: 3, sequence: 4 }));
+    }
+
+    #[test]
+    fn padded_circuits_share_a_shape() {
+        let reference = FastaReference::parse(FASTA).unwrap();
+        let max_lengths = MaxLengths { reference: 100, target: 100, cigar: 100 };
+        let short = SamRecord::parse("r1\t0\tchr1\t5\t60\t4M\t*\t0\t0\tACGT\t*").unwrap();
+        let long = SamRecord::parse("r2\t0\tchr1\t5\t60\t3M2I4M1D5M\t*\t0\t0\tACGAATACGACGTC\t*").unwrap();
+        let mut num_constraints = Vec::new();
+        for record in [short, long] {
+            let circuit = alignment_circuit_from_sam::<Fr>(&record, &reference, ScoringScheme::bwa_mem()).unwrap().pad_to(&max_lengths);
+            let cs = ConstraintSystem::new_ref();
+            circuit.generate_constraints(cs.clone()).unwrap();
+            assert!(cs.is_satisfied().unwrap());
+            num_constraints.push((cs.num_constraints(), cs.num_instance_variables()));
+        }
+        assert_eq!(num_constraints[0], num_constraints[1]);
+
+        // Claiming a shorter read than the packed one must fail.
+        let record = SamRecord::parse("r1\t0\tchr1\t5\t60\t4M\t*\t0\t0\tACGT\t*").unwrap();
+        let mut truncated = alignment_circuit_from_sam::<Fr>(&record, &reference, ScoringScheme::bwa_mem()).unwrap().pad_to(&max_lengths);
+        truncated.target_sequence_bases.pop();
+        let cs = ConstraintSystem::new_ref();
+        truncated.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+}
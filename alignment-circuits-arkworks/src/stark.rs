@@ -0,0 +1,677 @@
+// The alignment relation of AlignmentCircuit as a Plonky3 AIR over BabyBear, proven with a FRI-based
+// STARK that needs no trusted setup. The trace has one row per CIGAR operation holding the operation,
+// the bases it reads, the running indices and score, and the running memcheck products of the reads.
+//
+// The STARK is not zero knowledge, so unlike the Groth16 circuit the sequences are part of the public
+// statement rather than hidden behind commitments; only the CIGAR string is a witness. The verifier
+// computes the "write" side of the memory checks from the sequences itself, and the trace must read
+// them back.
+//
+// The memcheck challenges are sampled in the degree-4 extension, and the running products are kept as
+// its limbs. The uni-stark prover of Plonky3 0.2 commits to a single trace, so the proof is assembled
+// here from its folders in two phases: the walk is committed first, the challenges are drawn from a
+// transcript holding the statement and that commitment, and only then are the product columns filled
+// and committed. The quotient and the openings treat both commitments as one trace, and the AIR reads
+// the challenges and the "write" products among its public values. The reads are fixed before the
+// challenges are known, so by Schwartz-Zippel each repetition passes a wrong read with probability at
+// most the number of rows over the size of the extension, about 2^-124.
+
+use std::fmt;
+use std::ops::Mul;
+use std::panic::{self, AssertUnwindSafe};
+
+use p3_air::{Air, AirBuilder, AirBuilderWithPublicValues, BaseAir};
+use p3_challenger::{CanObserve, DuplexChallenger, FieldChallenger};
+use p3_commit::{ExtensionMmcs, Pcs, PolynomialSpace};
+use p3_dft::Radix2DitParallel;
+use p3_field::extension::{BinomialExtensionField, BinomiallyExtendable};
+use p3_field::{AbstractExtensionField, AbstractField, ExtensionField, Field, PackedValue};
+use p3_fri::{FriConfig, TwoAdicFriPcs};
+use p3_matrix::dense::{RowMajorMatrix, RowMajorMatrixView};
+use p3_matrix::stack::VerticalPair;
+use p3_matrix::Matrix;
+use p3_uni_stark::{get_log_quotient_degree, ProverConstraintFolder, StarkConfig, StarkGenericConfig, VerifierConstraintFolder};
+use serde::{Deserialize, Serialize};
+use ark_crypto_primitives::sponge::Absorb;
+use ark_ff::PrimeField;
+
+use crate::alignment::{
+    AlignmentCircuit, ScoringScheme, BASE_N, CIGAR_DELETION, CIGAR_INSERTION, CIGAR_MATCH, CIGAR_MISMATCH,
+    CIGAR_PADDING, CIGAR_SOFT_CLIP, NUM_BASES,
+};
+use crate::genome_commitment::{genome_mmcs, genome_permutation, GenomeMmcs, Perm, Val};
+
+const EXTENSION_DEGREE: usize = 4;
+
+type Challenge = BinomialExtensionField<Val, EXTENSION_DEGREE>;
+type ChallengeMmcs = ExtensionMmcs<Val, Challenge, GenomeMmcs>;
+type Challenger = DuplexChallenger<Val, Perm, 16, 8>;
+type FriPcs = TwoAdicFriPcs<Val, Radix2DitParallel, GenomeMmcs, ChallengeMmcs>;
+type Commitment = <FriPcs as Pcs<Challenge, Challenger>>::Commitment;
+type Domain = <FriPcs as Pcs<Challenge, Challenger>>::Domain;
+type PackedVal = <Val as Field>::Packing;
+type PackedChallenge = <Challenge as ExtensionField<Val>>::ExtensionPacking;
+pub type AlignmentStarkConfig = StarkConfig<FriPcs, Challenge, Challenger>;
+
+// Independent pairs of challenges, each of which a cheating prover must defeat at once.
+pub const MEMCHECK_REPETITIONS: usize = 2;
+
+// FRI parameters, for about 100 bits of conjectured security.
+const LOG_BLOWUP: usize = 2;
+const NUM_QUERIES: usize = 42;
+const PROOF_OF_WORK_BITS: usize = 16;
+
+// Trace columns. The operation and the two bases read are one-hot encoded, indexed by their codes.
+const OP: usize = 0;
+const REFERENCE_BASE: usize = OP + 6;
+const TARGET_BASE: usize = REFERENCE_BASE + NUM_BASES;
+const REFERENCE_INDEX: usize = TARGET_BASE + NUM_BASES;
+const TARGET_INDEX: usize = REFERENCE_INDEX + 1;
+const SCORE: usize = TARGET_INDEX + 1;
+const PREVIOUS_IS_INSERTION: usize = SCORE + 1;
+const PREVIOUS_IS_DELETION: usize = PREVIOUS_IS_INSERTION + 1;
+const ALIGNED_SO_FAR: usize = PREVIOUS_IS_DELETION + 1;
+const CLIPPED_AFTER_ALIGNED: usize = ALIGNED_SO_FAR + 1;
+// The product columns are the second phase of the trace, committed once the challenges are drawn.
+const PRODUCTS: usize = CLIPPED_AFTER_ALIGNED + 1; // reference then target product for every repetition, as limbs
+const NUM_PRODUCT_COLUMNS: usize = 2 * EXTENSION_DEGREE * MEMCHECK_REPETITIONS;
+const NUM_COLUMNS: usize = PRODUCTS + NUM_PRODUCT_COLUMNS;
+
+// Public values of the AIR: the substitution matrix, the score, the lengths, then for every repetition
+// the limbs of the two challenges and of the products of the reference and target "writes".
+const PUBLIC_SCORE: usize = 16;
+const PUBLIC_REFERENCE_LENGTH: usize = PUBLIC_SCORE + 1;
+const PUBLIC_TARGET_LENGTH: usize = PUBLIC_REFERENCE_LENGTH + 1;
+const PUBLIC_MEMCHECK: usize = PUBLIC_TARGET_LENGTH + 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StarkError {
+    InvalidProof(String),
+}
+
+impl fmt::Display for StarkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StarkError::InvalidProof(reason) => write!(f, "alignment proof does not verify: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StarkError {}
+
+pub fn i64_to_val<F: AbstractField>(score: i64) -> F {
+    let magnitude = F::from_canonical_u64(score.unsigned_abs());
+    if score < 0 { -magnitude } else { magnitude }
+}
+
+/// The public side of an alignment proof: both sequences, the scoring scheme and the claimed score.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AlignmentStatement {
+    pub reference_sequence_bases: Vec<usize>,
+    pub target_sequence_bases: Vec<usize>,
+    pub scoring_scheme: ScoringScheme,
+    pub alignment_score: i64,
+}
+
+impl AlignmentStatement {
+    /// A transcript of the statement and the height of the trace, which the prover and the verifier
+    /// continue with the commitments.
+    fn challenger(&self, degree_bits: usize) -> Challenger {
+        let mut challenger = Challenger::new(genome_permutation());
+        for sequence in [&self.reference_sequence_bases, &self.target_sequence_bases] {
+            challenger.observe(Val::from_canonical_usize(sequence.len()));
+            challenger.observe_slice(&sequence.iter().map(|&base| Val::from_canonical_usize(base)).collect::<Vec<_>>());
+        }
+        challenger.observe_slice(&self.scoring_parameters());
+        challenger.observe(i64_to_val::<Val>(self.alignment_score));
+        challenger.observe(Val::from_canonical_usize(degree_bits));
+        challenger
+    }
+
+    fn scoring_parameters(&self) -> Vec<Val> {
+        let scheme = &self.scoring_scheme;
+        let mut parameters: Vec<Val> = scheme.substitution_matrix.iter().flatten().map(|&score| i64_to_val(score)).collect();
+        parameters.extend([scheme.match_score, scheme.ambiguous_score, scheme.gap_open, scheme.gap_extend].map(i64_to_val::<Val>));
+        parameters
+    }
+
+    /// Public values of the AIR under the memcheck `challenges`, in the order it reads them.
+    fn public_values(&self, challenges: &[[Challenge; 2]]) -> Vec<Val> {
+        let mut values: Vec<Val> = self.scoring_parameters()[..16].to_vec();
+        values.push(i64_to_val(self.alignment_score));
+        values.push(Val::from_canonical_usize(self.reference_sequence_bases.len()));
+        values.push(Val::from_canonical_usize(self.target_sequence_bases.len()));
+        for &[alpha, beta] in challenges {
+            values.extend_from_slice(alpha.as_base_slice());
+            values.extend_from_slice(beta.as_base_slice());
+            for sequence in [&self.reference_sequence_bases, &self.target_sequence_bases] {
+                let product: Challenge = sequence
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &base)| memcheck_term(alpha, beta, Val::from_canonical_usize(i), Val::from_canonical_usize(base)))
+                    .product();
+                values.extend_from_slice(product.as_base_slice());
+            }
+        }
+        values
+    }
+}
+
+/// The memcheck challenges, MEMCHECK_REPETITIONS pairs.
+fn sample_challenges(challenger: &mut Challenger) -> Vec<[Challenge; 2]> {
+    (0..MEMCHECK_REPETITIONS).map(|_| [challenger.sample_ext_element(), challenger.sample_ext_element()]).collect()
+}
+
+fn memcheck_term(alpha: Challenge, beta: Challenge, index: Val, base: Val) -> Challenge {
+    alpha + base + beta * index
+}
+
+/// Multiplies two elements of the extension given by their limbs, reducing by x^EXTENSION_DEGREE = w.
+fn extension_mul<F: Field, E: AbstractField + Mul<F, Output = E>>(
+    a: &[E; EXTENSION_DEGREE],
+    b: &[E; EXTENSION_DEGREE],
+    w: F,
+) -> [E; EXTENSION_DEGREE] {
+    let mut product: [E; EXTENSION_DEGREE] = std::array::from_fn(|_| E::zero());
+    for (i, a_limb) in a.iter().enumerate() {
+        for (j, b_limb) in b.iter().enumerate() {
+            let term = a_limb.clone() * b_limb.clone();
+            if i + j < EXTENSION_DEGREE {
+                product[i + j] += term;
+            } else {
+                product[i + j - EXTENSION_DEGREE] += term * w;
+            }
+        }
+    }
+    product
+}
+
+/// The CIGAR walk of `enforce_cigar_walk` as an AIR. The parameters other than the substitution matrix
+/// are fixed in the AIR, as they are fixed at setup time for the circuit.
+pub struct AlignmentAir {
+    pub scoring_scheme: ScoringScheme,
+}
+
+impl<F> BaseAir<F> for AlignmentAir {
+    fn width(&self) -> usize {
+        NUM_COLUMNS
+    }
+}
+
+impl<AB: AirBuilderWithPublicValues> Air<AB> for AlignmentAir
+where
+    AB::F: BinomiallyExtendable<EXTENSION_DEGREE>,
+{
+    fn eval(&self, builder: &mut AB) {
+        let main = builder.main();
+        let public_values: Vec<AB::Expr> = builder.public_values().iter().map(|&value| value.into()).collect();
+        let (local, next) = (main.row_slice(0), main.row_slice(1));
+        let (local, next): (&[AB::Var], &[AB::Var]) = (&local, &next);
+        let val = i64_to_val::<AB::F>;
+
+        // Every one-hot group holds exactly one set bit.
+        for (start, len) in [(OP, 6), (REFERENCE_BASE, NUM_BASES), (TARGET_BASE, NUM_BASES)] {
+            let mut sum = AB::Expr::zero();
+            for &bit in &local[start..start + len] {
+                builder.assert_bool(bit);
+                sum += bit.into();
+            }
+            builder.assert_one(sum);
+        }
+        let op = |code: usize| -> AB::Expr { local[OP + code].into() };
+        let (is_match, is_insertion, is_deletion) = (op(CIGAR_MATCH), op(CIGAR_INSERTION), op(CIGAR_DELETION));
+        let (is_mismatch, is_padding, is_soft_clip) = (op(CIGAR_MISMATCH), op(CIGAR_PADDING), op(CIGAR_SOFT_CLIP));
+        let reference_basis = &local[REFERENCE_BASE..REFERENCE_BASE + NUM_BASES];
+        let target_basis = &local[TARGET_BASE..TARGET_BASE + NUM_BASES];
+        let symbol = |basis: &[AB::Var]| -> AB::Expr {
+            basis.iter().enumerate().map(|(code, &bit)| bit * AB::F::from_canonical_usize(code)).sum()
+        };
+        let (reference_base, target_base) = (symbol(reference_basis), symbol(target_basis));
+
+        // Bases match when they are equal and not N.
+        let bases_match: AB::Expr = (0..BASE_N).map(|code| reference_basis[code] * target_basis[code]).sum();
+        builder.assert_zero(is_match.clone() * (AB::Expr::one() - bases_match.clone()));
+        builder.assert_zero(is_mismatch.clone() * bases_match);
+
+        let mut substitution_score = reference_basis[BASE_N] * val(self.scoring_scheme.ambiguous_score);
+        for (a, &reference_weight) in reference_basis.iter().take(4).enumerate() {
+            let mut row = target_basis[BASE_N] * val(self.scoring_scheme.ambiguous_score);
+            for (b, &target_weight) in target_basis.iter().take(4).enumerate() {
+                row += public_values[4 * a + b].clone() * target_weight;
+            }
+            substitution_score += row * reference_weight;
+        }
+        let gaps = is_insertion.clone() + is_deletion.clone();
+        let opens = is_insertion.clone() * (AB::Expr::one() - local[PREVIOUS_IS_INSERTION])
+            + is_deletion.clone() * (AB::Expr::one() - local[PREVIOUS_IS_DELETION]);
+        let score_delta = is_match.clone() * val(self.scoring_scheme.match_score)
+            + opens * val(self.scoring_scheme.gap_open)
+            + gaps.clone() * val(self.scoring_scheme.gap_extend)
+            + is_mismatch.clone() * substitution_score;
+
+        let is_aligned = is_match.clone() + is_mismatch.clone() + gaps.clone();
+        let consumes_target = is_match.clone() + is_mismatch.clone() + is_insertion.clone() + is_soft_clip.clone();
+        let consumes_reference = is_match + is_mismatch + is_deletion.clone();
+        builder.assert_zero(local[CLIPPED_AFTER_ALIGNED] * is_aligned.clone());
+
+        let mut first_row = builder.when_first_row();
+        for column in [REFERENCE_INDEX, TARGET_INDEX, SCORE, PREVIOUS_IS_INSERTION, PREVIOUS_IS_DELETION, ALIGNED_SO_FAR, CLIPPED_AFTER_ALIGNED] {
+            first_row.assert_zero(local[column]);
+        }
+        for product in local[PRODUCTS..].chunks(EXTENSION_DEGREE) {
+            first_row.assert_one(product[0]);
+            for &limb in &product[1..] {
+                first_row.assert_zero(limb);
+            }
+        }
+
+        let mut transition = builder.when_transition();
+        transition.assert_eq(next[REFERENCE_INDEX], local[REFERENCE_INDEX] + consumes_reference.clone());
+        transition.assert_eq(next[TARGET_INDEX], local[TARGET_INDEX] + consumes_target.clone());
+        transition.assert_eq(next[SCORE], local[SCORE] + score_delta);
+        transition.assert_eq(next[PREVIOUS_IS_INSERTION], is_insertion);
+        transition.assert_eq(next[PREVIOUS_IS_DELETION], is_deletion);
+        // Padding may only be followed by more padding.
+        transition.assert_zero(is_padding.clone() * (AB::Expr::one() - next[OP + CIGAR_PADDING]));
+        let aligned_so_far: AB::Expr = local[ALIGNED_SO_FAR].into();
+        transition.assert_eq(next[ALIGNED_SO_FAR], aligned_so_far.clone() + is_aligned.clone() - aligned_so_far.clone() * is_aligned);
+        let clips = is_soft_clip * aligned_so_far;
+        transition.assert_eq(next[CLIPPED_AFTER_ALIGNED], clips.clone() + local[CLIPPED_AFTER_ALIGNED] - clips * local[CLIPPED_AFTER_ALIGNED]);
+
+        // Each product multiplies in the term of a base read, or 1 if the operation reads none. The
+        // base and index are in the base field, so only the first limb of the term depends on the base.
+        for repetition in 0..MEMCHECK_REPETITIONS {
+            let limbs = |start: usize| -> [AB::Expr; EXTENSION_DEGREE] { std::array::from_fn(|k| public_values[start + k].clone()) };
+            let alpha = limbs(public_memcheck(repetition, 0));
+            let beta = limbs(public_memcheck(repetition, 1));
+            let reads = [
+                (product_column(repetition, 0), &consumes_reference, &reference_base, REFERENCE_INDEX),
+                (product_column(repetition, 1), &consumes_target, &target_base, TARGET_INDEX),
+            ];
+            for (column, consumes, base, index) in reads {
+                let factor: [AB::Expr; EXTENSION_DEGREE] = std::array::from_fn(|k| {
+                    let term = alpha[k].clone() + beta[k].clone() * local[index];
+                    if k == 0 {
+                        consumes.clone() * (term + base.clone() - AB::Expr::one()) + AB::Expr::one()
+                    } else {
+                        consumes.clone() * term
+                    }
+                });
+                let product: [AB::Expr; EXTENSION_DEGREE] = std::array::from_fn(|k| local[column + k].into());
+                for (k, limb) in extension_mul(&factor, &product, AB::F::w()).into_iter().enumerate() {
+                    transition.assert_eq(next[column + k], limb);
+                }
+            }
+        }
+
+        // The last row is padding, so it holds the totals of the whole walk.
+        let mut last_row = builder.when_last_row();
+        last_row.assert_one(is_padding);
+        last_row.assert_eq(local[SCORE], public_values[PUBLIC_SCORE].clone());
+        last_row.assert_eq(local[REFERENCE_INDEX], public_values[PUBLIC_REFERENCE_LENGTH].clone());
+        last_row.assert_eq(local[TARGET_INDEX], public_values[PUBLIC_TARGET_LENGTH].clone());
+        for repetition in 0..MEMCHECK_REPETITIONS {
+            for side in 0..2 {
+                for k in 0..EXTENSION_DEGREE {
+                    last_row.assert_eq(local[product_column(repetition, side) + k], public_values[public_memcheck(repetition, 2 + side) + k].clone());
+                }
+            }
+        }
+    }
+}
+
+/// First trace column of the product of `side`, 0 for the reference and 1 for the target.
+fn product_column(repetition: usize, side: usize) -> usize {
+    PRODUCTS + EXTENSION_DEGREE * (2 * repetition + side)
+}
+
+/// First public value of `item` of a repetition: alpha, beta, then the reference and target products.
+fn public_memcheck(repetition: usize, item: usize) -> usize {
+    PUBLIC_MEMCHECK + EXTENSION_DEGREE * (4 * repetition + item)
+}
+
+/// Fills the first phase of the trace, walking `cigar_string_bases`, padded to a power of two with at
+/// least one padding row.
+fn generate_main_trace(statement: &AlignmentStatement, cigar_string_bases: &[usize]) -> RowMajorMatrix<Val> {
+    let height = (cigar_string_bases.len() + 1).next_power_of_two().max(8);
+    let substitution_matrix = &statement.scoring_scheme.substitution_matrix;
+    let mut values = Vec::with_capacity(height * PRODUCTS);
+    let mut row = vec![Val::zero(); PRODUCTS];
+    let (mut reference_index, mut target_index) = (0, 0);
+    for i in 0..height {
+        let op = cigar_string_bases.get(i).copied().unwrap_or(CIGAR_PADDING);
+        let reference_base = statement.reference_sequence_bases.get(reference_index).copied().unwrap_or(0);
+        let target_base = statement.target_sequence_bases.get(target_index).copied().unwrap_or(0);
+        row[OP..REFERENCE_INDEX].fill(Val::zero());
+        row[OP + op] = Val::one();
+        row[REFERENCE_BASE + reference_base] = Val::one();
+        row[TARGET_BASE + target_base] = Val::one();
+        values.extend_from_slice(&row);
+
+        let consumes_reference = matches!(op, CIGAR_MATCH | CIGAR_MISMATCH | CIGAR_DELETION);
+        let consumes_target = matches!(op, CIGAR_MATCH | CIGAR_MISMATCH | CIGAR_INSERTION | CIGAR_SOFT_CLIP);
+        let is_aligned = matches!(op, CIGAR_MATCH | CIGAR_MISMATCH | CIGAR_INSERTION | CIGAR_DELETION);
+        let score_delta = match op {
+            CIGAR_MATCH => statement.scoring_scheme.match_score,
+            CIGAR_MISMATCH if reference_base == BASE_N || target_base == BASE_N => statement.scoring_scheme.ambiguous_score,
+            CIGAR_MISMATCH => substitution_matrix[reference_base][target_base],
+            CIGAR_INSERTION | CIGAR_DELETION => {
+                let previous = if op == CIGAR_INSERTION { PREVIOUS_IS_INSERTION } else { PREVIOUS_IS_DELETION };
+                let opens = if row[previous].is_zero() { statement.scoring_scheme.gap_open } else { 0 };
+                opens + statement.scoring_scheme.gap_extend
+            }
+            _ => 0,
+        };
+        row[SCORE] += i64_to_val(score_delta);
+        reference_index += consumes_reference as usize;
+        target_index += consumes_target as usize;
+        row[REFERENCE_INDEX] = Val::from_canonical_usize(reference_index);
+        row[TARGET_INDEX] = Val::from_canonical_usize(target_index);
+        row[PREVIOUS_IS_INSERTION] = Val::from_bool(op == CIGAR_INSERTION);
+        row[PREVIOUS_IS_DELETION] = Val::from_bool(op == CIGAR_DELETION);
+        let aligned_so_far = !row[ALIGNED_SO_FAR].is_zero();
+        if op == CIGAR_SOFT_CLIP && aligned_so_far {
+            row[CLIPPED_AFTER_ALIGNED] = Val::one();
+        }
+        row[ALIGNED_SO_FAR] = Val::from_bool(aligned_so_far || is_aligned);
+    }
+    RowMajorMatrix::new(values, PRODUCTS)
+}
+
+/// Fills the second phase of the trace, the running products of the reads of `main` under
+/// `challenges`. Every row holds the products of the reads of the rows before it.
+fn generate_product_trace(main: &RowMajorMatrix<Val>, challenges: &[[Challenge; 2]]) -> RowMajorMatrix<Val> {
+    let one_hot = |row: &[Val], start: usize, len: usize| row[start..start + len].iter().position(|bit| bit.is_one()).unwrap_or(0);
+    let mut values = Vec::with_capacity(main.height() * NUM_PRODUCT_COLUMNS);
+    let mut products = [Challenge::one(); 2 * MEMCHECK_REPETITIONS];
+    for i in 0..main.height() {
+        for product in products.iter() {
+            values.extend_from_slice(product.as_base_slice());
+        }
+        let row = main.row_slice(i);
+        let op = one_hot(&row, OP, 6);
+        let reads = [
+            (matches!(op, CIGAR_MATCH | CIGAR_MISMATCH | CIGAR_DELETION), REFERENCE_BASE, REFERENCE_INDEX),
+            (matches!(op, CIGAR_MATCH | CIGAR_MISMATCH | CIGAR_INSERTION | CIGAR_SOFT_CLIP), TARGET_BASE, TARGET_INDEX),
+        ];
+        for (repetition, &[alpha, beta]) in challenges.iter().enumerate() {
+            for (side, &(consumes, basis, index)) in reads.iter().enumerate() {
+                if consumes {
+                    let base = Val::from_canonical_usize(one_hot(&row, basis, NUM_BASES));
+                    products[2 * repetition + side] *= memcheck_term(alpha, beta, row[index], base);
+                }
+            }
+        }
+    }
+    RowMajorMatrix::new(values, NUM_PRODUCT_COLUMNS)
+}
+
+/// The STARK configuration shared by prover and verifier, hashing with the Poseidon2 permutation of
+/// `genome_commitment`.
+pub fn alignment_stark_config() -> AlignmentStarkConfig {
+    let fri_config = FriConfig {
+        log_blowup: LOG_BLOWUP,
+        num_queries: NUM_QUERIES,
+        proof_of_work_bits: PROOF_OF_WORK_BITS,
+        mmcs: ChallengeMmcs::new(genome_mmcs()),
+    };
+    AlignmentStarkConfig::new(FriPcs::new(Radix2DitParallel, genome_mmcs(), fri_config))
+}
+
+/// A STARK proof of an alignment: the commitments to both phases of the trace and to the quotient, the
+/// values they open to at the out-of-domain point and the next one, and the FRI opening proof.
+#[derive(Serialize, Deserialize)]
+pub struct AlignmentStarkProof {
+    main_commitment: Commitment,
+    products_commitment: Commitment,
+    quotient_commitment: Commitment,
+    main_local: Vec<Challenge>,
+    main_next: Vec<Challenge>,
+    products_local: Vec<Challenge>,
+    products_next: Vec<Challenge>,
+    quotient_chunks: Vec<Vec<Challenge>>,
+    opening_proof: <FriPcs as Pcs<Challenge, Challenger>>::Proof,
+    degree_bits: usize,
+}
+
+/// Evaluates the constraints of `air` over the quotient domain and divides them by the vanishing
+/// polynomial of the trace domain, as the uni-stark prover does for a single trace. `main` and
+/// `products` are the two phases of the trace on the quotient domain.
+fn quotient_values(
+    air: &AlignmentAir,
+    public_values: &Vec<Val>,
+    trace_domain: Domain,
+    quotient_domain: Domain,
+    main: &impl Matrix<Val>,
+    products: &impl Matrix<Val>,
+    alpha: Challenge,
+) -> Vec<Challenge> {
+    let quotient_size = quotient_domain.size();
+    let next_step = quotient_size / trace_domain.size();
+    let mut selectors = trace_domain.selectors_on_coset(quotient_domain);
+    // Pad the selectors to a whole packing for quotient domains smaller than one.
+    for column in [&mut selectors.is_first_row, &mut selectors.is_last_row, &mut selectors.is_transition, &mut selectors.inv_zeroifier] {
+        column.resize(column.len().max(PackedVal::WIDTH), Val::zero());
+    }
+
+    let mut values = Vec::with_capacity(quotient_size);
+    for i_start in (0..quotient_size).step_by(PackedVal::WIDTH) {
+        let packed = |column: &[Val]| *PackedVal::from_slice(&column[i_start..i_start + PackedVal::WIDTH]);
+        let rows = main
+            .vertically_packed_row(i_start)
+            .chain(products.vertically_packed_row(i_start))
+            .chain(main.vertically_packed_row(i_start + next_step))
+            .chain(products.vertically_packed_row(i_start + next_step))
+            .collect();
+        let mut folder = ProverConstraintFolder::<AlignmentStarkConfig> {
+            main: RowMajorMatrix::new(rows, NUM_COLUMNS),
+            public_values,
+            is_first_row: packed(&selectors.is_first_row),
+            is_last_row: packed(&selectors.is_last_row),
+            is_transition: packed(&selectors.is_transition),
+            alpha,
+            accumulator: PackedChallenge::zero(),
+        };
+        air.eval(&mut folder);
+        let quotient = folder.accumulator * packed(&selectors.inv_zeroifier);
+        for lane in 0..PackedVal::WIDTH.min(quotient_size) {
+            values.push(Challenge::from_base_fn(|k| AbstractExtensionField::<PackedVal>::as_base_slice(&quotient)[k].as_slice()[lane]));
+        }
+    }
+    values
+}
+
+/// Proves the alignment of `circuit` with the STARK, returning the statement it proves. The salts of
+/// the circuit are not used as the statement holds the sequences themselves. Quality-weighted scores
+/// are not supported.
+pub fn prove_alignment<F: PrimeField + Absorb>(circuit: &AlignmentCircuit<F>) -> (AlignmentStatement, AlignmentStarkProof) {
+    assert!(circuit.target_qualities.is_none(), "the STARK does not weight scores by quality");
+    let statement = AlignmentStatement {
+        reference_sequence_bases: circuit.reference_sequence_bases.clone(),
+        target_sequence_bases: circuit.target_sequence_bases.clone(),
+        scoring_scheme: circuit.scoring_scheme,
+        alignment_score: circuit.alignment_score,
+    };
+    let config = alignment_stark_config();
+    let pcs = config.pcs();
+    let air = AlignmentAir { scoring_scheme: statement.scoring_scheme };
+    let main = generate_main_trace(&statement, &circuit.cigar_string_bases);
+    let degree_bits = main.height().trailing_zeros() as usize;
+    let trace_domain = Pcs::<Challenge, Challenger>::natural_domain_for_degree(pcs, main.height());
+
+    // The walk, and with it every read, is committed before the memcheck challenges are drawn.
+    let mut challenger = statement.challenger(degree_bits);
+    let (main_commitment, main_data) = Pcs::<Challenge, Challenger>::commit(pcs, vec![(trace_domain, main.clone())]);
+    challenger.observe(main_commitment);
+    let challenges = sample_challenges(&mut challenger);
+    let (products_commitment, products_data) = Pcs::<Challenge, Challenger>::commit(pcs, vec![(trace_domain, generate_product_trace(&main, &challenges))]);
+    challenger.observe(products_commitment);
+
+    let public_values = statement.public_values(&challenges);
+    let quotient_degree = 1 << get_log_quotient_degree::<Val, AlignmentAir>(&air, 0, public_values.len());
+    let quotient_domain = trace_domain.create_disjoint_domain(trace_domain.size() * quotient_degree);
+    let alpha: Challenge = challenger.sample_ext_element();
+    let quotient = quotient_values(
+        &air,
+        &public_values,
+        trace_domain,
+        quotient_domain,
+        &Pcs::<Challenge, Challenger>::get_evaluations_on_domain(pcs, &main_data, 0, quotient_domain),
+        &Pcs::<Challenge, Challenger>::get_evaluations_on_domain(pcs, &products_data, 0, quotient_domain),
+        alpha,
+    );
+    let quotient_chunks = quotient_domain.split_evals(quotient_degree, RowMajorMatrix::new_col(quotient).flatten_to_base());
+    let (quotient_commitment, quotient_data) = Pcs::<Challenge, Challenger>::commit(pcs, quotient_domain.split_domains(quotient_degree).into_iter().zip(quotient_chunks).collect());
+    challenger.observe(quotient_commitment);
+
+    let zeta: Challenge = challenger.sample_ext_element();
+    let zeta_next = trace_domain.next_point(zeta).unwrap();
+    let (opened_values, opening_proof) = Pcs::<Challenge, Challenger>::open(pcs, 
+        vec![
+            (&main_data, vec![vec![zeta, zeta_next]]),
+            (&products_data, vec![vec![zeta, zeta_next]]),
+            (&quotient_data, vec![vec![zeta]; quotient_degree]),
+        ],
+        &mut challenger,
+    );
+    let proof = AlignmentStarkProof {
+        main_commitment,
+        products_commitment,
+        quotient_commitment,
+        main_local: opened_values[0][0][0].clone(),
+        main_next: opened_values[0][0][1].clone(),
+        products_local: opened_values[1][0][0].clone(),
+        products_next: opened_values[1][0][1].clone(),
+        quotient_chunks: opened_values[2].iter().map(|chunk| chunk[0].clone()).collect(),
+        opening_proof,
+        degree_bits,
+    };
+    (statement, proof)
+}
+
+/// Verifies `proof` against `statement`. The FRI verifier of Plonky3 0.2 panics on some invalid
+/// proofs rather than returning an error, so panics are caught and reported as invalid proofs.
+pub fn verify_alignment(statement: &AlignmentStatement, proof: &AlignmentStarkProof) -> Result<(), StarkError> {
+    match panic::catch_unwind(AssertUnwindSafe(|| check_proof(statement, proof))) {
+        Ok(result) => result,
+        Err(_) => Err(StarkError::InvalidProof("FRI verification failed".to_string())),
+    }
+}
+
+fn check_proof(statement: &AlignmentStatement, proof: &AlignmentStarkProof) -> Result<(), StarkError> {
+    let config = alignment_stark_config();
+    let pcs = config.pcs();
+    let air = AlignmentAir { scoring_scheme: statement.scoring_scheme };
+    let trace_domain = Pcs::<Challenge, Challenger>::natural_domain_for_degree(pcs, 1 << proof.degree_bits);
+
+    // Replay the transcript of the prover.
+    let mut challenger = statement.challenger(proof.degree_bits);
+    challenger.observe(proof.main_commitment);
+    let challenges = sample_challenges(&mut challenger);
+    challenger.observe(proof.products_commitment);
+    let public_values = statement.public_values(&challenges);
+    let quotient_degree = 1 << get_log_quotient_degree::<Val, AlignmentAir>(&air, 0, public_values.len());
+    let quotient_domain = trace_domain.create_disjoint_domain(trace_domain.size() * quotient_degree);
+    let quotient_domains = quotient_domain.split_domains(quotient_degree);
+    let alpha: Challenge = challenger.sample_ext_element();
+    challenger.observe(proof.quotient_commitment);
+    let zeta: Challenge = challenger.sample_ext_element();
+    let zeta_next = trace_domain.next_point(zeta).unwrap();
+
+    let valid_shape = proof.main_local.len() == PRODUCTS
+        && proof.main_next.len() == PRODUCTS
+        && proof.products_local.len() == NUM_PRODUCT_COLUMNS
+        && proof.products_next.len() == NUM_PRODUCT_COLUMNS
+        && proof.quotient_chunks.len() == quotient_degree
+        && proof.quotient_chunks.iter().all(|chunk| chunk.len() == EXTENSION_DEGREE);
+    if !valid_shape {
+        return Err(StarkError::InvalidProof("proof has the wrong shape".to_string()));
+    }
+
+    Pcs::<Challenge, Challenger>::verify(pcs, 
+        vec![
+            (proof.main_commitment, vec![(trace_domain, vec![(zeta, proof.main_local.clone()), (zeta_next, proof.main_next.clone())])]),
+            (proof.products_commitment, vec![(trace_domain, vec![(zeta, proof.products_local.clone()), (zeta_next, proof.products_next.clone())])]),
+            (
+                proof.quotient_commitment,
+                quotient_domains.iter().zip(proof.quotient_chunks.iter()).map(|(domain, chunk)| (*domain, vec![(zeta, chunk.clone())])).collect(),
+            ),
+        ],
+        &proof.opening_proof,
+        &mut challenger,
+    )
+    .map_err(|err| StarkError::InvalidProof(format!("{:?}", err)))?;
+
+    // Recombine the quotient from its chunks at zeta.
+    let mut quotient = Challenge::zero();
+    for (i, (domain, chunk)) in quotient_domains.iter().zip(proof.quotient_chunks.iter()).enumerate() {
+        let zp: Challenge = quotient_domains
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| j != i)
+            .map(|(_, other)| other.zp_at_point(zeta) * other.zp_at_point(domain.first_point()).inverse())
+            .product();
+        quotient += chunk.iter().enumerate().map(|(k, &limb)| zp * <Challenge as AbstractExtensionField<Val>>::monomial(k) * limb).sum::<Challenge>();
+    }
+
+    let selectors = trace_domain.selectors_at_point(zeta);
+    let local: Vec<Challenge> = proof.main_local.iter().chain(proof.products_local.iter()).copied().collect();
+    let next: Vec<Challenge> = proof.main_next.iter().chain(proof.products_next.iter()).copied().collect();
+    let mut folder = VerifierConstraintFolder::<AlignmentStarkConfig> {
+        main: VerticalPair::new(RowMajorMatrixView::new_row(&local), RowMajorMatrixView::new_row(&next)),
+        public_values: &public_values,
+        is_first_row: selectors.is_first_row,
+        is_last_row: selectors.is_last_row,
+        is_transition: selectors.is_transition,
+        alpha,
+        accumulator: Challenge::zero(),
+    };
+    air.eval(&mut folder);
+    if folder.accumulator * selectors.inv_zeroifier != quotient {
+        return Err(StarkError::InvalidProof("constraints do not match the quotient".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aligner::{Aligner, Objective};
+    use crate::alignment::MaxLengths;
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem};
+    use ark_std::rand::Rng;
+
+    #[test]
+    fn stark_agrees_with_groth16_circuit() {
+        let rng = &mut ark_std::test_rng();
+        let reference: Vec<usize> = (0..150).map(|_| rng.gen_range(0..4)).collect();
+        let mut target = reference[10..140].to_vec();
+        target[20] = (target[20] + 1) % 4;
+        target.drain(60..63);
+        target.insert(100, BASE_N);
+        let max_lengths = MaxLengths { reference: 200, target: 200, cigar: 300 };
+        let aligner = Aligner::new(ScoringScheme::bwa_mem(), Objective::Maximize).local();
+        let (_, circuit) = aligner.circuit::<Fr>(&reference, &target, &max_lengths).unwrap();
+
+        let cs = ConstraintSystem::new_ref();
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        let (statement, proof) = prove_alignment(&circuit);
+        verify_alignment(&statement, &proof).unwrap();
+
+        let mut wrong_score = statement.clone();
+        wrong_score.alignment_score += 1;
+        assert!(verify_alignment(&wrong_score, &proof).is_err());
+        let mut wrong_target = statement.clone();
+        wrong_target.target_sequence_bases[0] = (wrong_target.target_sequence_bases[0] + 1) % 4;
+        assert!(verify_alignment(&wrong_target, &proof).is_err());
+
+        // The proof survives serialization, but not a change to an opened product.
+        let mut proof: AlignmentStarkProof = bincode::deserialize(&bincode::serialize(&proof).unwrap()).unwrap();
+        verify_alignment(&statement, &proof).unwrap();
+        proof.products_local[0] += Challenge::one();
+        assert!(verify_alignment(&statement, &proof).is_err());
+    }
+}
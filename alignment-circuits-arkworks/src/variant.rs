@@ -0,0 +1,473 @@
+// Proves that the genotype claimed by VCF records holds for a committed diploid consensus, versus a
+// committed reference. Each haplotype is aligned end to end against the reference with a private CIGAR,
+// checked by the same memory-checked walk as AlignmentCircuit, and at every queried position the
+// reference base must be the record's REF and each haplotype must align the REF or ALT base that its
+// allele names. Only the records and the three commitments are public, and for unphased genotypes the
+// circuit may swap the haplotypes, so that the phase stays private too.
+//
+// The alignments are not required to be optimal: any global alignment of a haplotype is accepted, but
+// the queried base must be aligned to the reference position, not inserted or deleted around it.
+
+use std::fmt;
+
+use ark_crypto_primitives::sponge::constraints::CryptographicSpongeVar;
+use ark_crypto_primitives::sponge::poseidon::constraints::PoseidonSpongeVar;
+use ark_crypto_primitives::sponge::Absorb;
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+
+use crate::aligner::{Aligner, Objective};
+use crate::alignment::{
+    enforce_cigar_walk, pack_bases_to, pack_cigar_to, unpack_cigar, usize_to_felt, MaxLengths,
+    ScoringScheme, BASE_N, CIGAR_DELETION, CIGAR_INSERTION,
+};
+use crate::commitment::{commit_bases, unpack_committed_sequence};
+use crate::memory::ReadOnlyMemoryVar;
+use crate::poseidon::poseidon_parameters;
+use crate::sam::encode_base;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VcfError {
+    MalformedRecord(String),
+    UnsupportedVariant(String),
+    ChromosomeMismatch { expected: String, found: String },
+    PositionOutOfBounds { pos: usize, length: usize },
+    ReferenceMismatch { pos: usize, expected: usize, found: usize },
+    HaplotypeNotAligned { pos: usize, haplotype: usize },
+    GenotypeMismatch { pos: usize, claimed: [usize; 2], found: [usize; 2] },
+}
+
+impl fmt::Display for VcfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VcfError::MalformedRecord(reason) => write!(f, "malformed VCF record: {}", reason),
+            VcfError::UnsupportedVariant(reason) => write!(f, "variant is not supported by the genotype circuit: {}", reason),
+            VcfError::ChromosomeMismatch { expected, found } => {
+                write!(f, "record is on {} but the committed reference is {}", found, expected)
+            }
+            VcfError::PositionOutOfBounds { pos, length } => write!(f, "POS {} is outside the reference of {} bases", pos, length),
+            VcfError::ReferenceMismatch { pos, expected, found } => {
+                write!(f, "REF at POS {} is base {} but the reference has base {}", pos, expected, found)
+            }
+            VcfError::HaplotypeNotAligned { pos, haplotype } => {
+                write!(f, "haplotype {} carries neither REF nor ALT aligned to POS {}", haplotype, pos)
+            }
+            VcfError::GenotypeMismatch { pos, claimed, found } => {
+                write!(f, "GT at POS {} is {:?} but the haplotypes carry alleles {:?}", pos, claimed, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VcfError {}
+
+/// The fields of a single-sample VCF line that the genotype circuit needs. Only biallelic SNVs with a
+/// fully called diploid genotype are supported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VcfRecord {
+    pub chrom: String,
+    pub pos: usize, // 1-based
+    pub reference_base: usize,
+    pub alternate_base: usize,
+    pub genotype: [usize; 2], // allele indices, 0 for REF and 1 for ALT
+    pub phased: bool,
+}
+
+impl VcfRecord {
+    /// Parses one tab-separated VCF data line, reading GT from the first sample.
+    pub fn parse(line: &str) -> Result<Self, VcfError> {
+        let fields: Vec<&str> = line.trim_end_matches(['\r', '\n']).split('\t').collect();
+        if fields.len() < 10 {
+            return Err(VcfError::MalformedRecord(format!("expected a sample column, found {} fields", fields.len())));
+        }
+        let pos = fields[1].parse().map_err(|_| VcfError::MalformedRecord(format!("bad POS {}", fields[1])))?;
+        if pos == 0 {
+            return Err(VcfError::MalformedRecord("POS is 1-based".to_string()));
+        }
+        let reference_base = parse_snv_base(fields[3])?;
+        let alternate_base = parse_snv_base(fields[4])?;
+        if reference_base == alternate_base {
+            return Err(VcfError::MalformedRecord(format!("ALT {} is the same as REF", fields[4])));
+        }
+
+        let gt_index = fields[8]
+            .split(':')
+            .position(|key| key == "GT")
+            .ok_or_else(|| VcfError::MalformedRecord("FORMAT has no GT".to_string()))?;
+        let gt = fields[9]
+            .split(':')
+            .nth(gt_index)
+            .ok_or_else(|| VcfError::MalformedRecord(format!("sample {} has no GT", fields[9])))?;
+        let phased = gt.contains('|');
+        let alleles: Vec<&str> = gt.split(['/', '|']).collect();
+        if alleles.len() != 2 {
+            return Err(VcfError::UnsupportedVariant(format!("GT {} is not diploid", gt)));
+        }
+        let mut genotype = [0; 2];
+        for (allele, field) in genotype.iter_mut().zip(alleles) {
+            *allele = match field {
+                "0" => 0,
+                "1" => 1,
+                "." => return Err(VcfError::UnsupportedVariant(format!("GT {} is not fully called", gt))),
+                _ => return Err(VcfError::MalformedRecord(format!("bad GT {}", gt))),
+            };
+        }
+        Ok(Self {
+            chrom: fields[0].to_string(),
+            pos,
+            reference_base,
+            alternate_base,
+            genotype,
+            phased,
+        })
+    }
+}
+
+fn parse_snv_base(field: &str) -> Result<usize, VcfError> {
+    if field.len() != 1 {
+        return Err(VcfError::UnsupportedVariant(format!("{} is not a single base", field)));
+    }
+    match encode_base(field.as_bytes()[0]) {
+        Ok(base) if base != BASE_N => Ok(base),
+        _ => Err(VcfError::UnsupportedVariant(format!("{} is not one of A, C, G or T", field))),
+    }
+}
+
+/// A queried locus as the circuit sees it: its public fields, and the allele of each haplotype.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GenotypeLocus {
+    pub pos: usize,
+    pub reference_base: usize,
+    pub alternate_base: usize,
+    pub genotype: [usize; 2], // as published, sorted unless phased
+    pub phased: bool,
+    pub swapped: bool, // haplotype i carries genotype[1 - i], only allowed when unphased
+}
+
+#[derive(Clone)]
+pub struct GenotypeCircuit<F: PrimeField> {
+    pub reference_sequence_felts: Vec<F>,
+    pub reference_sequence_bases: Vec<usize>,
+    pub haplotype_felts: [Vec<F>; 2],
+    pub haplotype_bases: [Vec<usize>; 2],
+    pub cigar_string_felts: [Vec<F>; 2], // each haplotype against the whole reference
+    pub cigar_string_bases: [Vec<usize>; 2],
+    pub loci: Vec<GenotypeLocus>, // the number of loci is fixed at setup time, as are the maximum lengths
+    pub reference_salt: F,
+    pub haplotype_salts: [F; 2],
+}
+
+/// The base of `target` aligned to `reference_index` by `cigar_string_bases`, if any.
+fn aligned_base(cigar_string_bases: &[usize], target: &[usize], reference_index: usize) -> Option<usize> {
+    let (mut i, mut j) = (0, 0);
+    for &op in cigar_string_bases {
+        match op {
+            CIGAR_INSERTION => j += 1,
+            CIGAR_DELETION => {
+                if i == reference_index {
+                    return None;
+                }
+                i += 1;
+            }
+            _ => {
+                if i == reference_index {
+                    return Some(target[j]);
+                }
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    None
+}
+
+impl<F: PrimeField> GenotypeCircuit<F> {
+    /// Aligns both haplotypes against `reference` and checks every record against them. The haplotypes
+    /// fit in `max_lengths.target` and each CIGAR in `max_lengths.cigar`.
+    pub fn new(
+        chrom: &str,
+        reference_sequence_bases: Vec<usize>,
+        haplotype_bases: [Vec<usize>; 2],
+        records: &[VcfRecord],
+        max_lengths: &MaxLengths,
+    ) -> Result<Self, VcfError> {
+        assert!(reference_sequence_bases.len() <= max_lengths.reference, "reference is longer than the circuit allows");
+        let aligner = Aligner::new(ScoringScheme::edit_distance(), Objective::Minimize);
+        let cigar_string_bases = haplotype_bases.clone().map(|haplotype| {
+            assert!(haplotype.len() <= max_lengths.target, "haplotype is longer than the circuit allows");
+            let cigar = aligner.align(&reference_sequence_bases, &haplotype).expect("unbanded global alignments always exist").cigar_string_bases;
+            assert!(cigar.len() <= max_lengths.cigar, "alignment is longer than the circuit allows");
+            cigar
+        });
+
+        let mut loci = Vec::new();
+        for record in records {
+            if record.chrom != chrom {
+                return Err(VcfError::ChromosomeMismatch { expected: chrom.to_string(), found: record.chrom.clone() });
+            }
+            let found = *reference_sequence_bases
+                .get(record.pos - 1)
+                .ok_or(VcfError::PositionOutOfBounds { pos: record.pos, length: reference_sequence_bases.len() })?;
+            if found != record.reference_base {
+                return Err(VcfError::ReferenceMismatch { pos: record.pos, expected: record.reference_base, found });
+            }
+            let mut alleles = [0; 2];
+            for (haplotype, allele) in alleles.iter_mut().enumerate() {
+                *allele = match aligned_base(&cigar_string_bases[haplotype], &haplotype_bases[haplotype], record.pos - 1) {
+                    Some(base) if base == record.reference_base => 0,
+                    Some(base) if base == record.alternate_base => 1,
+                    _ => return Err(VcfError::HaplotypeNotAligned { pos: record.pos, haplotype }),
+                };
+            }
+            let (genotype, swapped) = if record.phased {
+                (record.genotype, false)
+            } else {
+                let mut sorted = record.genotype;
+                sorted.sort();
+                (sorted, alleles[0] > alleles[1])
+            };
+            let claimed = if swapped { [genotype[1], genotype[0]] } else { genotype };
+            if claimed != alleles {
+                return Err(VcfError::GenotypeMismatch { pos: record.pos, claimed: record.genotype, found: alleles });
+            }
+            loci.push(GenotypeLocus {
+                pos: record.pos,
+                reference_base: record.reference_base,
+                alternate_base: record.alternate_base,
+                genotype,
+                phased: record.phased,
+                swapped,
+            });
+        }
+
+        Ok(Self {
+            reference_sequence_felts: pack_bases_to(&reference_sequence_bases, max_lengths.reference),
+            reference_sequence_bases,
+            haplotype_felts: haplotype_bases.clone().map(|haplotype| pack_bases_to(&haplotype, max_lengths.target)),
+            haplotype_bases,
+            cigar_string_felts: cigar_string_bases.clone().map(|cigar| pack_cigar_to(&cigar, max_lengths.cigar)),
+            cigar_string_bases,
+            loci,
+            reference_salt: F::zero(),
+            haplotype_salts: [F::zero(); 2],
+        })
+    }
+
+    pub fn with_salts(mut self, reference_salt: F, haplotype_salts: [F; 2]) -> Self {
+        self.reference_salt = reference_salt;
+        self.haplotype_salts = haplotype_salts;
+        self
+    }
+}
+
+impl<F: PrimeField + Absorb> GenotypeCircuit<F> {
+    /// Public inputs in the order the circuit allocates them: the reference and haplotype commitments,
+    /// then POS, REF, ALT, both GT alleles and whether they are phased for every locus.
+    pub fn public_inputs(&self) -> Vec<F> {
+        let mut inputs = vec![
+            commit_bases(&self.reference_sequence_bases, self.reference_salt),
+            commit_bases(&self.haplotype_bases[0], self.haplotype_salts[0]),
+            commit_bases(&self.haplotype_bases[1], self.haplotype_salts[1]),
+        ];
+        for locus in &self.loci {
+            inputs.extend([
+                F::from(locus.pos as u64),
+                usize_to_felt(locus.reference_base),
+                usize_to_felt(locus.alternate_base),
+                F::from(locus.genotype[0] as u64),
+                F::from(locus.genotype[1] as u64),
+                F::from(locus.phased),
+            ]);
+        }
+        inputs
+    }
+}
+
+impl<F: PrimeField> ConstraintSynthesizer<F> for GenotypeCircuit<F> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+        let mut reference_sequence_vars = Vec::new();
+        for elem in self.reference_sequence_felts.iter() {
+            reference_sequence_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+        let mut haplotype_vars = Vec::new();
+        let mut cigar_string_vars = Vec::new();
+        for (haplotype_felts, cigar_felts) in self.haplotype_felts.iter().zip(self.cigar_string_felts.iter()) {
+            let mut haplotype = Vec::new();
+            for elem in haplotype_felts.iter() {
+                haplotype.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+            }
+            haplotype_vars.push(haplotype);
+            let mut cigar = Vec::new();
+            for elem in cigar_felts.iter() {
+                cigar.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+            }
+            cigar_string_vars.push(cigar);
+        }
+
+        let params = poseidon_parameters();
+
+        let reference = unpack_committed_sequence(cs.clone(), &reference_sequence_vars, self.reference_sequence_bases.len(), self.reference_salt)?;
+        let mut haplotypes = Vec::new();
+        for ((vars, bases), salt) in haplotype_vars.iter().zip(self.haplotype_bases.iter()).zip(self.haplotype_salts) {
+            haplotypes.push(unpack_committed_sequence(cs.clone(), vars, bases.len(), salt)?);
+        }
+
+        let mut cigar_sponge = PoseidonSpongeVar::new(cs.clone(), &params);
+        for cigar in cigar_string_vars.iter() {
+            cigar_sponge.absorb(cigar)?;
+        }
+        let cigar_hash = cigar_sponge.squeeze_field_elements(1)?;
+
+        let mut commitment_vars = Vec::new();
+        for commitment in [&reference.commitment, &haplotypes[0].commitment, &haplotypes[1].commitment] {
+            let commitment_var = FpVar::new_input(cs.clone(), || commitment.value())?;
+            commitment_var.enforce_equal(commitment)?;
+            commitment_vars.push(commitment_var);
+        }
+
+        // Both haplotypes read the whole reference once. Scores are not used, so the matrix is constant.
+        let scheme = ScoringScheme::edit_distance();
+        let substitution_matrix_vars: Vec<_> = scheme.substitution_matrix_felts::<F>().into_iter().map(FpVar::constant).collect();
+        let mut reference_memory = ReadOnlyMemoryVar::from_bases(cs.clone(), &reference.bases, 2);
+        let mut haplotype_memories = Vec::new();
+        let mut walks = Vec::new();
+        for (h, haplotype) in haplotypes.iter().enumerate() {
+            let mut cigar_chars = Vec::new();
+            for block in cigar_string_vars[h].iter() {
+                cigar_chars.extend(unpack_cigar(block)?);
+            }
+            let mut haplotype_memory = ReadOnlyMemoryVar::from_bases(cs.clone(), &haplotype.bases, 1);
+            let walk = enforce_cigar_walk(cs.clone(), &cigar_chars, &mut reference_memory, &mut haplotype_memory, &scheme, &substitution_matrix_vars)?;
+            walk.target_length.enforce_equal(&haplotype.length)?;
+            walk.reference_length.enforce_equal(&reference.length)?;
+            haplotype_memories.push(haplotype_memory);
+            walks.push(walk);
+        }
+
+        let mut transcript = commitment_vars;
+        transcript.push(cigar_hash[0].clone());
+        reference_memory.finalize(&transcript)?;
+        for haplotype_memory in haplotype_memories {
+            haplotype_memory.finalize(&transcript)?;
+        }
+
+        for locus in &self.loci {
+            let pos_var = FpVar::new_input(cs.clone(), || Ok(F::from(locus.pos as u64)))?;
+            let reference_base_var = FpVar::new_input(cs.clone(), || Ok(usize_to_felt::<F>(locus.reference_base)))?;
+            let alternate_base_var = FpVar::new_input(cs.clone(), || Ok(usize_to_felt::<F>(locus.alternate_base)))?;
+            let genotype_vars = [
+                Boolean::new_input(cs.clone(), || Ok(locus.genotype[0] == 1))?,
+                Boolean::new_input(cs.clone(), || Ok(locus.genotype[1] == 1))?,
+            ];
+            let phased_var = Boolean::new_input(cs.clone(), || Ok(locus.phased))?;
+            let swapped_var = Boolean::new_witness(cs.clone(), || Ok(locus.swapped))?;
+            phased_var.and(&swapped_var)?.enforce_equal(&Boolean::FALSE)?;
+            reference_base_var.is_eq(&alternate_base_var)?.enforce_equal(&Boolean::FALSE)?;
+
+            let reference_index_var = &pos_var - F::one();
+            for (h, walk) in walks.iter().enumerate() {
+                let allele = swapped_var.select(&genotype_vars[1 - h], &genotype_vars[h])?;
+                let expected_base = allele.select(&alternate_base_var, &reference_base_var)?;
+                // The walk reads every reference position once, so exactly one operation is at the locus.
+                let mut at_locus_count = FpVar::zero();
+                for step in walk.steps.iter() {
+                    let at_locus = step.consumes_reference.and(&step.reference_index.is_eq(&reference_index_var)?)?;
+                    at_locus.and(&step.is_aligned.not())?.enforce_equal(&Boolean::FALSE)?;
+                    step.reference_base.conditional_enforce_equal(&reference_base_var, &at_locus)?;
+                    step.target_base.conditional_enforce_equal(&expected_base, &at_locus)?;
+                    at_locus_count += FpVar::from(at_locus);
+                }
+                at_locus_count.enforce_equal(&FpVar::one())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::{BASE_A, BASE_C, BASE_G, BASE_T};
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::rand::Rng;
+    use ark_std::UniformRand;
+
+    #[test]
+    fn parses_snv_records() {
+        let record = VcfRecord::parse("chr1\t12\trs1\tA\tG\t50\tPASS\t.\tDP:GT\t30:1|0\n").unwrap();
+        assert_eq!(
+            record,
+            VcfRecord { chrom: "chr1".to_string(), pos: 12, reference_base: BASE_A, alternate_base: BASE_G, genotype: [1, 0], phased: true }
+        );
+        assert!(matches!(VcfRecord::parse("chr1\t12\t.\tA\tGT\t.\t.\t.\tGT\t0/1"), Err(VcfError::UnsupportedVariant(_))));
+        assert!(matches!(VcfRecord::parse("chr1\t12\t.\tA\tG\t.\t.\t.\tGT\t./1"), Err(VcfError::UnsupportedVariant(_))));
+        assert!(matches!(VcfRecord::parse("chr1\t12\t.\tA\tG\t.\t.\t.\tDP\t3"), Err(VcfError::MalformedRecord(_))));
+    }
+
+    fn is_satisfied(circuit: GenotypeCircuit<Fr>) -> bool {
+        let cs = ConstraintSystem::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        cs.is_satisfied().unwrap()
+    }
+
+    #[test]
+    fn proves_genotypes_of_committed_haplotypes() {
+        let rng = &mut ark_std::test_rng();
+        let reference: Vec<usize> = (0..120).map(|_| rng.gen_range(BASE_A..=BASE_T)).collect();
+        // Haplotype 0 has an SNV at POS 31 and an insertion elsewhere, haplotype 1 a deletion and an SNV
+        // at POS 81.
+        let alternate = |base: usize| if base == BASE_C { BASE_G } else { BASE_C };
+        let mut haplotype_0 = reference.clone();
+        haplotype_0[30] = alternate(reference[30]);
+        haplotype_0.insert(60, BASE_A);
+        let mut haplotype_1 = reference.clone();
+        haplotype_1[80] = alternate(reference[80]);
+        haplotype_1.remove(10);
+        let base_name = |base: usize| ["A", "C", "G", "T"][base];
+        let record = |pos: usize, gt: &str| {
+            let line = format!("chr2\t{}\t.\t{}\t{}\t.\tPASS\t.\tGT\t{}", pos, base_name(reference[pos - 1]), base_name(alternate(reference[pos - 1])), gt);
+            VcfRecord::parse(&line).unwrap()
+        };
+        let records = vec![record(31, "0/1"), record(81, "0|1"), record(101, "0/0")];
+
+        let max_lengths = MaxLengths { reference: 168, target: 168, cigar: 168 };
+        let salts = [Fr::rand(rng), Fr::rand(rng)];
+        let circuit = GenotypeCircuit::<Fr>::new("chr2", reference.clone(), [haplotype_0.clone(), haplotype_1.clone()], &records, &max_lengths)
+            .unwrap()
+            .with_salts(Fr::rand(rng), salts);
+        assert_eq!(circuit.loci[0].genotype, [0, 1]);
+        assert!(circuit.loci[0].swapped);
+        let cs = ConstraintSystem::new_ref();
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.borrow().unwrap().instance_assignment[1..], circuit.public_inputs()[..]);
+        assert_eq!(circuit.public_inputs()[1], commit_bases(&haplotype_0, salts[0]));
+
+        // The natively checked errors.
+        let build = |records: &[VcfRecord]| GenotypeCircuit::<Fr>::new("chr2", reference.clone(), [haplotype_0.clone(), haplotype_1.clone()], records, &max_lengths);
+        assert!(matches!(build(&[record(31, "0|1")]), Err(VcfError::GenotypeMismatch { pos: 31, .. })));
+        assert!(matches!(build(&[record(81, "1/1")]), Err(VcfError::GenotypeMismatch { pos: 81, .. })));
+        let mut wrong_reference = record(31, "1|0");
+        wrong_reference.reference_base = (BASE_A..=BASE_T).find(|&base| base != reference[30] && base != alternate(reference[30])).unwrap();
+        assert!(matches!(build(&[wrong_reference]), Err(VcfError::ReferenceMismatch { pos: 31, .. })));
+        assert!(matches!(build(&[record(11, "0/0")]), Err(VcfError::HaplotypeNotAligned { pos: 11, haplotype: 1 })));
+
+        // A claimed genotype that the haplotypes do not carry is rejected by the circuit.
+        let mut wrong_genotype = circuit.clone();
+        wrong_genotype.loci[2].genotype = [0, 1];
+        assert!(!is_satisfied(wrong_genotype));
+        // So is swapping a phased genotype.
+        let mut swapped = circuit.clone();
+        swapped.loci[1].genotype = [1, 0];
+        swapped.loci[1].swapped = true;
+        assert!(!is_satisfied(swapped));
+        // And a different REF.
+        let mut wrong_reference = circuit;
+        wrong_reference.loci[2].reference_base = (BASE_A..=BASE_T).find(|&base| base != reference[100] && base != alternate(reference[100])).unwrap();
+        assert!(!is_satisfied(wrong_reference));
+    }
+}
@@ -0,0 +1,348 @@
+// Proves that a read aligns to a window of a chromosome or genome committed with a ReferenceTree. Only
+// the blocks covering the aligned window are witnessed, each opened against the public root, and the
+// position of the window is public unless the circuit is built with `with_hidden_start`.
+//
+// AlignmentCircuit can check the same paths with `with_reference_tree`, but it still witnesses the
+// reference it aligns to as a separate committed sequence and matches it against the opened blocks.
+// This circuit reads the bases straight from the blocks, so it has no reference commitment and costs
+// one memory check less.
+
+use ark_crypto_primitives::sponge::constraints::CryptographicSpongeVar;
+use ark_crypto_primitives::sponge::poseidon::constraints::PoseidonSpongeVar;
+use ark_crypto_primitives::sponge::Absorb;
+use ark_ff::PrimeField;
+use ark_r1cs_std::fields::fp::FpVar;
+use ark_r1cs_std::prelude::*;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+
+use crate::alignment::{
+    enforce_cigar_walk, i64_to_felt, unpack_bases, unpack_cigar, AlignmentCircuit, ScoringScheme, BASES_PER_BLOCK,
+    BITS_PER_BASE,
+};
+use crate::commitment::{commit_bases, unpack_committed_sequence};
+use crate::memory::{MemoryCell, ReadOnlyMemoryVar};
+use crate::poseidon::poseidon_parameters;
+use crate::reference_tree::{enforce_window_membership, ReferenceTree, ReferenceWindow};
+
+#[derive(Clone)]
+pub struct WindowAlignmentCircuit<F: PrimeField + Absorb> {
+    pub reference_root: F, // public root of the ReferenceTree
+    pub tree_height: usize, // fixed at setup time, as are the maximum window and read lengths
+    pub window: ReferenceWindow<F>, // start position and the witnessed blocks covering the longest window
+    pub hide_start: bool, // whether the start position is a witness rather than a public input
+    pub reference_sequence_bases: Vec<usize>, // the bases of the window
+    pub target_sequence_felts: Vec<F>,
+    pub target_sequence_bases: Vec<usize>,
+    pub cigar_string_felts: Vec<F>,
+    pub cigar_string_bases: Vec<usize>,
+    pub scoring_scheme: ScoringScheme,
+    pub alignment_score: i64,
+    pub target_salt: F, // salt of the public target commitment
+}
+
+impl<F: PrimeField + Absorb> WindowAlignmentCircuit<F> {
+    /// Moves `alignment`, an alignment against the reference bases `window_start..`, onto `tree`. The
+    /// circuit keeps the maximum lengths `alignment` was packed for.
+    pub fn new(tree: &ReferenceTree<F>, window_start: usize, alignment: AlignmentCircuit<F>) -> Self {
+        let window = tree.open_window(
+            window_start,
+            alignment.reference_sequence_bases.len(),
+            alignment.max_lengths().reference,
+        );
+        assert_eq!(
+            &tree.bases()[window_start..window_start + alignment.reference_sequence_bases.len()],
+            alignment.reference_sequence_bases.as_slice(),
+            "alignment is not against this window of the reference"
+        );
+        Self {
+            reference_root: tree.root(),
+            tree_height: tree.height(),
+            window,
+            hide_start: false,
+            reference_sequence_bases: alignment.reference_sequence_bases,
+            target_sequence_felts: alignment.target_sequence_felts,
+            target_sequence_bases: alignment.target_sequence_bases,
+            cigar_string_felts: alignment.cigar_string_felts,
+            cigar_string_bases: alignment.cigar_string_bases,
+            scoring_scheme: alignment.scoring_scheme,
+            alignment_score: alignment.alignment_score,
+            target_salt: alignment.target_salt,
+        }
+    }
+
+    /// Keeps the start position private, so that the proof only says the read aligns somewhere under
+    /// the root. The circuit then has one public input fewer and needs its own keys.
+    pub fn with_hidden_start(mut self) -> Self {
+        self.hide_start = true;
+        self
+    }
+
+    /// Public inputs in the order the circuit allocates them.
+    pub fn public_inputs(&self) -> Vec<F> {
+        let mut inputs = self.scoring_scheme.substitution_matrix_felts::<F>();
+        inputs.push(self.reference_root);
+        if !self.hide_start {
+            inputs.push(F::from(self.window.start as u64));
+        }
+        inputs.push(commit_bases(&self.target_sequence_bases, self.target_salt));
+        inputs.push(F::from(self.reference_sequence_bases.len() as u64));
+        inputs.push(F::from(self.target_sequence_bases.len() as u64));
+        inputs.push(i64_to_felt::<F>(self.alignment_score));
+        inputs
+    }
+}
+
+impl<F: PrimeField + Absorb> ConstraintSynthesizer<F> for WindowAlignmentCircuit<F> {
+    fn generate_constraints(self, cs: ConstraintSystemRef<F>) -> Result<(), SynthesisError> {
+        let mut substitution_matrix_vars = Vec::new();
+        for elem in self.scoring_scheme.substitution_matrix_felts::<F>() {
+            substitution_matrix_vars.push(FpVar::new_input(cs.clone(), || Ok(elem))?);
+        }
+        let root_var = FpVar::new_input(cs.clone(), || Ok(self.reference_root))?;
+        let start = F::from(self.window.start as u64);
+        let start_var = if self.hide_start {
+            FpVar::new_witness(cs.clone(), || Ok(start))?
+        } else {
+            FpVar::new_input(cs.clone(), || Ok(start))?
+        };
+        let target_commitment_var = FpVar::new_input(cs.clone(), || Ok(commit_bases(&self.target_sequence_bases, self.target_salt)))?;
+        let reference_length_var = FpVar::new_input(cs.clone(), || Ok(F::from(self.reference_sequence_bases.len() as u64)))?;
+        let target_length_var = FpVar::new_input(cs.clone(), || Ok(F::from(self.target_sequence_bases.len() as u64)))?;
+
+        let mut block_vars = Vec::new();
+        for elem in self.window.blocks.iter() {
+            block_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+
+        let mut target_sequence_vars = Vec::new();
+        for elem in self.target_sequence_felts.iter() {
+            target_sequence_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+
+        let mut cigar_string_vars = Vec::new();
+        for elem in self.cigar_string_felts.iter() {
+            cigar_string_vars.push(FpVar::new_witness(cs.clone(), || Ok(elem))?);
+        }
+
+        let params = poseidon_parameters();
+
+        // The window start splits into the first opened block and an offset inside it, the offset
+        // being one-hot encoded so that window membership of every slot is linear in it.
+        let first_block = self.window.start / BASES_PER_BLOCK;
+        let mut offset_one_hot = Vec::new();
+        for t in 0..BASES_PER_BLOCK {
+            offset_one_hot.push(Boolean::new_witness(cs.clone(), || Ok(self.window.start % BASES_PER_BLOCK == t))?);
+        }
+        let mut one_hot_sum = FpVar::zero();
+        let mut offset_var = FpVar::zero();
+        for (t, bit) in offset_one_hot.iter().enumerate() {
+            one_hot_sum += FpVar::from(bit.clone());
+            offset_var += FpVar::from(bit.clone()) * F::from(t as u64);
+        }
+        one_hot_sum.enforce_equal(&FpVar::one())?;
+        let first_block_var = FpVar::new_witness(cs.clone(), || Ok(F::from(first_block as u64)))?;
+        (&first_block_var * F::from(BASES_PER_BLOCK as u64) + &offset_var).enforce_equal(&start_var)?;
+
+        // The slot just past the window is one-hot encoded in the same way.
+        let window_end = self.window.start % BASES_PER_BLOCK + self.reference_sequence_bases.len();
+        let mut end_one_hot = Vec::new();
+        for t in 0..=self.window.blocks.len() * BASES_PER_BLOCK {
+            end_one_hot.push(Boolean::new_witness(cs.clone(), || Ok(window_end == t))?);
+        }
+        let mut end_one_hot_sum = FpVar::zero();
+        let mut end_var = FpVar::zero();
+        for (t, bit) in end_one_hot.iter().enumerate() {
+            end_one_hot_sum += FpVar::from(bit.clone());
+            end_var += FpVar::from(bit.clone()) * F::from(t as u64);
+        }
+        end_one_hot_sum.enforce_equal(&FpVar::one())?;
+        end_var.enforce_equal(&(&offset_var + &reference_length_var))?;
+
+        // Open every block at leaf first_block + i against the public root.
+        enforce_window_membership(
+            cs.clone(),
+            &params,
+            &root_var,
+            &first_block_var,
+            first_block,
+            &block_vars,
+            &self.window.paths,
+            self.tree_height,
+        )?;
+
+        let mut cigar_sponge = PoseidonSpongeVar::new(cs.clone(), &params);
+        cigar_sponge.absorb(&cigar_string_vars)?;
+        let cigar_hash = cigar_sponge.squeeze_field_elements(1)?;
+
+        let target = unpack_committed_sequence(cs.clone(), &target_sequence_vars, self.target_sequence_bases.len(), self.target_salt)?;
+        target.commitment.enforce_equal(&target_commitment_var)?;
+        target.length.enforce_equal(&target_length_var)?;
+
+        let mut window_slots = Vec::new();
+        for block in block_vars.iter() {
+            window_slots.extend(unpack_bases(block)?);
+        }
+
+        let mut cigar_chars = Vec::new();
+        for block in cigar_string_vars {
+            cigar_chars.extend(unpack_cigar(&block)?);
+        }
+
+        // Slot k holds window position k - offset, which is inside the window iff offset <= k < end,
+        // that is iff the window has started but not yet ended by slot k. A slot inside the window must
+        // hold a base, so that the window cannot run into the padding of the spare leaves.
+        let mut reference_cells = Vec::new();
+        let mut started = FpVar::zero();
+        let mut ended = FpVar::zero();
+        for (k, slot) in window_slots.iter().enumerate() {
+            if let Some(bit) = offset_one_hot.get(k) {
+                started += FpVar::from(bit.clone());
+            }
+            ended += FpVar::from(end_one_hot[k].clone());
+            let present = &started - &ended;
+            present.mul_equals(&FpVar::from(slot.is_padding.clone()), &FpVar::zero())?;
+            reference_cells.push(MemoryCell {
+                address: FpVar::constant(F::from(k as u64)) - &offset_var,
+                value: slot.value.clone(),
+                present,
+            });
+        }
+        let mut reference = ReadOnlyMemoryVar::new(cs.clone(), reference_cells, BITS_PER_BASE, 1);
+        let mut target = ReadOnlyMemoryVar::from_bases(cs.clone(), &target.bases, 1);
+
+        let walk = enforce_cigar_walk(cs.clone(), &cigar_chars, &mut reference, &mut target, &self.scoring_scheme, &substitution_matrix_vars)?;
+
+        let mut transcript = vec![root_var, start_var, target_commitment_var, reference_length_var.clone(), target_length_var.clone(), cigar_hash[0].clone()];
+        transcript.extend(substitution_matrix_vars.iter().cloned());
+        reference.finalize(&transcript)?;
+        target.finalize(&transcript)?;
+        walk.target_length.enforce_equal(&target_length_var)?;
+        walk.reference_length.enforce_equal(&reference_length_var)?;
+
+        let res_score = FpVar::<F>::new_input(cs.clone(), || Ok(i64_to_felt::<F>(self.alignment_score)))?;
+        res_score.enforce_equal(&walk.alignment_score)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment::{MaxLengths, BASE_A, BASE_T, CIGAR_MATCH};
+    use crate::sam::{alignment_circuit_from_sam, encode_sequence, FastaReference, SamRecord};
+    use ark_bls12_381::Fr;
+    use ark_relations::r1cs::ConstraintSystem;
+    use ark_std::rand::Rng;
+
+    #[test]
+    fn window_alignment_opens_against_root() {
+        let rng = &mut ark_std::test_rng();
+        let chromosome: String = (0..1000).map(|_| ['A', 'C', 'G', 'T'][rng.gen_range(0..4)]).collect();
+        let fasta = FastaReference::parse(&format!(">chr1\n{}\n", chromosome)).unwrap();
+        let tree = ReferenceTree::<Fr>::new(encode_sequence(chromosome.as_bytes()).unwrap(), 200);
+
+        // 40M2D40M starting at POS 160, so the window straddles blocks 1 to 3.
+        let read = format!("{}{}", &chromosome[159..199], &chromosome[201..241]);
+        let record = SamRecord::parse(&format!("r1\t0\tchr1\t160\t60\t40M2D40M\t*\t0\t0\t{}\t*", read)).unwrap();
+        let max_lengths = MaxLengths { reference: 200, target: 200, cigar: 200 };
+        let alignment = alignment_circuit_from_sam::<Fr>(&record, &fasta, ScoringScheme::bwa_mem()).unwrap().pad_to(&max_lengths);
+        let circuit = WindowAlignmentCircuit::new(&tree, record.pos - 1, alignment);
+        assert_eq!(circuit.public_inputs()[16], tree.root());
+
+        let cs = ConstraintSystem::new_ref();
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // A shorter read at another position gives a circuit of the same shape.
+        let record = SamRecord::parse(&format!("r2\t0\tchr1\t500\t60\t30M\t*\t0\t0\t{}\t*", &chromosome[499..529])).unwrap();
+        let alignment = alignment_circuit_from_sam::<Fr>(&record, &fasta, ScoringScheme::bwa_mem()).unwrap().pad_to(&max_lengths);
+        let short = WindowAlignmentCircuit::new(&tree, record.pos - 1, alignment);
+        let short_cs = ConstraintSystem::new_ref();
+        short.generate_constraints(short_cs.clone()).unwrap();
+        assert!(short_cs.is_satisfied().unwrap());
+        assert_eq!(short_cs.num_constraints(), cs.num_constraints());
+
+        // Claiming the same window one base later must fail.
+        let mut shifted = circuit;
+        shifted.window.start += 1;
+        let cs = ConstraintSystem::new_ref();
+        shifted.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn hidden_window_aligns_somewhere_in_genome() {
+        let rng = &mut ark_std::test_rng();
+        let random = |rng: &mut _, len| -> String { (0..len).map(|_| ['A', 'C', 'G', 'T'][Rng::gen_range(rng, 0..4)]).collect() };
+        let (chr1, chr2) = (random(rng, 300), random(rng, 500));
+        let fasta = FastaReference::parse(&format!(">chr1\n{}\n>chr2\n{}\n", chr1, chr2)).unwrap();
+        let tree = ReferenceTree::<Fr>::from_fasta(&fasta, 100).unwrap();
+        assert_eq!(tree.genome_position("chr2", 0).unwrap(), 4 * BASES_PER_BLOCK);
+        assert!(tree.genome_position("chr3", 0).is_err());
+        assert!(tree.genome_position("chr1", 300).is_err());
+
+        let record = SamRecord::parse(&format!("r1\t0\tchr2\t201\t60\t60M\t*\t0\t0\t{}\t*", &chr2[200..260])).unwrap();
+        let max_lengths = MaxLengths { reference: 100, target: 100, cigar: 100 };
+        let alignment = alignment_circuit_from_sam::<Fr>(&record, &fasta, ScoringScheme::bwa_mem()).unwrap().pad_to(&max_lengths);
+        let start = tree.genome_position(&record.rname, record.pos - 1).unwrap();
+        let circuit = WindowAlignmentCircuit::new(&tree, start, alignment).with_hidden_start();
+        let public_inputs = circuit.public_inputs();
+        assert_eq!(public_inputs.len(), 21);
+        assert_eq!(public_inputs[16], tree.root());
+        assert!(!public_inputs[17..].contains(&Fr::from(start as u64)));
+
+        let cs = ConstraintSystem::new_ref();
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+        assert_eq!(cs.num_instance_variables(), public_inputs.len() + 1);
+
+        // The position is private but still bound by the paths.
+        let mut shifted = circuit;
+        shifted.window.start += 1;
+        let cs = ConstraintSystem::new_ref();
+        shifted.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn window_cannot_run_past_the_end_of_the_reference() {
+        // Two full blocks of A followed by spare leaves of padding.
+        let tree = ReferenceTree::<Fr>::new(vec![BASE_A; 2 * BASES_PER_BLOCK], 100);
+        let max_lengths = MaxLengths { reference: 100, target: 100, cigar: 100 };
+        let scheme = ScoringScheme::bwa_mem();
+        let (bases, cigar) = (vec![BASE_A; 100], vec![CIGAR_MATCH; 100]);
+        let score = scheme.score(&bases, &bases, &cigar);
+        let alignment = AlignmentCircuit::<Fr>::padded(bases.clone(), bases, cigar, scheme, score, &max_lengths);
+        let circuit = WindowAlignmentCircuit::new(&tree, 2 * BASES_PER_BLOCK - 100, alignment).with_hidden_start();
+        let cs = ConstraintSystem::new_ref();
+        circuit.clone().generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+
+        // A poly-A read cannot align against the padding of the spare leaves past the end.
+        let mut past_end = circuit;
+        past_end.window = tree.open_window(100, 2 * BASES_PER_BLOCK - 100, max_lengths.reference);
+        let cs = ConstraintSystem::new_ref();
+        past_end.generate_constraints(cs.clone()).unwrap();
+        assert!(!cs.is_satisfied().unwrap());
+    }
+
+    #[test]
+    fn long_window_ends_at_the_last_base_of_the_reference() {
+        // Three blocks, so a window of up to 200 bases starting in the second block needs leaves up to
+        // the fifth.
+        let rng = &mut ark_std::test_rng();
+        let reference: Vec<usize> = (0..3 * BASES_PER_BLOCK).map(|_| rng.gen_range(BASE_A..=BASE_T)).collect();
+        let tree = ReferenceTree::<Fr>::new(reference.clone(), 200);
+        let max_lengths = MaxLengths { reference: 200, target: 200, cigar: 200 };
+        let scheme = ScoringScheme::bwa_mem();
+        let bases = reference[90..].to_vec();
+        let cigar = vec![CIGAR_MATCH; bases.len()];
+        let score = scheme.score(&bases, &bases, &cigar);
+        let alignment = AlignmentCircuit::<Fr>::padded(bases.clone(), bases, cigar, scheme, score, &max_lengths);
+        let circuit = WindowAlignmentCircuit::new(&tree, 90, alignment);
+        let cs = ConstraintSystem::new_ref();
+        circuit.generate_constraints(cs.clone()).unwrap();
+        assert!(cs.is_satisfied().unwrap());
+    }
+}